@@ -4,24 +4,35 @@ use reset_static_persistent_snd;
 use utils::{self, Direction, HorizontalVerticalAxis};
 use event_loop;
 use config;
+use conf::{ CheckLevel, GraphicsMode };
 use glium::{self, glutin};
 use specs::Join;
 use levels;
-use systems::*;
 use components::*;
 use std::sync::mpsc;
 use baal;
 use std::rc::Rc;
 use std::sync::Arc;
-use entities;
 use std::fmt;
 use gilrs;
+use editor::Editor;
+use progress::Progress;
+use replay::{ Recording, Replay };
+use game::{ Game, Outcome };
+use audio::BaalAudio;
+use level_watcher::{ Change, LevelWatcher };
+use log;
+use std::path::PathBuf;
+use time;
 
 static HELP: &'static str = "
 use up,down,left,right or w,s,a,d to move
 
 use escape to go to or escape from menu
 
+in editor use space to place, backspace to erase,
+tab and q to change entity, p to play and F2 to save
+
 ";
 
 static CREDIT: &'static str = "
@@ -107,6 +118,7 @@ impl Effect {
 pub enum Control {
     GotoLevel(levels::Level),
     ResetLevel,
+    PlayerDied,
     ResetGame,
     ResetCastle,
     CreateBall([f32;2],Arc<()>),
@@ -124,6 +136,7 @@ enum State {
     Game,
     Menu(usize),
     Text(usize,String),
+    Editor,
 }
 
 struct MenuEntry {
@@ -196,26 +209,28 @@ pub struct App {
     difficulty: f32,
     menu: Vec<MenuEntry>,
     menu_interline: Vec<usize>,
-    castles: Vec<levels::Castle>,
     state: State,
-    current_level: levels::Level,
+    game: Game,
+    editor: Option<Editor>,
+    recorder: Option<(Recording,PathBuf)>,
+    replay: Option<Replay>,
     camera: graphics::Camera,
     graphics: graphics::Graphics,
-    planner: specs::Planner<UpdateContext>,
     player_control_state: PlayerControlState,
     joystick_menu_state: JoystickMenuState,
-    control_rx: mpsc::Receiver<Control>,
-    control_tx: mpsc::Sender<Control>,
-    effect_rx: mpsc::Receiver<Effect>,
     effect_storage: Vec<Effect>,
-    effect_tx: mpsc::Sender<Effect>,
     focus: bool,
+    reload_timer: f32,
+    level_watcher: LevelWatcher,
+    /// the musics loaded in baal, castles refer to them by index
+    musics: Vec<String>,
     pub quit: bool,
 }
 
 pub enum AppError {
     InitGraphics(graphics::GraphicsCreationError),
     LevelCreation(String),
+    LoadProgress(String),
 }
 
 impl fmt::Display for AppError {
@@ -224,12 +239,14 @@ impl fmt::Display for AppError {
         match *self {
             InitGraphics(ref e) => write!(fmt,"graphics init failed: {}",e),
             LevelCreation(ref s) =>write!(fmt,"level creation error: {}",s),
+            LoadProgress(ref s) => write!(fmt,"load progress failed: {}",s),
         }
     }
 }
 
 impl App {
-    pub fn new<F: glium::backend::Facade>(facade: &F, castles: Vec<levels::Castle>) -> Result<App,AppError> {
+    pub fn new<F: glium::backend::Facade>(facade: &F, castles: Vec<levels::Castle>, musics: Vec<String>) -> Result<App,AppError> {
+        let config = config.load();
         // init graphics
         let graphics = try!(graphics::Graphics::new(facade, graphics::GraphicsSetting {
             colors: graphics::ColorsValue {
@@ -250,10 +267,9 @@ impl App {
                 cyan: config.graphics.cyan,
                 green: config.graphics.green,
             },
-            mode: match &*config.graphics.mode {
-                "light" => graphics::Mode::Light,
-                "dark" => graphics::Mode::Dark,
-                _ => unreachable!(),
+            mode: match config.graphics.mode {
+                GraphicsMode::Light => graphics::Mode::Light,
+                GraphicsMode::Dark => graphics::Mode::Dark,
             },
             luminosity: config.graphics.luminosity,
             circle_precision: config.graphics.circle_precision,
@@ -261,43 +277,19 @@ impl App {
             billboard_font_scale: config.graphics.billboard_font_scale,
         }).map_err(|e| AppError::InitGraphics(e)));
 
+        // load progress
+        let progress = try!(Progress::load().map_err(|e| AppError::LoadProgress(e)));
+
         // init camera
         let camera = graphics::Camera::new(0.0, 0.0, config.camera.zoom);
 
-        // init world
-        let mut world = specs::World::new();
-        world.register::<PlayerControl>();
-        world.register::<TowardPlayerControl>();
-        world.register::<MonsterControl>();
-
-        world.register::<PhysicState>();
-        world.register::<PhysicForce>();
-        world.register::<PhysicType>();
-        world.register::<PhysicDynamic>();
-        world.register::<PhysicStatic>();
-        world.register::<PhysicTrigger>();
-        world.register::<GridSquare>();
-
-        world.register::<Graphic>();
-
-        world.register::<Life>();
-        world.register::<Killer>();
-        world.register::<Ball>();
-        world.register::<Column>();
-
-        world.register::<Portal>();
-
-        world.register::<FixedCameraText>();
-        world.register::<Text>();
-        world.register::<FixedCamera>();
-
-        world.register::<DynPersistentSnd>();
-        world.register::<StaticPersistentSnd>();
+        // init game
+        let mut game = try!(Game::new(castles,progress,Box::new(BaalAudio),time::precise_time_ns()).map_err(|e| AppError::LevelCreation(e)));
 
         // check levels
-        let check_level = match &*config.levels.check_level {
-            "always" => true,
-            "debug" => {
+        let check_level = match config.levels.check_level {
+            CheckLevel::Always => true,
+            CheckLevel::Debug => {
                 let mut check_level = false;
                 debug_assert!({
                     check_level = true;
@@ -305,78 +297,56 @@ impl App {
                 });
                 check_level
             },
-            "never" => false,
-            _ => unreachable!(),
+            CheckLevel::Never => false,
         };
 
         if check_level {
-            for (c,castle) in castles.iter().enumerate() {
-                for (d,dungeon) in castle.dungeons.iter().enumerate() {
-                    for (r,_) in dungeon.rooms.iter().enumerate() {
-                        let level = levels::Level::Room {
-                            castle: c,
-                            dungeon: d,
-                            room: r,
-                        };
-                        try!(levels::load_level(&level, &castles, &mut world)
-                                .map_err(|e| AppError::LevelCreation(format!("load level {}.{}.{} failed: {}",castle.name,dungeon.name,r,e))));
-                    }
-                }
-            }
+            try!(game.check_levels().map_err(|e| AppError::LevelCreation(e)));
         }
-
-        // load level
-        let level = levels::Level::Entry;
-        try!(levels::load_level(&level, &castles, &mut world)
-             .map_err(|e| AppError::LevelCreation(format!("load entry level failed: {}",e))));
-        reset_static_persistent_snd(&world);
-
-        // init planner
-        let mut planner = specs::Planner::new(world,config.general.number_of_thread);
-        planner.add_system(PhysicSystem, "physic", 10);
-        planner.add_system(PlayerSystem::default(), "player", 5);
-        planner.add_system(MonsterSystem, "monster", 5);
-        planner.add_system(TowardPlayerSystem, "toward_player", 5);
-        planner.add_system(KillerSystem, "killer", 5);
-        planner.add_system(BallSystem, "ball", 5);
-        planner.add_system(PortalSystem, "portal", 5);
-        planner.add_system(ColumnSystem, "column", 5);
-        planner.add_system(LifeSystem, "life", 1);
-        planner.add_system(PersistentSndSystem::default(), "life", 2);
-
-        let (effect_tx, effect_rx) = mpsc::channel();
-        let (control_tx, control_rx) = mpsc::channel();
+        reset_static_persistent_snd(game.world());
+        let level_watcher = LevelWatcher::new(&game.castles);
 
         // create menu
-        let menu_interline = vec!(0,1,4,7,9,11);
+        let menu_interline = vec!(0,1,4,5,8,10,12);
         let menu = vec!(
             MenuEntry::new_button(
                 Box::new(|_| "continue".into()),
                 Rc::new(Box::new(|app| app.goto_state_game()))),
             MenuEntry::new_left_right(
-                Box::new(|app| format!("difficulty: {}",((app.difficulty*10.).round() as usize))),
+                Box::new(|app| app.locked_label(format!("difficulty: {}",((app.difficulty*10.).round() as usize)))),
                 Rc::new(Box::new(|app| {
+                    if app.simulation_locked() { return }
                     app.difficulty = (app.difficulty - 0.1).max(0.1);
                     app.save();
                 })),
                 Rc::new(Box::new(|app| {
+                    if app.simulation_locked() { return }
                     app.difficulty = (app.difficulty + 0.1).min(1.0);
                     app.save();
                 }))),
             MenuEntry::new_button(
-                Box::new(|_| "restart room".into()),
+                Box::new(|app| app.locked_label("restart room".into())),
+                Rc::new(Box::new(|app| {
+                    if app.simulation_locked() { return }
+                    app.game.send(Control::ResetLevel);
+                }))),
+            MenuEntry::new_button(
+                Box::new(|app| app.locked_label("restart castle".into())),
                 Rc::new(Box::new(|app| {
-                    app.control_tx.send(Control::ResetLevel).unwrap();
+                    if app.simulation_locked() { return }
+                    app.game.send(Control::ResetCastle);
                 }))),
             MenuEntry::new_button(
-                Box::new(|_| "restart castle".into()),
+                Box::new(|app| app.locked_label("restart game".into())),
                 Rc::new(Box::new(|app| {
-                    app.control_tx.send(Control::ResetCastle).unwrap();
+                    if app.simulation_locked() { return }
+                    app.game.send(Control::ResetGame);
                 }))),
             MenuEntry::new_button(
-                Box::new(|_| "restart game".into()),
+                Box::new(|app| app.locked_label("edit room".into())),
                 Rc::new(Box::new(|app| {
-                    app.control_tx.send(Control::ResetGame).unwrap();
+                    if app.simulation_locked() { return }
+                    app.goto_state_editor()
                 }))),
             MenuEntry::new_left_right(
                 Box::new(|_| format!("global volume: {}",(baal::global_volume()*10.).round() as usize)),
@@ -448,19 +418,19 @@ impl App {
             menu_interline: menu_interline,
             menu: menu,
             state: State::Game,
-            castles: castles,
-            current_level: level,
+            game: game,
+            editor: None,
+            recorder: None,
+            replay: None,
             joystick_menu_state: JoystickMenuState::Released,
             effect_storage: Vec::new(),
             camera: camera,
             graphics: graphics,
-            planner: planner,
             player_control_state: PlayerControlState::Keyboard(vec!()),
-            effect_rx: effect_rx,
-            effect_tx: effect_tx,
-            control_rx: control_rx,
-            control_tx: control_tx,
             focus: true,
+            reload_timer: 0.,
+            level_watcher: level_watcher,
+            musics: musics,
             quit: false,
         })
     }
@@ -469,25 +439,76 @@ impl App {
         use std;
         use std::io::Write;
 
-        let result =  conf::save(conf::Save {
+        let result = conf::Config::save(conf::Save {
             difficulty: self.difficulty,
             global_volume: baal::music::volume(),
             effect_volume: baal::effect::volume(),
             music_volume: baal::effect::volume(),
             luminosity: self.graphics.luminosity(),
             mode: match self.graphics.mode() {
-                graphics::Mode::Light => "light".into(),
-                graphics::Mode::Dark => "dark".into(),
+                graphics::Mode::Light => GraphicsMode::Light,
+                graphics::Mode::Dark => GraphicsMode::Dark,
             },
         });
         if let Some(err) = result.err() {
             writeln!(&mut std::io::stderr(), "ERROR failed to save save_file: {}", err).unwrap();
         }
     }
+    pub fn save_progress(&self) {
+        use std;
+        use std::io::Write;
+
+        if let Some(err) = self.game.progress.save().err() {
+            writeln!(&mut std::io::stderr(), "ERROR failed to save progress_file: {}", err).unwrap();
+        }
+    }
+    /// record the session from the current level until stop_recording
+    pub fn start_recording(&mut self, path: PathBuf) {
+        let config = config.load();
+        let seed = time::precise_time_ns();
+        self.game.reseed(seed);
+
+        let level = self.game.current_level.clone();
+        self.goto_level(level.clone());
+
+        let dt = 1.0 / config.event_loop.ups as f64;
+        self.recorder = Some((Recording::new(level,seed,dt,self.difficulty),path));
+    }
+    /// the menu can't change the simulation while a session is recorded or replayed
+    /// as the recording only has the moves of the character
+    fn simulation_locked(&self) -> bool {
+        self.recorder.is_some() || self.replay.is_some()
+    }
+    fn locked_label(&self, label: String) -> String {
+        if self.simulation_locked() {
+            format!("{} (locked)",label)
+        } else {
+            label
+        }
+    }
+    pub fn stop_recording(&mut self) {
+        use std;
+        use std::io::Write;
+
+        if let Some((recording,path)) = self.recorder.take() {
+            if let Some(err) = recording.save(&path).err() {
+                writeln!(&mut std::io::stderr(), "ERROR failed to save recording {}: {}", path.display(), err).unwrap();
+            }
+        }
+    }
+    /// replay the recording instead of the player input, quit at its end
+    pub fn start_replay(&mut self, recording: Recording) {
+        self.game.reseed(recording.seed);
+
+        if self.goto_level(recording.level.clone()) {
+            self.goto_state_game();
+        }
+        self.replay = Some(Replay::new(&recording));
+    }
     fn update_player_control(&mut self) {
         use std::f32::consts::PI;
 
-        let world = self.planner.mut_world();
+        let world = self.game.world();
 
         match self.player_control_state {
             PlayerControlState::Joystick(x,y) => {
@@ -558,19 +579,22 @@ impl App {
         }
     }
     pub fn goto_state_menu(&mut self) {
-        baal::effect::short::stop_all();
-        baal::effect::persistent::clear_positions_for_all();
-        baal::effect::persistent::update_volume_for_all();
+        {
+            let mut audio = self.game.audio();
+            audio.stop_all_effects();
+            audio.clear_persistent_positions_for_all();
+            audio.update_persistent_volume_for_all();
+        }
 
         match self.state {
-            State::Game => self.state = State::Menu(0),
+            State::Game | State::Editor => self.state = State::Menu(0),
             State::Menu(_) => (),
             State::Text(entry,_) => self.state = State::Menu(entry),
         }
     }
     pub fn goto_state_game(&mut self) {
         self.joystick_menu_state = JoystickMenuState::Released;
-        reset_static_persistent_snd(self.planner.mut_world());
+        reset_static_persistent_snd(self.game.world());
 
         self.state = State::Game;
     }
@@ -578,29 +602,86 @@ impl App {
         baal::effect::pause();
 
         match self.state {
-            State::Game => self.state = State::Text(0,text),
+            State::Game | State::Editor => self.state = State::Text(0,text),
             State::Text(entry,_) | State::Menu(entry) => self.state = State::Text(entry,text),
         }
     }
-    pub fn goto_level(&mut self, level: levels::Level) {
-        while let Ok(_) = self.control_rx.try_recv() {}
-        while let Ok(_) = self.effect_rx.try_recv() {}
+    /// edit the current room, the edition of the room is kept
+    /// while it is the current room
+    pub fn goto_state_editor(&mut self) {
+        let reuse = match self.editor {
+            Some(ref editor) => editor.level == self.game.current_level,
+            None => false,
+        };
 
-        if let Some(e) = levels::load_level(&level,&self.castles,self.planner.mut_world()).err() {
-            let level_name = match level {
-                levels::Level::Room { castle: c, dungeon: d, room: r } => format!("room (castle: {:?}, dungeon: {:?}, room: {:?})",
-                self.castles.get(c),
-                self.castles.get(c).and_then(|c| c.dungeons.get(d)),
-                self.castles.get(c).and_then(|c| c.dungeons.get(d)).and_then(|d| d.rooms.get(r)),
-                ),
-                levels::Level::Corridor { castle: c } => format!("corridor (castle: {:?})",self.castles.get(c)),
-                levels::Level::Entry => "entry".into(),
-            };
-            panic!(format!("ERROR: failed to load level {}: {}",level_name,e));
+        if !reuse {
+            match Editor::new(self.game.current_level.clone(),&self.game.castles) {
+                Ok(editor) => self.editor = Some(editor),
+                Err(e) => {
+                    self.goto_state_text(format!("cannot edit: {}",e));
+                    return;
+                },
+            }
         }
 
-        self.current_level = level;
+        self.joystick_menu_state = JoystickMenuState::Released;
+        self.state = State::Editor;
+    }
+    /// play the room edited
+    pub fn editor_play(&mut self) {
+        let (level,tiles) = {
+            let editor = self.editor.as_ref().unwrap();
+            (editor.level.clone(),editor.tiles())
+        };
+        let result = self.game.play_test(level,tiles);
+
+        match result {
+            Ok(()) => {
+                self.game.progress.abort();
+                self.update_player_control();
+                self.goto_state_game();
+            },
+            Err(e) => self.goto_state_text(format!("cannot play room: {}",e)),
+        }
+    }
+    pub fn editor_save(&mut self) {
+        let result = self.editor.as_ref().unwrap().save(&self.game.castles);
+        let text = match result {
+            Ok(path) => format!("room saved in {}",path.display()),
+            Err(e) => format!("cannot save room: {}",e),
+        };
+        self.goto_state_text(text);
+    }
+    /// load the level, if it fails the player goes back to the previous level
+    /// and the error is shown, return whether the level has been loaded
+    pub fn goto_level(&mut self, level: levels::Level) -> bool {
+        let result = self.game.goto_level_or_back(level);
         self.update_player_control();
+
+        match result {
+            Ok(None) => true,
+            Ok(Some(e)) | Err(e) => {
+                self.level_load_failed(e);
+                false
+            },
+        }
+    }
+    fn level_load_failed(&mut self, error: String) {
+        log::error(&*error);
+        self.goto_state_text(format!("level load failed:\n{}",error));
+    }
+    fn handle_outcomes(&mut self, outcomes: Vec<Outcome>) {
+        for outcome in outcomes {
+            match outcome {
+                Outcome::Loaded => self.update_player_control(),
+                Outcome::Reset => {
+                    self.update_player_control();
+                    self.goto_state_game();
+                },
+                Outcome::Progress => self.save_progress(),
+                Outcome::Failed(e) => self.level_load_failed(e),
+            }
+        }
     }
     pub fn focused(&mut self, focus: bool) {
         self.focus = focus;
@@ -613,21 +694,96 @@ impl App {
             baal::music::pause();
         }
     }
+    /// reload config.toml if it has been modified, new values of
+    /// entities are used by the next room loaded
+    fn reload_config(&mut self) {
+        use conf;
+
+        match conf::reload() {
+            Ok(true) => self.camera.zoom = config.load().camera.zoom,
+            Ok(false) => (),
+            Err(e) => self.goto_state_text(format!("configuration reload failed, previous one is kept:\n{}",e)),
+        }
+    }
+    /// reload castles or the current room if their files have been modified,
+    /// the player stays in the current level
+    fn reload_levels(&mut self) {
+        let config = config.load();
+        match self.level_watcher.poll(&self.game.current_level,&self.game.castles) {
+            Some(Change::Castles) => {
+                match levels::load_castles(vec!(config.levels.entry_music.val.clone())) {
+                    // music indices of castles refer to the musics loaded in baal
+                    Ok((_,ref musics)) if *musics != self.musics => {
+                        self.goto_state_text(String::from("castles reload failed:\ncastles or their musics changed, restart the game to load them"));
+                    },
+                    Ok((castles,_)) => {
+                        self.level_watcher.watch_castles(&castles);
+                        self.game.set_castles(castles);
+                        let editor_removed = self.editor.as_ref().map(|e| !e.level.exists(&self.game.castles)).unwrap_or(false);
+                        if editor_removed {
+                            self.editor = None;
+                            if let State::Editor = self.state {
+                                self.goto_state_menu();
+                            }
+                        }
+                        self.reload_level();
+                    },
+                    Err(e) => self.goto_state_text(format!("castles reload failed:\n{}",e)),
+                }
+            },
+            Some(Change::Room) => self.reload_level(),
+            None => (),
+        }
+    }
+    fn reload_level(&mut self) {
+        self.game.progress.abort();
+
+        let level = self.game.current_level.clone();
+        if self.goto_level(level) {
+            if let State::Game = self.state {
+                self.goto_state_game();
+            }
+        }
+    }
     pub fn update(&mut self, args: event_loop::UpdateArgs) {
         if !self.focus { return }
 
+        self.reload_timer -= args.dt as f32;
+        if self.reload_timer <= 0. {
+            self.reload_config();
+            self.reload_levels();
+            self.reload_timer = config.load().general.reload_period;
+        }
+        let config = config.load();
+
         match self.state {
             State::Game => {
-                let context = UpdateContext {
-                    dt: args.dt as f32 * self.difficulty,
-                    effect_tx: self.effect_tx.clone(),
-                    control_tx: self.control_tx.clone(),
-                };
-
-                self.planner.dispatch(context);
-                self.planner.wait();
+                let mut dt = args.dt as f32 * self.difficulty;
+
+                let replay_force = self.replay.as_mut().map(|replay| {
+                    dt = replay.dt as f32 * replay.difficulty;
+                    replay.next_force()
+                });
+                match replay_force {
+                    Some(Some((direction,intensity))) => self.game.set_player_force(direction,intensity),
+                    Some(None) => {
+                        println!("replay finished");
+                        self.replay = None;
+                        self.quit = true;
+                        return;
+                    },
+                    None => (),
+                }
+
+                if self.recorder.is_some() {
+                    let (direction,intensity) = self.game.player_force();
+                    self.recorder.as_mut().unwrap().0.push(direction,intensity);
+                }
+
+                self.game.dispatch(dt);
+                self.game.progress.update(args.dt as f32);
             },
-            State::Menu(_) | State::Text(_,_) => {
+            State::Menu(_) | State::Text(_,_) | State::Editor => {
                 let dir = if let JoystickMenuState::Pressed(dir, ref mut time) = self.joystick_menu_state {
                     if *time <= 0. {
                         *time = config.joystick.time_to_repeat;
@@ -643,37 +799,18 @@ impl App {
                 }
             },
         }
-        while let Ok(control) = self.control_rx.try_recv() {
-            match control {
-                Control::GotoLevel(level) => self.goto_level(level),
-                Control::ResetLevel => {
-                    let level = self.current_level.clone();
-                    self.goto_level(level);
-                    self.goto_state_game();
-                }
-                Control::ResetCastle => {
-                    let level = match self.current_level {
-                        levels::Level::Room { castle, dungeon: _, room: _ } => levels::Level::Corridor { castle: castle },
-                        levels::Level::Corridor { castle } => levels::Level::Corridor { castle: castle },
-                        levels::Level::Entry => levels::Level::Entry,
-                    };
-                    self.goto_level(level);
-                    self.goto_state_game();
-                }
-                Control::ResetGame => {
-                    self.goto_level(levels::Level::Entry);
-                    self.goto_state_game();
-                }
-                Control::CreateBall(pos,arc) => entities::add_ball(self.planner.mut_world(),pos,arc),
-            }
+        match self.game.handle_controls() {
+            Ok(outcomes) => self.handle_outcomes(outcomes),
+            Err(e) => self.level_load_failed(e),
         }
     }
     pub fn render(&mut self, args: event_loop::RenderArgs) {
+        let config = config.load();
         let dt = 1. / config.event_loop.max_fps as f32;
 
         match self.state {
             State::Game => {
-                let world = self.planner.mut_world();
+                let world = self.game.world();
 
                 // update camera
                 {
@@ -741,7 +878,7 @@ impl App {
                     }
                 }
 
-                while let Ok(effect) = self.effect_rx.try_recv() {
+                while let Some(effect) = self.game.try_recv_effect() {
                     effect.draw(&mut frame);
                     if let Some(effect) = effect.next(dt) {
                         self.effect_storage.push(effect);
@@ -778,19 +915,30 @@ impl App {
                 frame.draw_billboard_centered_text(&*text,config.menu.entry_color);
                 frame.finish().unwrap();
             }
+            State::Editor => {
+                let editor = self.editor.as_ref().unwrap();
+                self.camera.x = editor.cursor[0] as f32;
+                self.camera.y = editor.cursor[1] as f32;
+
+                let mut frame = graphics::Frame::new(&mut self.graphics, args.frame, &self.camera);
+                editor.draw(&mut frame,&self.game.castles);
+                frame.finish().unwrap();
+            }
         }
 
     }
     pub fn dir_pressed(&mut self, direction: Direction) {
         use std::ops::Rem;
 
+        let config = config.load();
+
         match self.state {
             State::Game => {
                 self.player_control_state.push_keyboard_dir(direction);
                 self.update_player_control();
             },
             State::Menu(entry) => {
-                baal::effect::short::play_on_listener(config.menu.clic_snd);
+                self.game.audio().play_effect_on_listener(config.menu.clic_snd);
                 match direction {
                     Direction::Up => self.state = State::Menu(if entry == 0 { self.menu.len()-1 } else { entry-1 }),
                     Direction::Down => self.state = State::Menu((entry+1).rem(self.menu.len())),
@@ -799,9 +947,10 @@ impl App {
                 }
             }
             State::Text(entry,_) => {
-                baal::effect::short::play_on_listener(config.menu.clic_snd);
+                self.game.audio().play_effect_on_listener(config.menu.clic_snd);
                 self.state = State::Menu(entry)
             }
+            State::Editor => self.editor.as_mut().unwrap().move_cursor(direction),
         }
     }
     pub fn dir_released(&mut self, direction: Direction) {
@@ -814,13 +963,19 @@ impl App {
         }
     }
     pub fn escape_pressed(&mut self) {
-        baal::effect::short::play_on_listener(config.menu.clic_snd);
+        let config = config.load();
+        self.game.audio().play_effect_on_listener(config.menu.clic_snd);
         match self.state {
-            State::Game | State::Text(_,_) => self.goto_state_menu(),
+            State::Game | State::Text(_,_) | State::Editor => self.goto_state_menu(),
             State::Menu(_) => self.goto_state_game(),
         }
     }
     pub fn key_pressed(&mut self, key: u8) {
+        let config = config.load();
+        if self.state == State::Editor && self.editor_key_pressed(key) {
+            return;
+        }
+
         if config.keys.up.contains(&key) {
             self.dir_pressed(Direction::Up);
         } else if config.keys.down.contains(&key) {
@@ -833,7 +988,27 @@ impl App {
             self.escape_pressed()
         }
     }
+    fn editor_key_pressed(&mut self, key: u8) -> bool {
+        let config = config.load();
+        if config.keys.editor_place.contains(&key) {
+            self.editor.as_mut().unwrap().place();
+        } else if config.keys.editor_erase.contains(&key) {
+            self.editor.as_mut().unwrap().erase();
+        } else if config.keys.editor_next_kind.contains(&key) {
+            self.editor.as_mut().unwrap().next_kind();
+        } else if config.keys.editor_previous_kind.contains(&key) {
+            self.editor.as_mut().unwrap().previous_kind();
+        } else if config.keys.editor_play.contains(&key) {
+            self.editor_play();
+        } else if config.keys.editor_save.contains(&key) {
+            self.editor_save();
+        } else {
+            return false;
+        }
+        true
+    }
     pub fn key_released(&mut self, key: u8) {
+        let config = config.load();
         if config.keys.up.contains(&key) {
             self.dir_released(Direction::Up);
         } else if config.keys.down.contains(&key) {
@@ -846,6 +1021,19 @@ impl App {
     }
     pub fn button_pressed(&mut self, button: gilrs::Button) {
         use gilrs::Button::*;
+
+        if self.state == State::Editor {
+            match button {
+                South => return self.editor.as_mut().unwrap().place(),
+                East => return self.editor.as_mut().unwrap().erase(),
+                North => return self.editor.as_mut().unwrap().next_kind(),
+                West => return self.editor.as_mut().unwrap().previous_kind(),
+                Start => return self.editor_play(),
+                RightTrigger => return self.editor_save(),
+                _ => (),
+            }
+        }
+
         match button {
             South | DPadDown => self.dir_pressed(Direction::Down),
             East | DPadRight => self.dir_pressed(Direction::Right),
@@ -867,6 +1055,8 @@ impl App {
     }
     pub fn touch(&mut self, touch: glutin::Touch) {
         use glium::glutin::TouchPhase::*;
+
+        let config = config.load();
         let loc = [touch.location.0,touch.location.1];
         if utils::inside_rectangle(loc,config.touch.escape_rec) {
             if let Started = touch.phase {
@@ -898,6 +1088,7 @@ impl App {
         }
     }
     pub fn axis_changed(&mut self, axis: gilrs::Axis, pos: f32) {
+        let config = config.load();
         if !axis.is_horizontal() && !axis.is_vertical() {
             return;
         }
@@ -912,7 +1103,7 @@ impl App {
                     self.update_player_control();
                 }
             },
-            State::Text(_,_) | State::Menu(_) => {
+            State::Text(_,_) | State::Menu(_) | State::Editor => {
                 match self.joystick_menu_state {
                     JoystickMenuState::Released => {
                         if pos.abs() >= config.joystick.press_epsilon {
@@ -0,0 +1,196 @@
+//! audio of the game logic stored in the world as the `Audio` resource,
+//! baal plays it, the null backend ignores it and the recording one logs it
+//!
+//! volumes and pause are settings of the window and stay in app
+
+use baal;
+use std::sync::{ Arc, Mutex };
+
+pub trait AudioBackend: Send + Sync {
+    fn play_effect(&mut self, effect: usize, position: [f32;3]);
+    fn stop_all_effects(&mut self);
+    fn set_listener(&mut self, position: [f32;3]);
+    fn listener(&self) -> [f32;3];
+    fn add_persistent_position(&mut self, effect: usize, position: [f32;3]);
+    fn add_persistent_positions_for_all(&mut self, positions: Vec<(usize,Vec<[f32;3]>)>);
+    fn clear_persistent_positions(&mut self, effect: usize);
+    fn clear_persistent_positions_for_all(&mut self);
+    fn update_persistent_volume_for_all(&mut self);
+    fn music_index(&self) -> Option<usize>;
+    fn play_music(&mut self, music: usize);
+
+    fn play_effect_on_listener(&mut self, effect: usize) {
+        let listener = self.listener();
+        self.play_effect(effect,listener);
+    }
+}
+
+/// the resource of the world
+pub type Audio = Box<AudioBackend>;
+
+/// to use once baal is initialized
+pub struct BaalAudio;
+
+impl AudioBackend for BaalAudio {
+    fn play_effect(&mut self, effect: usize, position: [f32;3]) {
+        baal::effect::short::play(effect,position);
+    }
+    fn stop_all_effects(&mut self) {
+        baal::effect::short::stop_all();
+    }
+    fn set_listener(&mut self, position: [f32;3]) {
+        baal::effect::set_listener(position);
+    }
+    fn listener(&self) -> [f32;3] {
+        baal::effect::listener()
+    }
+    fn add_persistent_position(&mut self, effect: usize, position: [f32;3]) {
+        baal::effect::persistent::add_position(effect,position);
+    }
+    fn add_persistent_positions_for_all(&mut self, positions: Vec<(usize,Vec<[f32;3]>)>) {
+        baal::effect::persistent::add_positions_for_all(positions);
+    }
+    fn clear_persistent_positions(&mut self, effect: usize) {
+        baal::effect::persistent::clear_positions(effect);
+    }
+    fn clear_persistent_positions_for_all(&mut self) {
+        baal::effect::persistent::clear_positions_for_all();
+    }
+    fn update_persistent_volume_for_all(&mut self) {
+        baal::effect::persistent::update_volume_for_all();
+    }
+    fn music_index(&self) -> Option<usize> {
+        baal::music::index()
+    }
+    fn play_music(&mut self, music: usize) {
+        baal::music::play(music);
+    }
+}
+
+/// what has been asked to the recording backend
+#[derive(Debug,Clone,PartialEq)]
+pub enum AudioEvent {
+    PlayEffect(usize,[f32;3]),
+    StopAllEffects,
+    SetListener([f32;3]),
+    AddPersistentPosition(usize,[f32;3]),
+    ClearPersistentPositions(usize),
+    ClearPersistentPositionsForAll,
+    PlayMusic(usize),
+}
+
+/// the events logged by a recording backend,
+/// shared so it can be read once the backend is in the world
+pub type AudioLog = Arc<Mutex<Vec<AudioEvent>>>;
+
+/// backend without audio device, it records events if it has a log
+pub struct NullAudio {
+    listener: [f32;3],
+    music: Option<usize>,
+    log: Option<AudioLog>,
+}
+
+impl NullAudio {
+    /// the entry music is playing like after baal init
+    pub fn new() -> Self {
+        NullAudio {
+            listener: [0.,0.,0.],
+            music: Some(0),
+            log: None,
+        }
+    }
+
+    /// a backend logging every event in the returned log
+    pub fn recording() -> (Self,AudioLog) {
+        let log = Arc::new(Mutex::new(vec!()));
+        let mut audio = NullAudio::new();
+        audio.log = Some(log.clone());
+        (audio,log)
+    }
+
+    fn record(&self, event: AudioEvent) {
+        if let Some(ref log) = self.log {
+            log.lock().unwrap().push(event);
+        }
+    }
+}
+
+impl AudioBackend for NullAudio {
+    fn play_effect(&mut self, effect: usize, position: [f32;3]) {
+        self.record(AudioEvent::PlayEffect(effect,position));
+    }
+    fn stop_all_effects(&mut self) {
+        self.record(AudioEvent::StopAllEffects);
+    }
+    fn set_listener(&mut self, position: [f32;3]) {
+        self.listener = position;
+        self.record(AudioEvent::SetListener(position));
+    }
+    fn listener(&self) -> [f32;3] {
+        self.listener
+    }
+    fn add_persistent_position(&mut self, effect: usize, position: [f32;3]) {
+        self.record(AudioEvent::AddPersistentPosition(effect,position));
+    }
+    fn add_persistent_positions_for_all(&mut self, positions: Vec<(usize,Vec<[f32;3]>)>) {
+        for (effect,positions) in positions {
+            for position in positions {
+                self.record(AudioEvent::AddPersistentPosition(effect,position));
+            }
+        }
+    }
+    fn clear_persistent_positions(&mut self, effect: usize) {
+        self.record(AudioEvent::ClearPersistentPositions(effect));
+    }
+    fn clear_persistent_positions_for_all(&mut self) {
+        self.record(AudioEvent::ClearPersistentPositionsForAll);
+    }
+    fn update_persistent_volume_for_all(&mut self) {}
+    fn music_index(&self) -> Option<usize> {
+        self.music
+    }
+    fn play_music(&mut self, music: usize) {
+        self.music = Some(music);
+        self.record(AudioEvent::PlayMusic(music));
+    }
+}
+
+#[test]
+fn recording_audio_test() {
+    let (mut audio,log) = NullAudio::recording();
+    audio.set_listener([1.,2.,0.]);
+    audio.play_effect_on_listener(3);
+    audio.play_music(1);
+    audio.add_persistent_positions_for_all(vec!((2,vec!([0.,0.,0.],[1.,0.,0.]))));
+
+    assert_eq!(audio.music_index(),Some(1));
+    assert_eq!(*log.lock().unwrap(),vec!(
+        AudioEvent::SetListener([1.,2.,0.]),
+        AudioEvent::PlayEffect(3,[1.,2.,0.]),
+        AudioEvent::PlayMusic(1),
+        AudioEvent::AddPersistentPosition(2,[0.,0.,0.]),
+        AudioEvent::AddPersistentPosition(2,[1.,0.,0.]),
+    ));
+}
+
+#[test]
+fn room_audio_test() {
+    use config;
+    use game::Game;
+    use levels::{ self, Level };
+    use progress::Progress;
+
+    let config = config.load();
+
+    let (castles,_) = levels::load_castles(vec!()).unwrap();
+    let (audio,log) = NullAudio::recording();
+    let mut game = Game::new(castles,Progress::new(),Box::new(audio),0).unwrap();
+    log.lock().unwrap().clear();
+
+    game.goto_level(Level::Corridor { castle: 0 }).unwrap();
+    let music = game.castles[0].music;
+    let events = log.lock().unwrap().clone();
+    assert_eq!(events[0],AudioEvent::StopAllEffects);
+    assert!(events.contains(&AudioEvent::PlayEffect(config.entities.portal_snd,[0.,0.,0.])));
+    assert_eq!(events.contains(&AudioEvent::PlayMusic(music)),music != 0);
+}
@@ -1,8 +1,12 @@
-use configuration::{ self, BitflagU32, VecStringPath };
-use toml;
+use configuration::{ self, BitflagU32, ConfigError, FileWatcher, Swappable, VecStringPath };
 use std;
+use std::fs;
+use std::io::{ self, Write };
+use std::path::Path;
+use std::sync::Mutex;
 
 use levels as levelss;
+use prefab;
 use graphics::{ Color, Layer };
 
 pub type VecVecStringPath = Vec<VecStringPath>;
@@ -11,221 +15,444 @@ pub type Array4F32 = [f32;4];
 pub type Array4F64 = [f64;4];
 pub type VecF32 = Vec<f32>;
 pub type VecU8 = Vec<u8>;
+pub type VecUsize = Vec<usize>;
 pub type Dungeons = Vec<levelss::Dungeon>;
 pub type Array3U8 = [u8;3];
+pub type Prefabs = prefab::Prefabs;
+pub type Characters = std::collections::BTreeMap<String,String>;
+
+/// assets are relative to the install directory,
+/// files written by the game are relative to the user directory
+fn resolve_paths(conf: &mut Config) {
+    let install_dir = Config::install_dir();
+    let user_dir = Config::user_dir();
+
+    for path in vec!(&mut conf.levels.dir,
+                     &mut conf.levels.entry_music,
+                     &mut conf.audio.effect_dir,
+                     &mut conf.audio.music_dir,
+                     &mut conf.graphics.font_file) {
+        path.val = install_dir.join(&*path.val).to_string_lossy().into_owned();
+    }
+    import_from_working_dir(Path::new(&*conf.general.progress_file.val),
+                            &user_dir.join(&*conf.general.progress_file.val));
+    for path in vec!(&mut conf.general.progress_file,
+                     &mut conf.general.log_file) {
+        path.val = user_dir.join(&*path.val).to_string_lossy().into_owned();
+    }
+}
+
+/// previous versions wrote their files in the working directory,
+/// they are copied to the user directory if it doesn't have them yet
+fn import_from_working_dir(old: &Path, file: &Path) {
+    if old.is_absolute() || file.exists() || !old.is_file() {
+        return;
+    }
+    let result = file.parent().map(|dir| fs::create_dir_all(dir)).unwrap_or(Ok(()))
+        .and_then(|_| fs::copy(old, file));
+
+    if let Err(e) = result {
+        writeln!(&mut io::stderr(), "ERROR failed to copy {} to {}: {}", old.display(), file.display(), e).unwrap();
+    }
+}
 
 fn config_constraint(conf: &Config) -> Result<(),String> {
     if conf.keys.up.len() == 0
        || conf.keys.down.len() == 0
        || conf.keys.left.len() == 0
        || conf.keys.right.len() == 0 {
-           return Err("ERROR: configuration file invalid: keys mustn't be empty".into());
+           return Err("keys mustn't be empty".into());
     }
 
     // assert persistent snd and static snd doesn't overlap
     if conf.entities.monster_persistent_snd == conf.entities.laser_persistent_snd {
-        return Err("ERROR: configuration file invalid: monster_persistent_snd and laser_persistent_snd must be different".into());
+        return Err("monster_persistent_snd and laser_persistent_snd must be different".into());
+    }
+
+    // assert prefabs persistent snd are not used both as static and dynamic
+    let mut static_snds = vec!(conf.entities.laser_persistent_snd);
+    let mut dyn_snds = vec!(conf.entities.monster_persistent_snd);
+    for prefab in conf.entities.prefabs.values() {
+        match prefab.persistent_snd {
+            Some(prefab::PrefabPersistentSnd::Static(id)) => static_snds.push(id),
+            Some(prefab::PrefabPersistentSnd::Dynamic(id)) => dyn_snds.push(id),
+            None => (),
+        }
+    }
+    if static_snds.iter().any(|id| dyn_snds.contains(id)) {
+        return Err("a persistent snd cannot be used both as static and dynamic".into());
     }
 
     Ok(())
 }
 
-configure!(
-    file = "config.toml";
-    debug_file = "config.toml";
-    save_file = "save.toml";
-
-    constraint = config_constraint;
-
-    general: {
-        number_of_thread: t usize,
-        persistent_snd_cooldown: t usize,
-        difficulty: t f32 save difficulty,
-    },
-    keys: {
-        up: t VecU8,
-        down: t VecU8,
-        left: t VecU8,
-        right: t VecU8,
-        escape: t VecU8,
-    },
-    effect: {
-        color: t Color,
-        angles: t VecF32,
-        persistance: t f32,
-        thickness: t f32,
-        inner_length: t f32,
-        length: t f32,
-    },
-    physic: {
-        rate: t f32,
-        unit: t f32,
-    },
-    touch: {
-        joystick_rec: t Array4F64,
-        joystick_radius: t f64,
-        escape_rec: t Array4F64,
-    },
-    joystick: {
-        time_to_repeat: t f32,
-        time_to_start_repeating: t f32,
-        press_epsilon: t f32,
-        release_epsilon: t f32,
-    },
-    menu:{
-        entry_color: t Color,
-        cursor_color: t Color,
-        background_color: t Color,
-        clic_snd: t usize,
-
-        background_width: t f32,
-        background_height: t f32,
-    },
-    entities: {
-        text_color: t Color,
-
-        ball_group: t BitflagU32,
-        ball_mask: t BitflagU32,
-        ball_killer_mask: t BitflagU32,
-        ball_kill_snd: t usize,
-        ball_die_snd: t usize,
-        ball_radius: t f32,
-        ball_velocity: t f32,
-        ball_time: t f32,
-        ball_weight: t f32,
-        ball_color: t Color,
-        ball_layer: t Layer,
-        ball_vel_snd_coef: t f32,
-        ball_vel_snd: t usize,
-
-        laser_group: t BitflagU32,
-        laser_mask: t BitflagU32,
-        laser_killer_mask: t BitflagU32,
-        laser_kill_snd: t usize,
-        laser_radius: t f32,
-        laser_color: t Color,
-        laser_layer: t Layer,
-        laser_persistent_snd: t usize,
-
-        column_group: t BitflagU32,
-        column_mask: t BitflagU32,
-        column_radius: t f32,
-        column_color: t Color,
-        column_layer: t Layer,
-        column_cooldown: t f32,
-        column_spawn_snd: t usize,
-
-        char_group: t BitflagU32,
-        char_mask: t BitflagU32,
-        char_radius: t f32,
-        char_velocity: t f32,
-        char_time: t f32,
-        char_weight: t f32,
-        char_color: t Color,
-        char_layer: t Layer,
-        char_die_snd: t usize,
-        char_restart: t f32,
-
-        wall_group: t BitflagU32,
-        wall_mask: t BitflagU32,
-        wall_radius: t f32,
-        wall_color: t Color,
-        wall_layer: t Layer,
-
-        monster_vision_mask: t BitflagU32,
-        monster_killer_mask: t BitflagU32,
-        monster_kill_snd: t usize,
-        monster_die_snd: t usize,
-        monster_group: t BitflagU32,
-        monster_mask: t BitflagU32,
-        monster_vision_time: t f32,
-        monster_radius: t f32,
-        monster_velocity: t f32,
-        monster_time: t f32,
-        monster_weight: t f32,
-        monster_color: t Color,
-        monster_layer: t Layer,
-        monster_persistent_snd: t usize,
-
-        portal_end_color: t Color,
-        portal_end_layer: t Layer,
-        portal_start_color: t Color,
-        portal_start_layer: t Layer,
-        portal_snd: t usize,
-    },
-    levels: {
-        hall_length: t usize,
-        corridor_length: t usize,
-        dir: t VecStringPath,
-        entry_music: t VecStringPath,
-        check_level: e String [always,debug,never],
-
-        empty_col: t Array3U8,
-        char_col: t Array3U8,
-        portal_col: t Array3U8,
-        laser_col: t Array3U8,
-        monster_col: t Array3U8,
-        column_col: t Array3U8,
-        wall_col: t Array3U8,
-    },
-    audio: {
-        effect_dir: t VecStringPath,
-        music_dir: t VecStringPath,
-        global_volume: t f32 save global_volume,
-        music_volume: t f32 save music_volume,
-        effect_volume: t f32 save effect_volume,
-        distance_model: e String [linear,pow2],
-        distance_model_min: t f32,
-        distance_model_max: t f32,
-        short_effects: t VecVecStringPath,
-        persistent_effects: t VecVecStringPath,
-        transition_type: e String [instant,smooth,overlap],
-        transition_time: t u64,
-    },
-    window: {
-        dimension: t Dimension,
-        vsync: t bool,
-        multisampling: t u16,
-        fullscreen: t bool,
-        fullscreen_on_primary_monitor: t bool,
-        fullscreen_monitor: t usize,
-    },
-    graphics: {
-        base03: t Array4F32,
-        base02: t Array4F32,
-        base01: t Array4F32,
-        base00: t Array4F32,
-        base0: t Array4F32,
-        base1: t Array4F32,
-        base2: t Array4F32,
-        base3: t Array4F32,
-        yellow: t Array4F32,
-        orange: t Array4F32,
-        red: t Array4F32,
-        magenta: t Array4F32,
-        violet: t Array4F32,
-        blue: t Array4F32,
-        cyan: t Array4F32,
-        green: t Array4F32,
-        mode: e String [light,dark] save mode,
-        luminosity: t f32 save luminosity,
-        circle_precision: t usize,
-        font_file: t VecStringPath,
-        billboard_font_scale: t f32,
-        font_scale: t f32,
-    },
-    text: {
-        top: t i32,
-        bottom: t i32,
-        right: t i32,
-        left: t i32,
-    },
-    camera: {
-        zoom: t f32,
-    },
-    event_loop: {
-        ups: t u64,
-        max_fps: t u64,
-    },
-);
+#[derive(Debug,Clone,Copy,PartialEq,FromToml,IntoToml)]
+pub enum CheckLevel {
+    Always,
+    Debug,
+    Never,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,FromToml,IntoToml)]
+pub enum DistanceModel {
+    Linear,
+    Pow2,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,FromToml,IntoToml)]
+pub enum TransitionType {
+    Instant,
+    Smooth,
+    Overlap,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,FromToml,IntoToml)]
+pub enum GraphicsMode {
+    Light,
+    Dark,
+}
+
+#[derive(FromToml)]
+pub struct General {
+    pub number_of_thread: usize,
+    pub persistent_snd_cooldown: usize,
+    pub difficulty: f32,
+    pub progress_file: VecStringPath,
+    pub log_file: VecStringPath,
+    pub reload_period: f32,
+}
+
+#[derive(FromToml)]
+pub struct Keys {
+    pub up: VecU8,
+    pub down: VecU8,
+    pub left: VecU8,
+    pub right: VecU8,
+    pub escape: VecU8,
+    pub editor_place: VecU8,
+    pub editor_erase: VecU8,
+    pub editor_next_kind: VecU8,
+    pub editor_previous_kind: VecU8,
+    pub editor_play: VecU8,
+    pub editor_save: VecU8,
+}
+
+#[derive(FromToml)]
+pub struct Effect {
+    pub color: Color,
+    pub angles: VecF32,
+    pub persistance: f32,
+    pub thickness: f32,
+    pub inner_length: f32,
+    pub length: f32,
+}
+
+#[derive(FromToml)]
+pub struct Physic {
+    pub rate: f32,
+    pub unit: f32,
+}
+
+#[derive(FromToml)]
+pub struct Touch {
+    pub joystick_rec: Array4F64,
+    pub joystick_radius: f64,
+    pub escape_rec: Array4F64,
+}
+
+#[derive(FromToml)]
+pub struct Joystick {
+    pub time_to_repeat: f32,
+    pub time_to_start_repeating: f32,
+    pub press_epsilon: f32,
+    pub release_epsilon: f32,
+}
+
+#[derive(FromToml)]
+pub struct Menu {
+    pub entry_color: Color,
+    pub cursor_color: Color,
+    pub background_color: Color,
+    pub clic_snd: usize,
+
+    pub background_width: f32,
+    pub background_height: f32,
+}
+
+#[derive(FromToml)]
+pub struct Editor {
+    pub cursor_color: Color,
+    pub cursor_radius: f32,
+    pub kind_radius: f32,
+    pub unknown_color: Color,
+}
+
+#[derive(FromToml)]
+pub struct Entities {
+    pub text_color: Color,
+
+    pub ball_group: BitflagU32,
+    pub ball_mask: BitflagU32,
+    pub ball_killer_mask: BitflagU32,
+    pub ball_kill_snd: usize,
+    pub ball_die_snd: usize,
+    pub ball_radius: f32,
+    pub ball_velocity: f32,
+    pub ball_time: f32,
+    pub ball_weight: f32,
+    pub ball_color: Color,
+    pub ball_layer: Layer,
+    pub ball_vel_snd_coef: f32,
+    pub ball_vel_snd: usize,
+
+    pub laser_group: BitflagU32,
+    pub laser_mask: BitflagU32,
+    pub laser_killer_mask: BitflagU32,
+    pub laser_kill_snd: usize,
+    pub laser_radius: f32,
+    pub laser_color: Color,
+    pub laser_layer: Layer,
+    pub laser_persistent_snd: usize,
+
+    pub column_group: BitflagU32,
+    pub column_mask: BitflagU32,
+    pub column_radius: f32,
+    pub column_color: Color,
+    pub column_layer: Layer,
+    pub column_cooldown: f32,
+    pub column_spawn_snd: usize,
+
+    pub char_group: BitflagU32,
+    pub char_mask: BitflagU32,
+    pub char_radius: f32,
+    pub char_velocity: f32,
+    pub char_time: f32,
+    pub char_weight: f32,
+    pub char_color: Color,
+    pub char_layer: Layer,
+    pub char_die_snd: usize,
+    pub char_restart: f32,
+
+    pub wall_group: BitflagU32,
+    pub wall_mask: BitflagU32,
+    pub wall_radius: f32,
+    pub wall_color: Color,
+    pub wall_layer: Layer,
+
+    pub monster_vision_mask: BitflagU32,
+    pub monster_killer_mask: BitflagU32,
+    pub monster_kill_snd: usize,
+    pub monster_die_snd: usize,
+    pub monster_group: BitflagU32,
+    pub monster_mask: BitflagU32,
+    pub monster_idle_velocity: f32,
+    pub monster_idle_vision_time: f32,
+    pub monster_idle_vision_length: f32,
+    pub monster_alerted_velocity: f32,
+    pub monster_alerted_vision_time: f32,
+    pub monster_alerted_vision_length: f32,
+    pub monster_chasing_velocity: f32,
+    pub monster_chasing_vision_time: f32,
+    pub monster_chasing_vision_length: f32,
+    pub monster_searching_velocity: f32,
+    pub monster_searching_vision_time: f32,
+    pub monster_searching_vision_length: f32,
+    pub monster_awareness_gain: f32,
+    pub monster_awareness_decay: f32,
+    pub monster_alerted_awareness: f32,
+    pub monster_chasing_awareness: f32,
+    pub monster_navigation_mask: BitflagU32,
+    pub monster_waypoint_radius: f32,
+    pub monster_radius: f32,
+    pub monster_velocity: f32,
+    pub monster_time: f32,
+    pub monster_weight: f32,
+    pub monster_color: Color,
+    pub monster_layer: Layer,
+    pub monster_persistent_snd: usize,
+
+    pub portal_end_color: Color,
+    pub portal_end_layer: Layer,
+    pub portal_start_color: Color,
+    pub portal_start_layer: Layer,
+    pub portal_snd: usize,
+
+    pub prefabs: Prefabs,
+}
+
+#[derive(FromToml)]
+pub struct Levels {
+    pub hall_length: usize,
+    pub corridor_length: usize,
+    pub dir: VecStringPath,
+    pub entry_music: VecStringPath,
+    pub check_level: CheckLevel,
+
+    pub empty_col: Array3U8,
+    pub char_col: Array3U8,
+    pub portal_col: Array3U8,
+    pub laser_col: Array3U8,
+    pub monster_col: Array3U8,
+    pub column_col: Array3U8,
+    pub wall_col: Array3U8,
+
+    pub characters: Characters,
+
+    pub completed_marker: String,
+}
+
+#[derive(FromToml)]
+pub struct Solver {
+    pub max_updates: usize,
+    pub waits: VecUsize,
+    pub waypoint_radius: f32,
+    pub slow_distance: f32,
+}
+
+#[derive(FromToml)]
+pub struct Audio {
+    pub effect_dir: VecStringPath,
+    pub music_dir: VecStringPath,
+    pub global_volume: f32,
+    pub music_volume: f32,
+    pub effect_volume: f32,
+    pub distance_model: DistanceModel,
+    pub distance_model_min: f32,
+    pub distance_model_max: f32,
+    pub short_effects: VecVecStringPath,
+    pub persistent_effects: VecVecStringPath,
+    pub transition_type: TransitionType,
+    pub transition_time: u64,
+}
+
+#[derive(FromToml)]
+pub struct Window {
+    pub dimension: Dimension,
+    pub vsync: bool,
+    pub multisampling: u16,
+    pub fullscreen: bool,
+    pub fullscreen_on_primary_monitor: bool,
+    pub fullscreen_monitor: usize,
+}
+
+#[derive(FromToml)]
+pub struct Graphics {
+    pub base03: Array4F32,
+    pub base02: Array4F32,
+    pub base01: Array4F32,
+    pub base00: Array4F32,
+    pub base0: Array4F32,
+    pub base1: Array4F32,
+    pub base2: Array4F32,
+    pub base3: Array4F32,
+    pub yellow: Array4F32,
+    pub orange: Array4F32,
+    pub red: Array4F32,
+    pub magenta: Array4F32,
+    pub violet: Array4F32,
+    pub blue: Array4F32,
+    pub cyan: Array4F32,
+    pub green: Array4F32,
+    pub mode: GraphicsMode,
+    pub luminosity: f32,
+    pub circle_precision: usize,
+    pub font_file: VecStringPath,
+    pub billboard_font_scale: f32,
+    pub font_scale: f32,
+}
+
+#[derive(FromToml)]
+pub struct Text {
+    pub top: i32,
+    pub bottom: i32,
+    pub right: i32,
+    pub left: i32,
+}
+
+#[derive(FromToml)]
+pub struct Camera {
+    pub zoom: f32,
+}
+
+#[derive(FromToml)]
+pub struct EventLoop {
+    pub ups: u64,
+    pub max_fps: u64,
+}
+
+#[derive(FromToml,Configuration)]
+#[configuration(app = "ruga", defaults = "config.toml", file = "config.toml", save_file = "save.toml")]
+#[configuration(resolve = "resolve_paths", constraint = "config_constraint")]
+#[configuration(save(difficulty = "general.difficulty", global_volume = "audio.global_volume", music_volume = "audio.music_volume", effect_volume = "audio.effect_volume", mode = "graphics.mode", luminosity = "graphics.luminosity"))]
+pub struct Config {
+    pub general: General,
+    pub keys: Keys,
+    pub effect: Effect,
+    pub physic: Physic,
+    pub touch: Touch,
+    pub joystick: Joystick,
+    pub menu: Menu,
+    pub editor: Editor,
+    pub entities: Entities,
+    pub levels: Levels,
+    pub solver: Solver,
+    pub audio: Audio,
+    pub window: Window,
+    pub graphics: Graphics,
+    pub text: Text,
+    pub camera: Camera,
+    pub event_loop: EventLoop,
+}
+
+/// the values of config.toml overridden by save.toml
+#[derive(IntoToml)]
+pub struct Save {
+    pub difficulty: f32,
+    pub global_volume: f32,
+    pub music_volume: f32,
+    pub effect_volume: f32,
+    pub mode: GraphicsMode,
+    pub luminosity: f32,
+}
+
+lazy_static! {
+    /// `table.key=value` of the command line
+    static ref OVERRIDES: Mutex<Vec<String>> = Mutex::new(vec!());
+    pub static ref CONFIG: Swappable<Config> = {
+        use std::process::exit;
+
+        import_from_working_dir(Path::new("save.toml"), &Config::save_file());
+
+        // files are watched from the first load
+        let _ = WATCHERS.lock();
+
+        match Config::load(&*OVERRIDES.lock().unwrap()) {
+            Ok(conf) => Swappable::new(conf),
+            Err(err) => {
+                println!("ERROR: configuration load failed: {}",err);
+                exit(1);
+            }
+        }
+    };
+    static ref WATCHERS: Mutex<Vec<FileWatcher>> = Mutex::new(Config::files().into_iter().map(FileWatcher::new).collect());
+}
+
+/// overrides of every file, to set before the first use of the configuration
+pub fn set_overrides(overrides: Vec<String>) {
+    *OVERRIDES.lock().unwrap() = overrides;
+}
+
+/// reload the configuration if one of its files has been modified,
+/// return whether it has been reloaded,
+/// the configuration is kept if the new one is invalid,
+/// configurations loaded before keep their values
+pub fn reload() -> Result<bool,ConfigError> {
+    let changed = WATCHERS.lock().unwrap().iter_mut().fold(false, |changed, watcher| watcher.changed() || changed);
+    if !changed {
+        return Ok(false);
+    }
+    let conf = try!(Config::load(&*OVERRIDES.lock().unwrap()));
+    CONFIG.swap(conf);
+    Ok(true)
+}
 
@@ -0,0 +1,423 @@
+//! derive of `FromToml` and `IntoToml` for structs and enums
+//! and of `Configuration` for the root of a configuration file
+//!
+//! attributes of fields:
+//! * `#[configuration(default)]` the default of the type if the key is missing
+//! * `#[configuration(default = "expr")]` the expression if the key is missing
+//! * `#[configuration(rename = "key")]` the key in the file
+//! * fields of type `Option<T>` are optional
+//!
+//! enums are strings for unit variants and tables of one key
+//! for variants with one value, the variant name is lowercased
+//!
+//! attributes of the root of a configuration:
+//! * `app` the name of the directories of the configuration
+//! * `defaults` a file relative to the crate compiled as the first layer
+//! * `file` and `debug_file` the name of the file in the install and user directories
+//! * `save_file` the name of the save file in the user directory
+//! * `resolve` a function modifying the configuration once loaded
+//! * `constraint` a function checking the configuration once resolved
+//! * `save(key = "table.key", ...)` the keys of the save file
+//!   and the keys of the configuration they override
+//! * `migrations` a `&[Migration]` of the save file, its version is their number
+
+#![recursion_limit = "256"]
+
+extern crate proc_macro;
+extern crate syn;
+#[macro_use] extern crate quote;
+
+use proc_macro::TokenStream;
+use syn::{ Attribute, Body, DeriveInput, Field, Lit, MetaItem, NestedMetaItem, Ty, Variant, VariantData };
+
+#[proc_macro_derive(FromToml, attributes(configuration))]
+pub fn derive_from_toml(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_derive_input(&input.to_string()).unwrap();
+    let tokens = match ast.body {
+        Body::Struct(VariantData::Struct(ref fields)) => from_toml_struct(&ast, fields),
+        Body::Enum(ref variants) => from_toml_enum(&ast, variants),
+        _ => panic!("FromToml can only be derived for enums and structs with named fields"),
+    };
+    tokens.parse().unwrap()
+}
+
+#[proc_macro_derive(IntoToml, attributes(configuration))]
+pub fn derive_into_toml(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_derive_input(&input.to_string()).unwrap();
+    let tokens = match ast.body {
+        Body::Struct(VariantData::Struct(ref fields)) => into_toml_struct(&ast, fields),
+        Body::Enum(ref variants) => into_toml_enum(&ast, variants),
+        _ => panic!("IntoToml can only be derived for enums and structs with named fields"),
+    };
+    tokens.parse().unwrap()
+}
+
+#[proc_macro_derive(Configuration, attributes(configuration))]
+pub fn derive_configuration(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_derive_input(&input.to_string()).unwrap();
+    configuration(&ast).parse().unwrap()
+}
+
+/// the items of the configuration attributes
+fn metas(attrs: &[Attribute]) -> Vec<MetaItem> {
+    let mut metas = vec!();
+    for attr in attrs {
+        if let MetaItem::List(ref name, ref items) = attr.value {
+            if name == "configuration" {
+                for item in items {
+                    match *item {
+                        NestedMetaItem::MetaItem(ref meta) => metas.push(meta.clone()),
+                        NestedMetaItem::Literal(_) => panic!("unexpected literal in configuration attribute"),
+                    }
+                }
+            }
+        }
+    }
+    metas
+}
+
+fn is_option(ty: &Ty) -> bool {
+    match *ty {
+        Ty::Path(None, ref path) => path.segments.last().map(|s| s.ident == "Option").unwrap_or(false),
+        _ => false,
+    }
+}
+
+enum Missing {
+    Required,
+    Optional,
+    Default(Option<String>),
+}
+
+struct FieldOptions {
+    key: String,
+    missing: Missing,
+}
+
+fn field_options(field: &Field) -> FieldOptions {
+    let ident = field.ident.as_ref().expect("fields must be named");
+    let mut options = FieldOptions {
+        key: ident.as_ref().into(),
+        missing: if is_option(&field.ty) { Missing::Optional } else { Missing::Required },
+    };
+
+    for meta in metas(&field.attrs) {
+        match meta {
+            MetaItem::Word(ref name) if name == "default" => options.missing = Missing::Default(None),
+            MetaItem::NameValue(ref name, Lit::Str(ref value, _)) if name == "default" => options.missing = Missing::Default(Some(value.clone())),
+            MetaItem::NameValue(ref name, Lit::Str(ref value, _)) if name == "rename" => options.key = value.clone(),
+            _ => panic!("unexpected configuration attribute on field {}", ident),
+        }
+    }
+    options
+}
+
+fn variant_key(variant: &Variant) -> String {
+    let mut key = variant.ident.as_ref().to_lowercase();
+    for meta in metas(&variant.attrs) {
+        match meta {
+            MetaItem::NameValue(ref name, Lit::Str(ref value, _)) if name == "rename" => key = value.to_lowercase(),
+            _ => panic!("unexpected configuration attribute on variant {}", variant.ident),
+        }
+    }
+    key
+}
+
+fn from_toml_struct(ast: &DeriveInput, fields: &[Field]) -> quote::Tokens {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let options = fields.iter().map(field_options).collect::<Vec<_>>();
+    let keys = options.iter().map(|o| &*o.key).collect::<Vec<_>>();
+    let inits = fields.iter().zip(options.iter()).map(|(field, options)| {
+        let ident = &field.ident;
+        let key = &*options.key;
+        match options.missing {
+            Missing::Required => quote! {
+                #ident: try!(configuration::required(table, #key)),
+            },
+            Missing::Optional => quote! {
+                #ident: try!(configuration::optional(table, #key)),
+            },
+            Missing::Default(None) => quote! {
+                #ident: try!(configuration::optional(table, #key)).unwrap_or_else(Default::default),
+            },
+            Missing::Default(Some(ref expr)) => {
+                let expr = syn::parse_expr(expr).expect("invalid default expression");
+                quote! {
+                    #ident: try!(configuration::optional(table, #key)).unwrap_or_else(|| #expr),
+                }
+            },
+        }
+    }).collect::<Vec<_>>();
+
+    quote! {
+        impl #impl_generics configuration::FromToml for #name #ty_generics #where_clause {
+            fn from_toml(val: &configuration::toml::Value) -> Result<Self,configuration::TomlError> {
+                let table = try!(val.as_table().ok_or_else(|| configuration::TomlError::expect("table",val)));
+                try!(configuration::check_keys(table, &[#(#keys),*]));
+                Ok(#name {
+                    #(#inits)*
+                })
+            }
+        }
+    }
+}
+
+fn into_toml_struct(ast: &DeriveInput, fields: &[Field]) -> quote::Tokens {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let inserts = fields.iter().map(|field| {
+        let ident = &field.ident;
+        let key = field_options(field).key;
+        if is_option(&field.ty) {
+            quote! {
+                if let Some(value) = s.#ident {
+                    table.insert(String::from(#key), configuration::IntoToml::into_toml(value));
+                }
+            }
+        } else {
+            quote! {
+                table.insert(String::from(#key), configuration::IntoToml::into_toml(s.#ident));
+            }
+        }
+    }).collect::<Vec<_>>();
+
+    quote! {
+        impl #impl_generics configuration::IntoToml for #name #ty_generics #where_clause {
+            #[allow(unused_mut)]
+            fn into_toml(s: Self) -> configuration::toml::Value {
+                let mut table = configuration::toml::Table::new();
+                #(#inserts)*
+                configuration::toml::Value::Table(table)
+            }
+        }
+    }
+}
+
+/// the single value of the variant if any
+fn variant_value(variant: &Variant) -> Option<&Ty> {
+    match variant.data {
+        VariantData::Unit => None,
+        VariantData::Tuple(ref fields) if fields.len() == 1 => Some(&fields[0].ty),
+        _ => panic!("variant {} must be a unit or have one value", variant.ident),
+    }
+}
+
+fn from_toml_enum(ast: &DeriveInput, variants: &[Variant]) -> quote::Tokens {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let expected = variants.iter()
+        .map(|v| format!("\"{}\"", variant_key(v)))
+        .collect::<Vec<_>>()
+        .join(" or ");
+
+    let mut units = vec!();
+    let mut values = vec!();
+    for variant in variants {
+        let ident = &variant.ident;
+        let key = variant_key(variant);
+        match variant_value(variant) {
+            None => units.push(quote! {
+                if string.to_lowercase() == #key {
+                    return Ok(#name::#ident);
+                }
+            }),
+            Some(ty) => values.push(quote! {
+                if key.to_lowercase() == #key {
+                    return <#ty as configuration::FromToml>::from_toml(value)
+                        .map(#name::#ident)
+                        .map_err(|e| e.key(key));
+                }
+            }),
+        }
+    }
+
+    quote! {
+        impl #impl_generics configuration::FromToml for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn from_toml(val: &configuration::toml::Value) -> Result<Self,configuration::TomlError> {
+                match *val {
+                    configuration::toml::Value::String(ref string) => {
+                        #(#units)*
+                    },
+                    configuration::toml::Value::Table(ref table) if table.len() == 1 => {
+                        let (key,value) = table.iter().next().unwrap();
+                        #(#values)*
+                    },
+                    _ => (),
+                }
+                Err(configuration::TomlError::expect(#expected,val))
+            }
+        }
+    }
+}
+
+fn into_toml_enum(ast: &DeriveInput, variants: &[Variant]) -> quote::Tokens {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let arms = variants.iter().map(|variant| {
+        let ident = &variant.ident;
+        let key = variant_key(variant);
+        match variant_value(variant) {
+            None => quote! {
+                #name::#ident => configuration::toml::Value::String(String::from(#key)),
+            },
+            Some(_) => quote! {
+                #name::#ident(value) => {
+                    let mut table = configuration::toml::Table::new();
+                    table.insert(String::from(#key), configuration::IntoToml::into_toml(value));
+                    configuration::toml::Value::Table(table)
+                },
+            },
+        }
+    }).collect::<Vec<_>>();
+
+    quote! {
+        impl #impl_generics configuration::IntoToml for #name #ty_generics #where_clause {
+            fn into_toml(s: Self) -> configuration::toml::Value {
+                match s {
+                    #(#arms)*
+                }
+            }
+        }
+    }
+}
+
+fn configuration(ast: &DeriveInput) -> quote::Tokens {
+    let name = &ast.ident;
+
+    let mut app = None;
+    let mut defaults = None;
+    let mut file = None;
+    let mut debug_file = None;
+    let mut save_file = None;
+    let mut resolve = None;
+    let mut constraint = None;
+    let mut migrations = None;
+    let mut save_keys = vec!();
+    let mut save_paths = vec!();
+
+    for meta in metas(&ast.attrs) {
+        match meta {
+            MetaItem::NameValue(ref key, Lit::Str(ref value, _)) if key == "app" => app = Some(value.clone()),
+            MetaItem::NameValue(ref key, Lit::Str(ref value, _)) if key == "defaults" => defaults = Some(value.clone()),
+            MetaItem::NameValue(ref key, Lit::Str(ref value, _)) if key == "file" => file = Some(value.clone()),
+            MetaItem::NameValue(ref key, Lit::Str(ref value, _)) if key == "debug_file" => debug_file = Some(value.clone()),
+            MetaItem::NameValue(ref key, Lit::Str(ref value, _)) if key == "save_file" => save_file = Some(value.clone()),
+            MetaItem::NameValue(ref key, Lit::Str(ref value, _)) if key == "resolve" => {
+                resolve = Some(syn::parse_expr(value).expect("invalid resolve function"));
+            },
+            MetaItem::NameValue(ref key, Lit::Str(ref value, _)) if key == "constraint" => {
+                constraint = Some(syn::parse_expr(value).expect("invalid constraint function"));
+            },
+            MetaItem::NameValue(ref key, Lit::Str(ref value, _)) if key == "migrations" => {
+                migrations = Some(syn::parse_expr(value).expect("invalid migrations"));
+            },
+            MetaItem::List(ref key, ref items) if key == "save" => {
+                for item in items {
+                    match *item {
+                        NestedMetaItem::MetaItem(MetaItem::NameValue(ref key, Lit::Str(ref path, _))) => {
+                            save_keys.push(key.as_ref().to_string());
+                            save_paths.push(path.clone());
+                        },
+                        _ => panic!("save expect key = \"path\""),
+                    }
+                }
+            },
+            _ => panic!("unexpected configuration attribute on {}", name),
+        }
+    }
+
+    let app = app.expect("configuration expect an app");
+    let file = file.expect("configuration expect a file");
+    let debug_file = debug_file.unwrap_or(file.clone());
+    let save_file = save_file.expect("configuration expect a save_file");
+    let defaults = defaults.map(|defaults| quote! {
+        try!(layers.add_source("defaults", include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/", #defaults))));
+    });
+    let migrations = match migrations {
+        Some(migrations) => quote! { #migrations },
+        None => quote! { &[] },
+    };
+    let resolve = resolve.map(|resolve| quote! {
+        (#resolve)(&mut conf);
+    });
+    let constraint = constraint.map(|constraint| quote! {
+        try!((#constraint)(&conf).map_err(configuration::ConfigError::Constraint));
+    });
+
+    quote! {
+        impl #name {
+            /// the name of the configuration files, the debug file in debug build
+            pub fn file() -> &'static str {
+                let mut file = #file;
+                debug_assert!({
+                    file = #debug_file;
+                    true
+                });
+                file
+            }
+
+            /// the install directory or the current directory if not found
+            pub fn install_dir() -> ::std::path::PathBuf {
+                configuration::install_dir(#app, #name::file()).unwrap_or(::std::path::PathBuf::from("."))
+            }
+
+            /// the user configuration directory or the current directory if not found
+            pub fn user_dir() -> ::std::path::PathBuf {
+                configuration::user_dir(#app).unwrap_or(::std::path::PathBuf::from("."))
+            }
+
+            /// the configuration files of the install and user directories
+            pub fn files() -> Vec<::std::path::PathBuf> {
+                let mut files = vec!(#name::install_dir().join(#name::file()));
+                let user_file = #name::user_dir().join(#name::file());
+                if !files.contains(&user_file) {
+                    files.push(user_file);
+                }
+                files
+            }
+
+            pub fn save_file() -> ::std::path::PathBuf {
+                #name::user_dir().join(#save_file)
+            }
+
+            /// the keys of the save file and the keys they override
+            pub fn save_keys() -> &'static [(&'static str, &'static str)] {
+                &[#((#save_keys, #save_paths)),*]
+            }
+
+            /// the migrations of the save file, its version is their number
+            pub fn save_migrations() -> &'static [configuration::Migration] {
+                #migrations
+            }
+
+            /// load the layers: the defaults, the files, the save file
+            /// and the overrides like `table.key=value`
+            pub fn load(overrides: &[String]) -> Result<Self,configuration::ConfigError> {
+                let mut layers = configuration::Layers::new();
+                #defaults
+                for file in #name::files() {
+                    try!(layers.add_file(file));
+                }
+                try!(layers.add_save(#name::save_file(), #name::save_keys(), #name::save_migrations()));
+                try!(layers.add_overrides(overrides));
+
+                let mut conf = try!(<#name as configuration::FromToml>::from_toml(&configuration::toml::Value::Table(layers.table()))
+                    .map_err(|e| layers.error(e)));
+                #resolve
+                #constraint
+                Ok(conf)
+            }
+
+            /// write the values over the save file, see `configuration::write_save`
+            pub fn save<S: configuration::IntoToml>(save: S) -> ::std::io::Result<()> {
+                configuration::write_save(#name::save_file(), configuration::IntoToml::into_toml(save), #name::save_migrations())
+            }
+        }
+    }
+}
@@ -1,41 +1,79 @@
-#[macro_use] extern crate configuration;
-#[macro_use] extern crate lazy_static;
-extern crate toml;
+extern crate configuration;
+#[macro_use] extern crate configuration_derive;
 
-pub type array3 = [u8;3];
+use configuration::{ toml, TomlError };
 
 fn config_constraint(conf: &Config) -> Result<(),String> {
+    if conf.general.view <= 0. {
+        return Err(String::from("general.view must be positive"));
+    }
     Ok(())
 }
 
-configure!(
-    file = "config.toml";
-    debug_file = "config.toml";
-    save_file= "save.toml";
+/// the version 0 of the save file named `up` `forward`
+fn rename_forward(save: &mut toml::Table) -> Result<(),TomlError> {
+    if let Some(value) = save.remove("forward") {
+        save.insert(String::from("up"),value);
+    }
+    Ok(())
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,FromToml,IntoToml)]
+pub enum Safety {
+    Weak,
+    Robust,
+}
 
-    constraint = config_constraint;
+#[derive(FromToml)]
+pub struct General {
+    pub view: f32,
+    pub fullscreen: usize,
+    pub safety: Safety,
+}
 
-    general: {
-        view: t f32,
-        fullscreen: t usize save fullscreen,
-        safety: e String [weak,robust] save safety,
-    },
-    control: {
-        up: t array3 save up,
-        down: t u8,
-    },
-);
+#[derive(FromToml)]
+pub struct Control {
+    pub up: [u8;3],
+    pub down: u8,
+    #[configuration(default = "1")]
+    pub left: u8,
+    pub right: Option<u8>,
+}
 
+#[derive(FromToml,Configuration)]
+#[configuration(app = "configuration-usage", defaults = "config.toml", file = "config.toml", save_file = "save.toml", constraint = "config_constraint")]
+#[configuration(migrations = "&[rename_forward]")]
+#[configuration(save(fullscreen = "general.fullscreen", safety = "general.safety", up = "control.up"))]
+pub struct Config {
+    pub general: General,
+    pub control: Control,
+}
+
+#[derive(IntoToml)]
+pub struct Save {
+    pub fullscreen: usize,
+    pub safety: Safety,
+    pub up: [u8;3],
+}
+
+/// run with overrides like `general.view=60.0`
 fn main() {
-    assert!(config.general.view == 45f32);
-    assert!(config.general.fullscreen == 4usize);
-    assert!(config.control.up == [1u8,2u8,3u8]);
-    assert!(config.control.down == 5u8);
-    assert!(config.general.safety == String::from("weak"));
-
-    assert!(save(Save {
-        fullscreen: 4,
-        safety: "weak".into(),
-        up: [1,2,3],
-    }).is_ok());
+    let overrides = std::env::args().skip(1).collect::<Vec<_>>();
+    let config = match Config::load(&overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("ERROR: {}",e);
+            std::process::exit(1);
+        },
+    };
+    println!("view: {}, fullscreen: {}, safety: {:?}",config.general.view,config.general.fullscreen,config.general.safety);
+    println!("up: {:?}, down: {}, left: {}, right: {:?}",config.control.up,config.control.down,config.control.left,config.control.right);
+
+    if let Err(e) = Config::save(Save {
+        fullscreen: config.general.fullscreen,
+        safety: Safety::Weak,
+        up: config.control.up,
+    }) {
+        println!("ERROR: save failed: {}",e);
+    }
 }
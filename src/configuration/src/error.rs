@@ -0,0 +1,258 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{ self, Read };
+use toml;
+
+/// error of a toml value with the path of the key in fault
+#[derive(Debug,Clone,PartialEq)]
+pub struct TomlError {
+    /// dotted key path like `entities.char_mask` or `audio.short_effects[2]`
+    pub path: String,
+    pub kind: TomlErrorKind,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum TomlErrorKind {
+    /// the type expected and a description of the value found
+    Type {
+        expected: String,
+        found: String,
+    },
+    MissingKey,
+    UnexpectedKey,
+    /// the value has the type expected but is invalid
+    Invalid(String),
+}
+
+/// short description of a value like `integer 3` or `array of length 2`
+pub fn describe(value: &toml::Value) -> String {
+    use toml::Value::*;
+    match *value {
+        String(ref s) => format!("string {:?}",s),
+        Integer(i) => format!("integer {}",i),
+        Float(f) => format!("float {}",f),
+        Boolean(b) => format!("boolean {}",b),
+        Datetime(ref d) => format!("datetime {}",d),
+        Array(ref a) => format!("array of length {}",a.len()),
+        Table(_) => "table".into(),
+    }
+}
+
+impl TomlError {
+    pub fn expect(expected: &str, found: &toml::Value) -> Self {
+        TomlError {
+            path: String::new(),
+            kind: TomlErrorKind::Type {
+                expected: expected.into(),
+                found: describe(found),
+            },
+        }
+    }
+
+    pub fn missing_key(key: &str) -> Self {
+        TomlError {
+            path: key.into(),
+            kind: TomlErrorKind::MissingKey,
+        }
+    }
+
+    pub fn unexpected_key(key: &str) -> Self {
+        TomlError {
+            path: key.into(),
+            kind: TomlErrorKind::UnexpectedKey,
+        }
+    }
+
+    pub fn invalid<S: Into<String>>(message: S) -> Self {
+        TomlError {
+            path: String::new(),
+            kind: TomlErrorKind::Invalid(message.into()),
+        }
+    }
+
+    /// the error of the value of the key in the parent table
+    pub fn key(mut self, key: &str) -> Self {
+        self.path = if self.path.is_empty() || self.path.starts_with('[') {
+            format!("{}{}",key,self.path)
+        } else {
+            format!("{}.{}",key,self.path)
+        };
+        self
+    }
+
+    /// the error of the element at index in the parent array
+    pub fn index(mut self, index: usize) -> Self {
+        self.path = if self.path.is_empty() || self.path.starts_with('[') {
+            format!("[{}]{}",index,self.path)
+        } else {
+            format!("[{}].{}",index,self.path)
+        };
+        self
+    }
+}
+
+impl fmt::Display for TomlError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        use self::TomlErrorKind::*;
+        if !self.path.is_empty() {
+            try!(write!(fmt,"{}: ",self.path));
+        }
+        match self.kind {
+            Type { ref expected, ref found } => write!(fmt,"expect {}, found {}",expected,found),
+            MissingKey => write!(fmt,"missing key"),
+            UnexpectedKey => write!(fmt,"unexpected key"),
+            Invalid(ref message) => write!(fmt,"{}",message),
+        }
+    }
+}
+
+/// line and column starting at 1
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// split a path like `a.b[2].c` into `a`, `b`, `[2]`, `c`
+pub fn segments(path: &str) -> Vec<String> {
+    let mut segments = vec!();
+    for key in path.split('.') {
+        let mut parts = key.split('[');
+        if let Some(name) = parts.next() {
+            if !name.is_empty() {
+                segments.push(String::from(name));
+            }
+        }
+        for index in parts {
+            segments.push(format!("[{}",index));
+        }
+    }
+    segments
+}
+
+fn unquote(key: &str) -> String {
+    key.trim().trim_matches('"').into()
+}
+
+/// the position of the deepest table header or key of the path in the source,
+/// the n-th header of an array of tables is the element n of the array
+pub fn locate(source: &str, path: &str) -> Option<Position> {
+    let path = segments(path);
+    let mut table: Vec<String> = vec!();
+    let mut arrays: Vec<(Vec<String>,usize)> = vec!();
+    let mut best: Option<(usize,Position)> = None;
+
+    for (n,line) in source.lines().enumerate() {
+        let trimmed = line.trim_left();
+        let position = Position {
+            line: n+1,
+            column: line.len() - trimmed.len() + 1,
+        };
+
+        let full = if trimmed.starts_with("[[") {
+            let header = match trimmed[2..].find("]]") {
+                Some(end) => trimmed[2..2+end].split('.').map(unquote).collect::<Vec<_>>(),
+                None => continue,
+            };
+            let index = match arrays.iter_mut().find(|&&mut (ref h,_)| *h == header) {
+                Some(&mut (_,ref mut count)) => { *count += 1; *count },
+                None => { arrays.push((header.clone(),0)); 0 },
+            };
+            table = header;
+            table.push(format!("[{}]",index));
+            table.clone()
+        } else if trimmed.starts_with('[') {
+            table = match trimmed[1..].find(']') {
+                Some(end) => trimmed[1..1+end].split('.').map(unquote).collect(),
+                None => continue,
+            };
+            table.clone()
+        } else if trimmed.starts_with('#') {
+            continue
+        } else if let Some(eq) = trimmed.find('=') {
+            let mut full = table.clone();
+            full.push(unquote(&trimmed[..eq]));
+            full
+        } else {
+            continue
+        };
+
+        if full.len() <= path.len() && full[..] == path[..full.len()] {
+            if best.map(|(depth,_)| full.len() > depth).unwrap_or(true) {
+                best = Some((full.len(),position));
+            }
+        }
+    }
+    best.map(|(_,position)| position)
+}
+
+/// error while loading a configuration
+#[derive(Debug)]
+pub enum ConfigError {
+    Io {
+        file: String,
+        error: io::Error,
+    },
+    /// toml syntax errors
+    Parse {
+        file: String,
+        errors: Vec<(Position,String)>,
+    },
+    Value {
+        file: String,
+        /// the position of the key if found in the file
+        position: Option<Position>,
+        error: TomlError,
+    },
+    Constraint(String),
+}
+
+impl ConfigError {
+    /// the error of a value of the file, the file is read again to locate the key
+    pub fn value(file: &str, error: TomlError) -> Self {
+        let mut source = String::new();
+        let position = fs::File::open(file)
+            .and_then(|mut f| f.read_to_string(&mut source))
+            .ok()
+            .and_then(|_| locate(&*source,&*error.path));
+
+        ConfigError::Value {
+            file: file.into(),
+            position: position,
+            error: error,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        use self::ConfigError::*;
+        match *self {
+            Io { ref file, ref error } => write!(fmt,"{}: {}",file,error.description()),
+            Parse { ref file, ref errors } => {
+                try!(write!(fmt,"{}: toml parsing failed",file));
+                for &(position,ref message) in errors {
+                    try!(write!(fmt,"\n\t{}:{}:{}: {}",file,position.line,position.column,message));
+                }
+                Ok(())
+            },
+            Value { ref file, position: Some(position), ref error } => write!(fmt,"{}:{}:{}: {}",file,position.line,position.column,error),
+            Value { ref file, position: None, ref error } => write!(fmt,"{}: {}",file,error),
+            Constraint(ref message) => write!(fmt,"constraint failed: {}",message),
+        }
+    }
+}
+
+#[test]
+fn locate_test() {
+    let source = "[general]\ndifficulty = 1.0\n\n[audio]\n  effects = [\"a\",\n\"b\"]\n\n[[dungeons]]\nname = \"a\"\n[[dungeons]]\nname = \"b\"\n";
+    assert_eq!(locate(source,"general.difficulty"),Some(Position { line: 2, column: 1 }));
+    assert_eq!(locate(source,"general.unknown"),Some(Position { line: 1, column: 1 }));
+    assert_eq!(locate(source,"audio.effects[1]"),Some(Position { line: 5, column: 3 }));
+    assert_eq!(locate(source,"dungeons[1].name"),Some(Position { line: 11, column: 1 }));
+    assert_eq!(locate(source,"window"),None);
+    assert_eq!(format!("{}",TomlError::expect("float",&toml::Value::Integer(1)).key("difficulty").key("general")),
+               "general.difficulty: expect float, found integer 1");
+    assert_eq!(TomlError::missing_key("name").index(1).key("dungeons").path,"dungeons[1].name");
+}
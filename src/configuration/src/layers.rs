@@ -0,0 +1,299 @@
+//! a configuration merged from layers, each layer overrides the previous ones:
+//! tables are merged key by key, other values are replaced
+
+use error::{ locate, segments, ConfigError, Position, TomlError };
+use save::{ migrate, version, Migration };
+use std::env;
+use std::fs;
+use std::io::{ self, Read, Write };
+use std::path::{ Path, PathBuf };
+use toml;
+
+struct Layer {
+    /// the file or the origin of the layer like `--set`
+    name: String,
+    source: Option<String>,
+    table: toml::Table,
+    /// keys of a save file and the paths they override
+    renames: Vec<(String,String)>,
+}
+
+pub struct Layers {
+    layers: Vec<Layer>,
+}
+
+pub fn parse(name: &str, source: &str) -> Result<toml::Table,ConfigError> {
+    let mut parser = toml::Parser::new(source);
+    match parser.parse() {
+        Some(table) => Ok(table),
+        None => Err(ConfigError::Parse {
+            file: name.into(),
+            errors: parser.errors.iter().map(|e| {
+                let (line,column) = parser.to_linecol(e.lo);
+                (Position { line: line+1, column: column+1 }, e.desc.clone())
+            }).collect(),
+        }),
+    }
+}
+
+pub fn read(file: &Path) -> Result<String,ConfigError> {
+    let mut source = String::new();
+    try!(fs::File::open(file)
+         .and_then(|mut f| f.read_to_string(&mut source))
+         .map_err(|e| ConfigError::Io { file: file.to_string_lossy().into_owned(), error: e }));
+    Ok(source)
+}
+
+/// insert the value at the path like `general.difficulty`
+fn insert(table: &mut toml::Table, path: &str, value: toml::Value) -> Result<(),TomlError> {
+    let mut keys = path.split('.').collect::<Vec<_>>();
+    let last = keys.pop().unwrap();
+    let mut table = table;
+    for key in keys {
+        table = match *table.entry(key.into()).or_insert_with(|| toml::Value::Table(toml::Table::new())) {
+            toml::Value::Table(ref mut table) => table,
+            ref value => return Err(TomlError::expect("table",value).key(key)),
+        };
+    }
+    table.insert(last.into(),value);
+    Ok(())
+}
+
+fn merge(base: &mut toml::Table, layer: &toml::Table) {
+    for (key,value) in layer {
+        if let toml::Value::Table(ref layer) = *value {
+            if let Some(&mut toml::Value::Table(ref mut base)) = base.get_mut(key) {
+                merge(base,layer);
+                continue;
+            }
+        }
+        base.insert(key.clone(),value.clone());
+    }
+}
+
+/// whether the table has a value at the path split in segments
+fn contains(table: &toml::Table, segments: &[String]) -> bool {
+    let mut value = match segments.first().and_then(|key| table.get(key)) {
+        Some(value) => value,
+        None => return segments.is_empty(),
+    };
+    for segment in &segments[1..] {
+        let next = if segment.starts_with('[') {
+            segment.trim_matches(|c| c == '[' || c == ']').parse::<usize>().ok()
+                .and_then(|i| value.as_slice().and_then(|array| array.get(i)))
+        } else {
+            value.as_table().and_then(|table| table.get(segment))
+        };
+        value = match next {
+            Some(next) => next,
+            None => return false,
+        };
+    }
+    true
+}
+
+impl Layers {
+    pub fn new() -> Self {
+        Layers {
+            layers: vec!(),
+        }
+    }
+
+    pub fn add_source(&mut self, name: &str, source: &str) -> Result<(),ConfigError> {
+        let table = try!(parse(name,source));
+        self.layers.push(Layer {
+            name: name.into(),
+            source: Some(source.into()),
+            table: table,
+            renames: vec!(),
+        });
+        Ok(())
+    }
+
+    /// add the file if it exists
+    pub fn add_file<P: AsRef<Path>>(&mut self, file: P) -> Result<(),ConfigError> {
+        let file = file.as_ref();
+        if !file.is_file() {
+            return Ok(());
+        }
+        let source = try!(read(file));
+        self.add_source(&*file.to_string_lossy(),&*source)
+    }
+
+    /// add the save file if it exists, migrated to the current version,
+    /// keys are the keys of the save file and the paths they override,
+    /// unknown keys are ignored as they may come from another version,
+    /// a file of a newer version is skipped with a warning
+    pub fn add_save<P: AsRef<Path>>(&mut self, file: P, keys: &[(&str,&str)], migrations: &[Migration]) -> Result<(),ConfigError> {
+        let file = file.as_ref();
+        if !file.is_file() {
+            return Ok(());
+        }
+        let name = file.to_string_lossy().into_owned();
+        let source = try!(read(file));
+        let mut save = try!(parse(&*name,&*source));
+        match version(&save) {
+            Ok(version) if version > migrations.len() => {
+                writeln!(&mut io::stderr(), "WARNING {} is skipped: its version {} is newer than the supported version {}",
+                         name, version, migrations.len()).unwrap();
+                return Ok(());
+            }
+            _ => (),
+        }
+        if let Err(error) = migrate(&mut save,migrations) {
+            return Err(ConfigError::Value {
+                position: locate(&*source,&*error.path),
+                file: name,
+                error: error,
+            });
+        }
+
+        let mut table = toml::Table::new();
+        for (key,value) in save {
+            if let Some(&(_,path)) = keys.iter().find(|&&(k,_)| k == key) {
+                insert(&mut table,path,value).unwrap();
+            }
+        }
+        self.layers.push(Layer {
+            name: name,
+            source: Some(source),
+            table: table,
+            renames: keys.iter().map(|&(key,path)| (String::from(key),String::from(path))).collect(),
+        });
+        Ok(())
+    }
+
+    /// add overrides like `table.key=value`, values that aren't valid toml are strings
+    pub fn add_overrides(&mut self, overrides: &[String]) -> Result<(),ConfigError> {
+        let name = "--set";
+        let mut table = toml::Table::new();
+        for (i,string) in overrides.iter().enumerate() {
+            let (path,value) = match string.find('=') {
+                Some(eq) => (string[..eq].trim(), string[eq+1..].trim()),
+                None => return Err(ConfigError::Value {
+                    file: name.into(),
+                    position: None,
+                    error: TomlError::invalid(format!("expect table.key=value, found {:?}",string)).index(i),
+                }),
+            };
+            let value = toml::Parser::new(&*format!("value = {}",value)).parse()
+                .and_then(|mut table| table.remove("value"))
+                .unwrap_or(toml::Value::String(value.into()));
+            try!(insert(&mut table,path,value).map_err(|e| ConfigError::Value {
+                file: name.into(),
+                position: None,
+                error: e,
+            }));
+        }
+        self.layers.push(Layer {
+            name: name.into(),
+            source: None,
+            table: table,
+            renames: vec!(),
+        });
+        Ok(())
+    }
+
+    /// the merge of every layer
+    pub fn table(&self) -> toml::Table {
+        let mut table = toml::Table::new();
+        for layer in &self.layers {
+            merge(&mut table,&layer.table);
+        }
+        table
+    }
+
+    /// the error located in the last layer that defines the value,
+    /// or the table of the value if it is missing
+    pub fn error(&self, mut error: TomlError) -> ConfigError {
+        let segments = segments(&*error.path);
+        let parent = &segments[..segments.len().saturating_sub(1)];
+        let layer = self.layers.iter().rev().find(|layer| contains(&layer.table,&segments))
+            .or_else(|| self.layers.iter().rev().find(|layer| contains(&layer.table,parent)));
+
+        let layer = match layer {
+            Some(layer) => layer,
+            None => return ConfigError::Value {
+                file: String::new(),
+                position: None,
+                error: error,
+            },
+        };
+
+        for &(ref key,ref path) in &layer.renames {
+            if error.path == *path
+                || error.path.starts_with(&*format!("{}.",path))
+                || error.path.starts_with(&*format!("{}[",path)) {
+                error.path = format!("{}{}",key,&error.path[path.len()..]);
+                break;
+            }
+        }
+
+        ConfigError::Value {
+            file: layer.name.clone(),
+            position: layer.source.as_ref().and_then(|source| locate(&*source,&*error.path)),
+            error: error,
+        }
+    }
+}
+
+/// the first ancestor of the directory of the executable, or its `share/<app>`,
+/// that contains the file
+pub fn install_dir(app: &str, file: &str) -> Option<PathBuf> {
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(_) => return None,
+    };
+    let mut dir = exe.parent();
+    while let Some(d) = dir {
+        for candidate in vec!(d.to_path_buf(),d.join("share").join(app)) {
+            if candidate.join(file).is_file() {
+                return Some(candidate);
+            }
+        }
+        dir = d.parent();
+    }
+    None
+}
+
+/// `$XDG_CONFIG_HOME/<app>` or `$HOME/.config/<app>`
+pub fn user_dir(app: &str) -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME").map(PathBuf::from) {
+        Some(ref dir) if dir.is_absolute() => Some(dir.join(app)),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join(app)),
+    }
+}
+
+#[test]
+fn layers_test() {
+    let mut layers = Layers::new();
+    layers.add_source("defaults","[general]\nview = 45.0\nsafety = \"weak\"\n[control]\nup = [1,2,3]").unwrap();
+    layers.add_source("user","[general]\nsafety = \"robust\"").unwrap();
+    layers.add_overrides(&[String::from("control.up=[4,5,6]"),String::from("general.name=ruga")]).unwrap();
+
+    let table = layers.table();
+    assert_eq!(table["general"].lookup("view"),Some(&toml::Value::Float(45.)));
+    assert_eq!(table["general"].lookup("safety"),Some(&toml::Value::String("robust".into())));
+    assert_eq!(table["general"].lookup("name"),Some(&toml::Value::String("ruga".into())));
+    assert_eq!(table["control"].lookup("up").and_then(|v| v.as_slice()).map(|a| a.len()),Some(3));
+
+    match layers.error(TomlError::expect("integer",&toml::Value::Float(0.)).key("safety").key("general")) {
+        ConfigError::Value { file, position, .. } => {
+            assert_eq!(file,"user");
+            assert_eq!(position,Some(Position { line: 2, column: 1 }));
+        },
+        _ => panic!("expect value error"),
+    }
+    match layers.error(TomlError::missing_key("down").key("control")) {
+        ConfigError::Value { file, .. } => assert_eq!(file,"--set"),
+        _ => panic!("expect value error"),
+    }
+
+    // a save of a newer version is skipped
+    let file = env::temp_dir().join(format!("configuration_layers_test_{}.toml",::std::process::id()));
+    fs::File::create(&file).and_then(|mut f| f.write_all(b"version = 1\nsafety = \"none\"")).unwrap();
+    let mut layers = Layers::new();
+    layers.add_save(&file,&[("safety","general.safety")],&[]).unwrap();
+    assert!(layers.table().is_empty());
+    fs::remove_file(&file).unwrap();
+}
@@ -1,216 +1,156 @@
-//! save value must be clonable
-
-#[macro_use] extern crate lazy_static;
-extern crate toml;
+//! configuration are plain structs deriving `FromToml` and `Configuration`
+//! from the configuration_derive crate, see its documentation for the attributes
+//!
+//! a configuration is merged from layers: builtin defaults, the file in the
+//! install directory, the file in the user directory, the save file and
+//! overrides of the command line
+//!
+//! the save file has a version and is migrated when read, see `Migration`
+//!
+//! the configuration can be reloaded while the program runs with `Swappable`
+//! and `FileWatcher`, references to the previous configuration stay valid
+
+pub extern crate toml;
+
+mod error;
+mod layers;
+mod save;
+
+pub use error::{ describe, locate, ConfigError, Position, TomlError, TomlErrorKind };
+pub use layers::{ install_dir, user_dir, Layers };
+pub use save::{ migrate, with_suffix, write_save, Migration, VERSION_KEY };
 
 use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::fs;
+use std::io::{ self, Write };
+use std::path::{ Path, PathBuf };
+use std::sync::{ Arc, RwLock };
+use std::time::SystemTime;
+
+/// read the table of a toml file
+pub fn read_table(file: &str) -> Result<toml::Table,ConfigError> {
+    let source = try!(layers::read(Path::new(file)));
+    layers::parse(file,&*source)
+}
 
-#[macro_export]
-macro_rules! configure {
-    (
-        file = $file:expr;
-        debug_file = $debug_file:expr;
-        save_file = $save_file:expr;
-        constraint = $constraint:ident;
-        $($table:ident: {
-            $($key:ident: $(e $string:ident[$($variante:ident),*] $(save $enum_save_key:ident)*)* $(t $value:ident $(save $save_key:ident)*)*,)*
-        },)*
-    )
-        =>
+/// write the value in the file, its directory is created if needed:
+/// the value is written in `<file>.tmp` then renamed so the file is never left half written
+pub fn write_toml<P: AsRef<Path>>(file: P, value: toml::Value) -> io::Result<()> {
+    let file = file.as_ref();
+    if let Some(dir) = file.parent() {
+        try!(fs::create_dir_all(dir));
+    }
+    let tmp = save::with_suffix(file,".tmp");
     {
-        pub struct Save {
-            $($($($(pub $enum_save_key: $string,)*)*)*)*
-            $($($($(pub $save_key: $value,)*)*)*)*
-        }
-        impl_from_into_toml_for_struct!(Save {
-            $($($($($enum_save_key: $string,)*)*)*)*
-            $($($($($save_key: $value,)*)*)*)*
-        });
-        pub fn save(save: Save) -> std::io::Result<()> {
-            use std::fs::File;
-            use std::io::Write;
-            use configuration::IntoToml;
-            use std::path::Path;
-
-            let mut file = try!(File::create(Path::new($save_file)));
-            file.write_fmt(format_args!("{}",IntoToml::into_toml(save)))
-        }
-        $(#[allow(non_camel_case_types)] pub struct $table {
-            $(pub $key:
-              $($string)*
-              $($value)*
-              ,)*
-        })*
-        pub struct Config {
-            $(pub $table: $table,)*
-        }
+        let mut tmp = try!(fs::File::create(&tmp));
+        try!(tmp.write_fmt(format_args!("{}",value)));
+        try!(tmp.sync_all());
+    }
+    fs::rename(tmp,file)
+}
 
-        fn _get_table_from_file(file: &mut std::fs::File) -> Result<toml::Table,String> {
-            use std::io::Read;
+/// error on the first key of the table that isn't in keys
+pub fn check_keys(table: &toml::Table, keys: &[&str]) -> Result<(),TomlError> {
+    match table.keys().find(|key| !keys.contains(&&***key)) {
+        Some(key) => Err(TomlError::unexpected_key(key)),
+        None => Ok(()),
+    }
+}
 
-            let mut config_string = String::new();
-            try!(file.read_to_string(&mut config_string).map_err(|_| "ERROR: configuration file invalid: not valid UTF-8"));
-            let mut config_parser = toml::Parser::new(&*config_string);
+pub fn required<T: FromToml>(table: &toml::Table, key: &str) -> Result<T,TomlError> {
+    try!(optional(table,key)).ok_or_else(|| TomlError::missing_key(key))
+}
 
-            let config_table = config_parser.parse();
-            config_table.ok_or({
-                let mut error_msg = String::from("ERROR: configuration file invalid: toml parsing failed:");
-                let errors: Vec<toml::ParserError> = config_parser.errors.drain(..).collect();
+pub fn optional<T: FromToml>(table: &toml::Table, key: &str) -> Result<Option<T>,TomlError> {
+    match table.get(key) {
+        Some(value) => T::from_toml(value).map(Some).map_err(|e| e.key(key)),
+        None => Ok(None),
+    }
+}
 
-                for err in errors {
-                    let lo = config_parser.to_linecol(err.lo);
-                    let hi = config_parser.to_linecol(err.hi);
-                    error_msg.push_str(&*format!("\n\tfrom ({},{}) to ({},{}) {}",lo.0,lo.1,hi.0,hi.1,err.desc));
-                }
-                error_msg
-            })
-        }
-        fn _load_config() -> Result<Config,String> {
-            use std::fs::File;
-            use std::error::Error;
-            use toml;
-            use configuration::FromToml;
-            use std::path::Path;
-
-            let file = {
-                let mut file = $file;
-                debug_assert!({
-                    file = $debug_file;
-                    true
-                });
-                file
-            };
-            let mut config_file = try!(File::open(file).map_err(|e| {
-                format!("ERROR: an error occured when openning configuration file at {}{}{}",
-                        file,
-                        format!("\n\tdescription: {}",e.description()),
-                        if let Some(cause) = e.cause() { format!("\n\tcause: {}",cause.description()) } else { String::from("") })
-            }));
-            let mut config_table = try!(_get_table_from_file(&mut config_file));
-
-            let save = if Path::new($save_file).is_file() {
-                let mut save_file = try!(File::open($save_file).map_err(|e| {
-                    format!("ERROR: an error occured when openning save file at {}{}{}",
-                            file,
-                            format!("\n\tdescription: {}",e.description()),
-                            if let Some(cause) = e.cause() { format!("\n\tcause: {}",cause.description()) } else { String::from("") })
-                }));
-                let save_table = try!(_get_table_from_file(&mut save_file));
-                Some(try!(Save::from_toml(&toml::Value::Table(save_table))
-                          .map_err(|e| format!("ERROR: save file invalid: {}",e))))
-            } else {
-                None
-            };
-
-            let res = Config {
-                $($table: {
-                    let table_toml_value = try!(config_table
-                                                .remove(stringify!($table))
-                                                .ok_or_else(|| format!("ERROR: configuration file invalid: expect {} table",stringify!($table))));
-                    if let toml::Value::Table(mut table_table) = table_toml_value {
-                        let table = $table {$(
-                                $key: {
-                                    let value = try!(table_table
-                                             .remove(stringify!($key))
-                                             .ok_or_else(|| format!("ERROR: configuration file invalid: expect {}.{} key",stringify!($table),stringify!($key))));
-                                    $(
-                                        let variante_error = || {
-                                            let mut variante = String::from("");
-                                            $(
-                                                variante.push_str(&*format!(" \"{}\" or",stringify!($variante)));
-                                             )*
-                                                variante.pop().unwrap();
-                                                variante.pop().unwrap();
-                                                variante.pop().unwrap();
-                                                format!("ERROR: configuration file invalid: {}.{} expect{}",stringify!($table),stringify!($key),variante)
-                                        };
-
-                                        if false {
-                                            unreachable!();
-                                        }
-                                        $(
-                                        else if let Some(ref save) = save {
-                                            save.$enum_save_key.clone()
-                                        }
-                                        )*
-                                        else if let toml::Value::$string(string) = value {
-                                            if false {
-                                                unreachable!();
-                                            }
-                                            $(
-                                            else if string == String::from(stringify!($variante)) {
-                                                String::from(stringify!($variante))
-                                            }
-                                            )*
-                                            else {
-                                                return Err(variante_error());
-                                            }
-                                        } else {
-                                            return Err(variante_error());
-                                        }
-                                     )*
-                                    $(
-                                        if false {
-                                            unreachable!();
-                                        }
-                                        $(
-                                        else if let Some(ref save) = save {
-                                            save.$save_key.clone()
-                                        }
-                                        )*
-                                        else {
-                                            try!($value::from_toml(&value)
-                                                 .map_err(|e| format!("ERROR: configuration file invalid: {}.{}{}",stringify!($table),stringify!($key),e)))
-                                        }
-                                     )*
-                                },
-                                )*};
-
-                        if !table_table.is_empty() {
-                            let mut error = String::from("ERROR: configuration file invalid: unused keys:");
-                            for (key,_) in table_table {
-                                error.push_str(&*format!("\n\t{}.{}", stringify!($table), key));
-                            }
-                            return Err(error);
-                        }
-                        table
-                    } else {
-                        return Err(format!("ERROR: configuration file invalid: expect {} table",stringify!($table)));
-                    }
-                },)*
-            };
-            if !config_table.is_empty() {
-                let mut error = String::from("ERROR: configuration file invalid: unused keys:");
-                for (key,_) in config_table {
-                    error.push_str(&*format!("\n\t{}", key));
-                }
-                return Err(error);
-            }
-            try!($constraint(&res));
-            Ok(res)
+/// a value that can be replaced while it is read:
+/// readers load a snapshot of the value, a replaced value
+/// is freed once the last snapshot of it is dropped
+pub struct Swappable<T> {
+    value: RwLock<Arc<T>>,
+}
+
+impl<T> Swappable<T> {
+    pub fn new(value: T) -> Self {
+        Swappable {
+            value: RwLock::new(Arc::new(value)),
         }
+    }
 
-        lazy_static! {
-            pub static ref CONFIG: Config = {
-                use std::process::exit;
+    /// the current value, it doesn't change when the value is swapped
+    pub fn load(&self) -> Arc<T> {
+        self.value.read().unwrap().clone()
+    }
 
-                match _load_config() {
-                    Ok(conf) => conf,
-                    Err(err) => {
-                        println!("{}",err);
-                        exit(1);
-                    }
-                }
-            };
+    pub fn swap(&self, value: T) {
+        *self.value.write().unwrap() = Arc::new(value);
+    }
+}
+
+/// poll the modification time of a file
+pub struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl FileWatcher {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let mut watcher = FileWatcher {
+            path: path.into(),
+            modified: None,
+        };
+        watcher.modified = watcher.modified();
+        watcher
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path).and_then(|m| m.modified()).ok()
+    }
+
+    /// whether the file has been modified since the last call
+    pub fn changed(&mut self) -> bool {
+        let modified = self.modified();
+        if modified != self.modified {
+            self.modified = modified;
+            true
+        } else {
+            false
         }
-    };
+    }
+}
+
+#[test]
+fn swappable_test() {
+    let swappable = Swappable::new(1);
+    let previous = swappable.load();
+    swappable.swap(2);
+    assert_eq!(*previous,1);
+    assert_eq!(*swappable.load(),2);
+
+    // a replaced value is freed with its last snapshot
+    use std::rc::Rc;
+    let value = Rc::new(());
+    let swappable = Swappable::new(value.clone());
+    let previous = swappable.load();
+    swappable.swap(value.clone());
+    swappable.swap(value.clone());
+    assert_eq!(Rc::strong_count(&value),3);
+    drop(previous);
+    assert_eq!(Rc::strong_count(&value),2);
+    drop(swappable);
+    assert_eq!(Rc::strong_count(&value),1);
 }
 
 pub trait FromToml: Sized {
     /// convert toml element into a rust type,
     /// it raises an error if it is not the toml element expected
-    fn from_toml(&toml::Value) -> Result<Self,String>;
+    fn from_toml(&toml::Value) -> Result<Self,TomlError>;
 }
 pub trait IntoToml {
     /// convert value into toml value
@@ -230,18 +170,14 @@ macro_rules! impl_from_into_toml_for_enum {
         $($variant:ident),*
     }) => {
         impl configuration::FromToml for $ty {
-            fn from_toml(val: &toml::Value) -> Result<Self,String> {
-                if let &toml::Value::String(ref val) = val {
-                    if false {
-                        unreachable!()
-                    } $(else if val.to_lowercase() == stringify!($variant).to_lowercase() {
-                        Ok($ty::$variant)
-                    })* else {
-                        Err(" unexpected variante".into())
-                    }
-                } else {
-                    Err(" expect string".into())
+            fn from_toml(val: &toml::Value) -> Result<Self,configuration::TomlError> {
+                if let &toml::Value::String(ref string) = val {
+                    $(if string.to_lowercase() == stringify!($variant).to_lowercase() {
+                        return Ok($ty::$variant);
+                    })*
                 }
+                let variants: Vec<String> = vec!($(format!("{:?}",stringify!($variant).to_lowercase())),*);
+                Err(configuration::TomlError::expect(&*variants.join(" or "),val))
             }
         }
         impl configuration::IntoToml for $ty {
@@ -267,19 +203,12 @@ macro_rules! impl_from_into_toml_for_struct {
         $($variant_id:ident: $variant_ty:ident),*
     }) => {
         impl configuration::FromToml for $ty {
-            fn from_toml(val: &toml::Value) -> Result<Self,String> {
-                let table = try!(val.as_table().ok_or(String::from(" expect table")));
-                for (key,_) in table {
-                    match &**key {
-                        $(
-                            stringify!($variant_id) => (),
-                         )*
-                        _ => return Err(format!(" unexpected key: {}",key)),
-                    }
-                }
+            fn from_toml(val: &toml::Value) -> Result<Self,configuration::TomlError> {
+                let table = try!(val.as_table().ok_or_else(|| configuration::TomlError::expect("table",val)));
+                try!(configuration::check_keys(table,&[$(stringify!($variant_id)),*]));
                 Ok($ty {
                     $(
-                        $variant_id: try!($variant_ty::from_toml(try!(table.get(stringify!($variant_id)).ok_or(format!(" expect key: {}",stringify!($variant_id)))))),
+                        $variant_id: try!(configuration::required::<$variant_ty>(table,stringify!($variant_id))),
                      )*
                 })
             }
@@ -299,8 +228,8 @@ macro_rules! impl_from_into_toml_for_struct {
 macro_rules! toml_integer {
     ($ty:ty) => {
         impl FromToml for $ty {
-            fn from_toml(val: &toml::Value) -> Result<Self,String> {
-                Ok(try!(val.as_integer().ok_or(" expect integer")) as $ty)
+            fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+                Ok(try!(val.as_integer().ok_or_else(|| TomlError::expect("integer",val))) as $ty)
             }
         }
         impl IntoToml for $ty {
@@ -324,8 +253,8 @@ toml_integer!(isize);
 macro_rules! toml_float {
     ($ty:ty) => {
         impl FromToml for $ty {
-            fn from_toml(val: &toml::Value) -> Result<Self,String> {
-                Ok(try!(val.as_float().ok_or(" expect foat")) as $ty)
+            fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+                Ok(try!(val.as_float().ok_or_else(|| TomlError::expect("float",val))) as $ty)
             }
         }
         impl IntoToml for $ty {
@@ -339,8 +268,8 @@ toml_float!(f32);
 toml_float!(f64);
 
 impl FromToml for bool {
-    fn from_toml(val: &toml::Value) -> Result<Self,String> {
-        Ok(try!(val.as_bool().ok_or(" expect boolean")))
+    fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+        val.as_bool().ok_or_else(|| TomlError::expect("boolean",val))
     }
 }
 impl IntoToml for bool {
@@ -350,8 +279,8 @@ impl IntoToml for bool {
 }
 
 impl FromToml for String {
-    fn from_toml(val: &toml::Value) -> Result<Self,String> {
-        Ok(String::from(try!(val.as_str().ok_or(" expect string"))))
+    fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+        Ok(String::from(try!(val.as_str().ok_or_else(|| TomlError::expect("string",val)))))
     }
 }
 impl IntoToml for String {
@@ -363,15 +292,15 @@ impl IntoToml for String {
 macro_rules! toml_array {
     ($n:expr => $($i:expr)+) => {
         impl<T: FromToml> FromToml for [T;$n] {
-            fn from_toml(val: &toml::Value) -> Result<Self,String> {
-                let array = try!(val.as_slice().ok_or(" expect array"));
+            fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+                let array = try!(val.as_slice().ok_or_else(|| TomlError::expect(&*format!("array of length {}",$n),val)));
                 if array.len() != $n {
-                    return Err(format!(" expect length of array to be {}", $n));
+                    return Err(TomlError::expect(&*format!("array of length {}",$n),val));
                 }
                 Ok([
                    $(
                        try!(T::from_toml(&array[$i])
-                            .map_err(|e| format!("[{}]{}",$i,e))),
+                            .map_err(|e| e.index($i))),
                    )+
                 ])
             }
@@ -396,13 +325,13 @@ toml_array!(9 => 0 1 2 3 4 5 6 7 8);
 toml_array!(10 => 0 1 2 3 4 5 6 7 8 9);
 
 impl<T: FromToml> FromToml for Vec<T> {
-    fn from_toml(val: &toml::Value) -> Result<Self,String> {
-        let array = try!(val.as_slice().ok_or(" expect array"));
+    fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+        let array = try!(val.as_slice().ok_or_else(|| TomlError::expect("array",val)));
         let mut res = vec!();
         let mut i = 0;
         for elt in array {
             res.push(try!(T::from_toml(elt)
-                          .map_err(|e| format!("[{}]{}",i,e))));
+                          .map_err(|e| e.index(i))));
             i += 1;
         }
         Ok(res)
@@ -417,15 +346,15 @@ impl<T: IntoToml> IntoToml for Vec<T> {
 macro_rules! toml_tuple {
     ($n:expr =>  $([$i:ident $ni:expr])+) => {
         impl<$($i: FromToml),+> FromToml for ($($i),+) {
-            fn from_toml(val: &toml::Value) -> Result<Self,String> {
-                let array = try!(val.as_slice().ok_or(" expect array"));
+            fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+                let array = try!(val.as_slice().ok_or_else(|| TomlError::expect(&*format!("array of length {}",$n),val)));
                 if array.len() != $n {
-                    return Err(format!(" expect length of array to be {}", $n));
+                    return Err(TomlError::expect(&*format!("array of length {}",$n),val));
                 }
                 Ok((
                    $(
                        try!($i::from_toml(&array[$ni])
-                            .map_err(|e| format!("[{}]{}",$ni,e))),
+                            .map_err(|e| e.index($ni))),
                    )+
                 ))
             }
@@ -453,13 +382,13 @@ toml_tuple!(10 => [A 0][B 1][C 2][D 3][E 4][F 5][G 6][H 7][I 8][J 9]);
 macro_rules! toml_map {
     ($t:ty: $e:expr) => {
         impl<T: FromToml> FromToml for $t {
-            fn from_toml(val: &toml::Value) -> Result<Self,String> {
-                let config_map = try!(val.as_table().ok_or(" expect table"));
+            fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+                let config_map = try!(val.as_table().ok_or_else(|| TomlError::expect("table",val)));
                 let mut map = $e;
                 for (key,value) in config_map {
 
                     let value: T = try!(T::from_toml(value)
-                                        .map_err(|e| format!(".{}{}",key,e)));
+                                        .map_err(|e| e.key(key)));
 
                     map.insert(key.clone(),value);
                 }
@@ -489,16 +418,16 @@ pub struct BitflagU32 {
     pub val: u32,
 }
 impl FromToml for BitflagU32 {
-    fn from_toml(val: &toml::Value) -> Result<Self,String> {
-        let err = " expect string of 1 and 0 and of length < 32";
-        let mut string = String::from(try!(val.as_str().ok_or(err)));
-        if string.len() > 32 { return Err(String::from(err)) }
+    fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+        let err = || TomlError::expect("string of 1 and 0 of length <= 32",val);
+        let mut string = String::from(try!(val.as_str().ok_or_else(&err)));
+        if string.len() > 32 { return Err(err()) }
         let mut bitval = 0;
         while let Some(chr) = string.pop() {
             match chr {
                 '0' => bitval <<= 1,
                 '1' => bitval = (bitval << 1) + 1,
-                _ => return Err(String::from(err)),
+                _ => return Err(err()),
             }
         }
         Ok(BitflagU32 {
@@ -517,15 +446,12 @@ pub struct VecStringPath {
     pub val: String,
 }
 impl FromToml for VecStringPath {
-    fn from_toml(val: &toml::Value) -> Result<Self,String> {
-        use std::path::PathBuf;
-
-        let err = " expect array of string";
+    fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
         let mut path = PathBuf::new();
-        for elt in try!(val.as_slice().ok_or(err)) {
-            path.push(try!(elt.as_str().ok_or(err)));
+        for (i,elt) in try!(val.as_slice().ok_or_else(|| TomlError::expect("array of strings",val))).iter().enumerate() {
+            path.push(try!(elt.as_str().ok_or_else(|| TomlError::expect("string",elt).index(i))));
         }
-        let string = try!(path.to_str().ok_or(" unvalid UTF-8")).into();
+        let string = try!(path.to_str().ok_or_else(|| TomlError::invalid("path is not valid UTF-8"))).into();
 
         Ok(VecStringPath {
             val: string,
@@ -0,0 +1,128 @@
+//! save files are tables of flat keys with a format version:
+//! older files are migrated when read, unknown keys are kept when written
+//! and the previous file is kept as a backup
+
+use error::TomlError;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+use toml;
+use { read_table, write_toml };
+
+/// convert a save table from the version at its index to the next version
+pub type Migration = fn(&mut toml::Table) -> Result<(),TomlError>;
+
+/// the key of the version in the save file, files without it are version 0
+pub const VERSION_KEY: &'static str = "version";
+
+/// the file with the suffix appended to its name like `save.toml.bak`
+pub fn with_suffix(file: &Path, suffix: &str) -> PathBuf {
+    let mut name = file.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// the version of the save table
+pub fn version(table: &toml::Table) -> Result<usize,TomlError> {
+    match table.get(VERSION_KEY) {
+        Some(&toml::Value::Integer(version)) if version >= 0 => Ok(version as usize),
+        Some(value) => Err(TomlError::expect("positive integer",value).key(VERSION_KEY)),
+        None => Ok(0),
+    }
+}
+
+/// remove the version of the table and apply the migrations from that version,
+/// the current version is the number of migrations
+pub fn migrate(table: &mut toml::Table, migrations: &[Migration]) -> Result<(),TomlError> {
+    let version = try!(version(table));
+    if version > migrations.len() {
+        return Err(TomlError::invalid(format!("{} is newer than the supported version {}",version,migrations.len()))
+                   .key(VERSION_KEY));
+    }
+    table.remove(VERSION_KEY);
+    for migration in &migrations[version..] {
+        try!(migration(table));
+    }
+    Ok(())
+}
+
+/// write the values over the current save file: keys of the current file that
+/// aren't in the values are kept, a file that can't be read or migrated, like a
+/// file of a newer version, is never replaced,
+/// the current file is copied to `<file>.v<version>.bak` before being replaced
+/// so the file of a previous version is kept after migration
+pub fn write_save<P: AsRef<Path>>(file: P, values: toml::Value, migrations: &[Migration]) -> io::Result<()> {
+    let file = file.as_ref();
+    let values = match values {
+        toml::Value::Table(table) => table,
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput,"save values must be a table")),
+    };
+
+    let mut table = toml::Table::new();
+    if file.is_file() {
+        table = try!(read_table(&*file.to_string_lossy())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData,format!("{} is not replaced: {}",file.display(),e))));
+        let backup = with_suffix(file,&*format!(".v{}.bak",version(&table).unwrap_or(0)));
+        try!(migrate(&mut table,migrations)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData,format!("{} is not replaced: {}",file.display(),e))));
+        try!(fs::copy(file,backup));
+    }
+    table.extend(values);
+    table.insert(VERSION_KEY.into(),toml::Value::Integer(migrations.len() as i64));
+
+    write_toml(file,toml::Value::Table(table))
+}
+
+#[test]
+fn save_test() {
+    use std::io::{ Read, Write };
+
+    fn rename_forward(table: &mut toml::Table) -> Result<(),TomlError> {
+        if let Some(value) = table.remove("forward") {
+            table.insert("up".into(),value);
+        }
+        Ok(())
+    }
+    let migrations: &[Migration] = &[rename_forward];
+
+    let mut table = toml::Parser::new("forward = 1\nother = 2").parse().unwrap();
+    migrate(&mut table,migrations).unwrap();
+    assert_eq!(table.get("up"),Some(&toml::Value::Integer(1)));
+    assert_eq!(table.get("forward"),None);
+
+    let mut table = toml::Parser::new("version = 2").parse().unwrap();
+    assert_eq!(migrate(&mut table,migrations).unwrap_err().path,"version");
+
+    let dir = ::std::env::temp_dir().join(format!("configuration_save_test_{}",::std::process::id()));
+    let file = dir.join("save.toml");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    write_toml(&file,toml::Value::Table(toml::Parser::new("forward = 1\nother = 2").parse().unwrap())).unwrap();
+
+    let mut values = toml::Table::new();
+    values.insert("down".into(),toml::Value::Integer(3));
+    write_save(&file,toml::Value::Table(values),migrations).unwrap();
+
+    let table = read_table(&*file.to_string_lossy()).unwrap();
+    assert_eq!(table.get("version"),Some(&toml::Value::Integer(1)));
+    assert_eq!(table.get("up"),Some(&toml::Value::Integer(1)));
+    assert_eq!(table.get("other"),Some(&toml::Value::Integer(2)));
+    assert_eq!(table.get("down"),Some(&toml::Value::Integer(3)));
+    assert!(read_table(&*with_suffix(&file,".v0.bak").to_string_lossy()).unwrap().contains_key("forward"));
+    assert!(!with_suffix(&file,".tmp").exists());
+
+    // the backup of the previous version is kept
+    write_save(&file,toml::Value::Table(toml::Table::new()),migrations).unwrap();
+    assert!(read_table(&*with_suffix(&file,".v0.bak").to_string_lossy()).unwrap().contains_key("forward"));
+    assert!(read_table(&*with_suffix(&file,".v1.bak").to_string_lossy()).unwrap().contains_key("down"));
+
+    // newer and invalid files are not replaced
+    for source in &["version = 2\nup = 4", "up = "] {
+        fs::File::create(&file).and_then(|mut f| f.write_all(source.as_bytes())).unwrap();
+        assert!(write_save(&file,toml::Value::Table(toml::Table::new()),migrations).is_err());
+        let mut current = String::new();
+        fs::File::open(&file).and_then(|mut f| f.read_to_string(&mut current)).unwrap();
+        assert_eq!(current,*source);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
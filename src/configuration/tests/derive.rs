@@ -0,0 +1,66 @@
+extern crate configuration;
+#[macro_use] extern crate configuration_derive;
+
+use configuration::{ FromToml, IntoToml, TomlError, TomlErrorKind };
+use configuration::toml;
+
+#[derive(Debug,Clone,PartialEq,FromToml,IntoToml)]
+enum Mode {
+    Light,
+    Dark,
+    Custom(f32),
+}
+
+#[derive(Debug,PartialEq,FromToml)]
+struct Graphics {
+    mode: Mode,
+    #[configuration(default)]
+    luminosity: f32,
+    #[configuration(default = "vec!(1,2)")]
+    sizes: Vec<u32>,
+    #[configuration(rename = "window-title")]
+    title: Option<String>,
+}
+
+#[derive(Debug,PartialEq,FromToml)]
+struct Root {
+    graphics: Graphics,
+}
+
+fn parse(string: &str) -> Result<Root,TomlError> {
+    let table = toml::Parser::new(string).parse().unwrap();
+    Root::from_toml(&toml::Value::Table(table))
+}
+
+#[test]
+fn derive_test() {
+    let root = parse("[graphics]\nmode = \"dark\"\nwindow-title = \"ruga\"").unwrap();
+    assert_eq!(root, Root {
+        graphics: Graphics {
+            mode: Mode::Dark,
+            luminosity: 0.,
+            sizes: vec!(1,2),
+            title: Some(String::from("ruga")),
+        },
+    });
+
+    let root = parse("[graphics]\nmode = { custom = 0.5 }\nluminosity = 1.0").unwrap();
+    assert_eq!(root.graphics.mode,Mode::Custom(0.5));
+    assert_eq!(root.graphics.title,None);
+
+    assert_eq!(format!("{}",parse("[graphics]\nmode = \"light\"\nluminosity = \"high\"").unwrap_err()),
+               "graphics.luminosity: expect float, found string \"high\"");
+    assert_eq!(parse("[graphics]\nmode = \"light\"\nunknown = 1").unwrap_err(),TomlError {
+        path: String::from("graphics.unknown"),
+        kind: TomlErrorKind::UnexpectedKey,
+    });
+    assert_eq!(format!("{}",parse("[graphics]\nmode = \"blue\"").unwrap_err()),
+               "graphics.mode: expect \"light\" or \"dark\" or \"custom\", found string \"blue\"");
+    assert_eq!(parse("[graphics]\nmode = { custom = true }").unwrap_err().path,"graphics.mode.custom");
+    assert_eq!(parse("[graphics]\nsizes = [\"1\"]\nmode = \"dark\"").unwrap_err().path,"graphics.sizes[0]");
+    assert_eq!(parse("[graphics]").unwrap_err(),TomlError::missing_key("mode").key("graphics"));
+
+    let value = IntoToml::into_toml(Mode::Custom(2.));
+    assert_eq!(Mode::from_toml(&value),Ok(Mode::Custom(2.)));
+    assert_eq!(IntoToml::into_toml(Mode::Light),toml::Value::String(String::from("light")));
+}
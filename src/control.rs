@@ -4,9 +4,8 @@ use resource::*;
 use specs::Join;
 use specs;
 use config;
-use rand;
+use rand::Rng;
 use rand::distributions::{IndependentSample, Range};
-use baal;
 use utils::Into3D;
 
 #[derive(Debug,Clone,Default)]
@@ -26,24 +25,26 @@ impl Default for PlayerSystem {
 }
 impl specs::System<app::UpdateContext> for PlayerSystem {
     fn run(&mut self, arg: specs::RunArg, context: app::UpdateContext) {
-        let (players, states, entities) = arg.fetch(|world| {
+        let config = config.load();
+        let (players, states, mut audio, entities) = arg.fetch(|world| {
             (
                 world.read::<PlayerControl>(),
                 world.read::<PhysicState>(),
+                world.write_resource::<Audio>(),
                 world.entities(),
             )
         });
 
         if let Some((_,entity)) = (&players, &entities).iter().nth(0) {
             let state = states.get(entity).expect("playrcontrol expect state component");
-            baal::effect::set_listener(state.position.into_3d());
+            audio.set_listener(state.position.into_3d());
             self.restart_cooldown = Some(config.entities.char_restart);
         } else {
             self.restart_cooldown = if let Some(cooldown) = self.restart_cooldown {
                 if cooldown > 0. {
                     Some(cooldown - context.dt)
                 } else {
-                    context.control_tx.send(app::Control::ResetLevel).unwrap();
+                    context.control_tx.send(app::Control::PlayerDied).unwrap();
                     None
                 }
             } else {
@@ -90,18 +91,105 @@ impl specs::System<app::UpdateContext> for TowardPlayerSystem {
     }
 }
 
+/// awareness state of a monster, it goes up while the monster
+/// sees the player and falls back down gradually once it lost sight
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum MonsterState {
+    Idle,
+    Alerted,
+    Chasing,
+    Searching,
+}
+impl MonsterState {
+    fn velocity(&self) -> f32 {
+        let config = config.load();
+        match *self {
+            MonsterState::Idle => config.entities.monster_idle_velocity,
+            MonsterState::Alerted => config.entities.monster_alerted_velocity,
+            MonsterState::Chasing => config.entities.monster_chasing_velocity,
+            MonsterState::Searching => config.entities.monster_searching_velocity,
+        }
+    }
+    fn vision_time(&self) -> f32 {
+        let config = config.load();
+        match *self {
+            MonsterState::Idle => config.entities.monster_idle_vision_time,
+            MonsterState::Alerted => config.entities.monster_alerted_vision_time,
+            MonsterState::Chasing => config.entities.monster_chasing_vision_time,
+            MonsterState::Searching => config.entities.monster_searching_vision_time,
+        }
+    }
+    fn vision_length(&self) -> f32 {
+        let config = config.load();
+        match *self {
+            MonsterState::Idle => config.entities.monster_idle_vision_length,
+            MonsterState::Alerted => config.entities.monster_alerted_vision_length,
+            MonsterState::Chasing => config.entities.monster_chasing_vision_length,
+            MonsterState::Searching => config.entities.monster_searching_vision_length,
+        }
+    }
+    /// the state after a lookup given the awareness and the visibility of the player
+    fn next(&self, awareness: f32, player_visible: bool, alerted_awareness: f32, chasing_awareness: f32) -> MonsterState {
+        if player_visible {
+            if awareness >= chasing_awareness {
+                MonsterState::Chasing
+            } else if awareness >= alerted_awareness {
+                MonsterState::Alerted
+            } else {
+                *self
+            }
+        } else {
+            match *self {
+                MonsterState::Chasing => MonsterState::Searching,
+                MonsterState::Searching if awareness < chasing_awareness => MonsterState::Alerted,
+                MonsterState::Alerted if awareness < alerted_awareness => MonsterState::Idle,
+                state => state,
+            }
+        }
+    }
+}
+
+#[test]
+fn monster_state_next_test() {
+    use self::MonsterState::*;
+
+    // escalate while visible
+    assert_eq!(Idle.next(0.1, true, 0.2, 0.6), Idle);
+    assert_eq!(Idle.next(0.3, true, 0.2, 0.6), Alerted);
+    assert_eq!(Alerted.next(0.7, true, 0.2, 0.6), Chasing);
+    assert_eq!(Searching.next(0.7, true, 0.2, 0.6), Chasing);
+
+    // fall back down gradually
+    assert_eq!(Chasing.next(0.9, false, 0.2, 0.6), Searching);
+    assert_eq!(Searching.next(0.7, false, 0.2, 0.6), Searching);
+    assert_eq!(Searching.next(0.5, false, 0.2, 0.6), Alerted);
+    assert_eq!(Alerted.next(0.5, false, 0.2, 0.6), Alerted);
+    assert_eq!(Alerted.next(0.1, false, 0.2, 0.6), Idle);
+}
+
 pub struct MonsterControl {
+    state: MonsterState,
+    awareness: f32,
     next_lookup: f32,
+    since_lookup: f32,
+    last_known_position: Option<[f32;2]>,
+    /// waypoints toward the last known position of the player in reverse order
+    path: Vec<[f32;2]>,
 }
 impl specs::Component for MonsterControl {
     type Storage = specs::VecStorage<Self>;
 }
 impl MonsterControl {
-    pub fn new() -> Self {
-        let mut rng = rand::thread_rng();
-        let range = Range::new(0.,config.entities.monster_vision_time);
+    /// rng is the rng of the world so sessions can be replayed
+    pub fn new<R: Rng>(rng: &mut R) -> Self {
+        let range = Range::new(0.,MonsterState::Idle.vision_time());
         MonsterControl {
-            next_lookup: range.ind_sample(&mut rng),
+            state: MonsterState::Idle,
+            awareness: 0.,
+            next_lookup: range.ind_sample(rng),
+            since_lookup: 0.,
+            last_known_position: None,
+            path: vec!(),
         }
     }
 }
@@ -109,13 +197,16 @@ impl MonsterControl {
 pub struct MonsterSystem;
 impl specs::System<app::UpdateContext> for MonsterSystem {
     fn run(&mut self, arg: specs::RunArg, context: app::UpdateContext) {
-        let (mut monsters, players, mut forces, states, physic_world, entities) = arg.fetch(|world| {
+        let config = config.load();
+        let (mut monsters, players, mut forces, states, types, physic_world, navigation_grid, entities) = arg.fetch(|world| {
             (
                 world.write::<MonsterControl>(),
                 world.read::<PlayerControl>(),
                 world.write::<PhysicForce>(),
                 world.read::<PhysicState>(),
+                world.read::<PhysicType>(),
                 world.read_resource::<PhysicWorld>(),
+                world.read_resource::<NavigationGrid>(),
                 world.entities(),
             )
         });
@@ -126,44 +217,91 @@ impl specs::System<app::UpdateContext> for MonsterSystem {
             break;
         }
 
-        if let Some(player_pos) = player_pos {
-            for (mut monster, entity) in (&mut monsters, &entities).iter() {
-                let state = states.get(entity).expect("monster expect state component");
-                let force = forces.get_mut(entity).expect("monster expect force component");
+        for (mut monster, entity) in (&mut monsters, &entities).iter() {
+            let state = states.get(entity).expect("monster expect state component");
+            let force = forces.get_mut(entity).expect("monster expect force component");
+            let typ = types.get(entity).expect("monster expect type component");
 
-                let pos = state.position;
-                let angle = (player_pos[1] - pos[1]).atan2(player_pos[0] - pos[0]);
+            let pos = state.position;
 
-                force.direction = angle;
+            monster.next_lookup -= context.dt;
+            monster.since_lookup += context.dt;
 
-                monster.next_lookup -= context.dt;
+            if monster.next_lookup <= 0. {
+                let mut player_visible = false;
 
-                if monster.next_lookup <= 0. {
+                if let Some(player_pos) = player_pos {
                     let length = ((player_pos[1] - pos[1]).powi(2) + (player_pos[0] - pos[0]).powi(2)).sqrt();
-                    let ray = Ray {
-                        origin: pos,
-                        angle: angle,
-                        length: length,
-                        mask: config.entities.monster_vision_mask.val,
-                    };
-
-                    let mut player_visible = false;
-                    physic_world.raycast(&ray, &mut |(other_entity,_,_)| {
-                        if players.get(other_entity).is_some() {
-                            player_visible = true;
-                        }
-                        true
-                    });
+                    let vision_length = monster.state.vision_length();
+
+                    if length <= vision_length {
+                        let ray = Ray {
+                            origin: pos,
+                            angle: (player_pos[1] - pos[1]).atan2(player_pos[0] - pos[0]),
+                            length: length,
+                            mask: config.entities.monster_vision_mask.val,
+                        };
+
+                        physic_world.raycast(&ray, &mut |(other_entity,_,_)| {
+                            if players.get(other_entity).is_some() {
+                                player_visible = true;
+                            }
+                            true
+                        });
+                    }
+
+                    // awareness grows faster as the monster closes the distance
                     if player_visible {
-                        force.intensity = 1.;
-                    } else {
-                        force.intensity = 0.;
+                        monster.awareness += monster.since_lookup
+                            * config.entities.monster_awareness_gain
+                            * (2. - length/vision_length);
+                        monster.last_known_position = Some(player_pos);
+                        monster.path = navigation_grid.find_path(pos, player_pos).unwrap_or(vec!(player_pos));
                     }
+                }
+
+                if !player_visible {
+                    monster.awareness -= monster.since_lookup * config.entities.monster_awareness_decay;
+                }
+                monster.awareness = monster.awareness.max(0.).min(1.);
+
+                monster.state = monster.state.next(
+                    monster.awareness,
+                    player_visible,
+                    config.entities.monster_alerted_awareness,
+                    config.entities.monster_chasing_awareness);
+
+                if monster.state == MonsterState::Idle {
+                    monster.last_known_position = None;
+                    monster.path.clear();
+                }
+
+                monster.since_lookup = 0.;
+                monster.next_lookup = monster.state.vision_time();
+            }
+
+            let waypoint_radius = config.entities.monster_waypoint_radius;
+            while monster.path.len() > 1 {
+                let waypoint = *monster.path.last().unwrap();
+                if (waypoint[0] - pos[0]).powi(2) + (waypoint[1] - pos[1]).powi(2) < waypoint_radius.powi(2) {
+                    monster.path.pop();
+                } else {
+                    break;
+                }
+            }
 
-                    monster.next_lookup = config.entities.monster_vision_time;
+            if let Some(&waypoint) = monster.path.last() {
+                force.direction = (waypoint[1] - pos[1]).atan2(waypoint[0] - pos[0]);
+                force.intensity = (monster.state.velocity()*typ.damping/typ.force).max(0.).min(1.);
+                if monster.path.len() == 1 && (waypoint[0] - pos[0]).powi(2) + (waypoint[1] - pos[1]).powi(2) < waypoint_radius.powi(2) {
+                    monster.path.clear();
                 }
+            } else {
+                if let Some(last_known_position) = monster.last_known_position {
+                    force.direction = (last_known_position[1] - pos[1]).atan2(last_known_position[0] - pos[0]);
+                }
+                force.intensity = 0.;
             }
         }
     }
 }
-
@@ -0,0 +1,208 @@
+//! in-game room editor, rooms are saved in the png format read by load_level
+
+use config;
+use configuration;
+use graphics::{ Color, Frame, Layer };
+use levels::{ self, Castle, Level };
+use png::{ self, HasParameters };
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use utils::Direction;
+
+/// the entities of entities.rs that can be placed,
+/// the tagged portals of the castle are placeable too
+pub const KINDS: [&'static str; 6] = ["wall","character","portal","laser","monster","column"];
+
+pub struct Editor {
+    pub level: Level,
+    pub cursor: [isize;2],
+    kind: usize,
+    kinds: Vec<String>,
+    tiles: HashMap<[isize;2],String>,
+}
+
+/// the castle of the room and the path of its png map
+fn castle_and_map_path<'a>(level: &Level, castles: &'a Vec<Castle>) -> Result<(&'a Castle,PathBuf),String> {
+    match *level {
+        Level::Room { castle: castle_id, dungeon: dungeon_id, room: room_id } => {
+            let castle = try!(castles.get(castle_id).ok_or(String::from("castle doesn't exist")));
+            let room = try!(castle.dungeons.get(dungeon_id).and_then(|d| d.rooms.get(room_id))
+                            .ok_or(String::from("room doesn't exist")));
+
+            if levels::room_text_path(castle,room).exists() {
+                return Err(format!("room {} is a text room",room));
+            }
+
+            let map_path = levels::room_map_path(castle,room);
+            match map_path.extension().and_then(|e| e.to_str()) {
+                Some("map") | Some("json") | Some("tmx") => Err(format!("room {} is not a png map",room)),
+                _ => Ok((castle,map_path)),
+            }
+        },
+        _ => Err(String::from("only rooms can be edited")),
+    }
+}
+
+fn kind_color(name: &str, castle: &Castle) -> Color {
+    let config = config.load();
+    if let Some(graphic) = castle.prefabs.get(name)
+        .or_else(|| config.entities.prefabs.get(name))
+        .and_then(|prefab| prefab.graphic.as_ref()) {
+        return graphic.color;
+    }
+
+    if name.starts_with("portal.") {
+        return config.entities.portal_start_color;
+    }
+
+    match name {
+        "character" => config.entities.char_color,
+        "portal" => config.entities.portal_start_color,
+        "laser" => config.entities.laser_color,
+        "monster" => config.entities.monster_color,
+        "column" => config.entities.column_color,
+        "wall" => config.entities.wall_color,
+        _ => config.editor.unknown_color,
+    }
+}
+
+impl Editor {
+    /// edit the room, its png map is loaded if it exists
+    pub fn new(level: Level, castles: &Vec<Castle>) -> Result<Editor,String> {
+        let (castle,map_path) = try!(castle_and_map_path(&level,castles));
+
+        let mut tiles = HashMap::new();
+        if map_path.exists() {
+            let map = try!(levels::read_png_map(&map_path,castle).map_err(|e| format!("{}",e)));
+            for (pos,name) in map {
+                if name != "empty" {
+                    tiles.insert(pos,name);
+                }
+            }
+        }
+
+        let cursor = tiles.iter()
+            .find(|&(_,name)| name == "character")
+            .map(|(pos,_)| *pos)
+            .unwrap_or([0,0]);
+
+        let mut kinds: Vec<String> = KINDS.iter().map(|&kind| kind.into()).collect();
+        let mut tagged_portals = castle.colors.values()
+            .filter(|name| name.starts_with("portal."))
+            .cloned()
+            .collect::<Vec<_>>();
+        tagged_portals.sort();
+        tagged_portals.dedup();
+        kinds.extend(tagged_portals);
+
+        Ok(Editor {
+            level: level,
+            cursor: cursor,
+            kind: 0,
+            kinds: kinds,
+            tiles: tiles,
+        })
+    }
+
+    pub fn kind(&self) -> &str {
+        &*self.kinds[self.kind]
+    }
+
+    pub fn next_kind(&mut self) {
+        self.kind = (self.kind + 1) % self.kinds.len();
+    }
+
+    pub fn previous_kind(&mut self) {
+        self.kind = (self.kind + self.kinds.len() - 1) % self.kinds.len();
+    }
+
+    pub fn move_cursor(&mut self, direction: Direction) {
+        match direction {
+            Direction::Up => self.cursor[1] += 1,
+            Direction::Down => self.cursor[1] -= 1,
+            Direction::Right => self.cursor[0] += 1,
+            Direction::Left => self.cursor[0] -= 1,
+        }
+    }
+
+    pub fn place(&mut self) {
+        let kind = self.kind().into();
+        self.tiles.insert(self.cursor,kind);
+    }
+
+    pub fn erase(&mut self) {
+        self.tiles.remove(&self.cursor);
+    }
+
+    pub fn tiles(&self) -> Vec<([isize;2],String)> {
+        self.tiles.iter().map(|(pos,name)| (*pos,name.clone())).collect()
+    }
+
+    pub fn draw(&self, frame: &mut Frame, castles: &Vec<Castle>) {
+        let config = config.load();
+        let castle = match self.level {
+            Level::Room { castle, .. } => &castles[castle],
+            _ => unreachable!(),
+        };
+
+        for (pos,name) in &self.tiles {
+            frame.draw_square(pos[0] as f32,pos[1] as f32,0.5,Layer::Middle,kind_color(name,castle));
+        }
+
+        let x = self.cursor[0] as f32;
+        let y = self.cursor[1] as f32;
+        frame.draw_square(x,y,config.editor.cursor_radius,Layer::Floor,config.editor.cursor_color);
+        frame.draw_square(x,y,config.editor.kind_radius,Layer::Ceil,kind_color(self.kind(),castle));
+        frame.draw_text(x+1.,y,config.graphics.font_scale,self.kind(),Layer::Ceil,config.editor.cursor_color);
+    }
+
+    /// save the room in its png map, the top left tile is at the origin of the image
+    pub fn save(&self, castles: &Vec<Castle>) -> Result<PathBuf,String> {
+        let (castle,map_path) = try!(castle_and_map_path(&self.level,castles));
+
+        if self.tiles.is_empty() {
+            return Err(String::from("room is empty"));
+        }
+
+        let min_x = self.tiles.keys().map(|p| p[0]).min().unwrap();
+        let max_x = self.tiles.keys().map(|p| p[0]).max().unwrap();
+        let min_y = self.tiles.keys().map(|p| p[1]).min().unwrap();
+        let max_y = self.tiles.keys().map(|p| p[1]).max().unwrap();
+
+        let width = (max_x - min_x + 1) as usize;
+        let height = (max_y - min_y + 1) as usize;
+
+        let empty = try!(castle.color_of("empty").ok_or(String::from("no color defines empty")));
+        let mut data = Vec::with_capacity(3*width*height);
+        for py in 0..height {
+            for px in 0..width {
+                // -y because opengl and image editor are usually inverted
+                let pos = [min_x + px as isize, max_y - py as isize];
+                let color = match self.tiles.get(&pos) {
+                    Some(name) => try!(castle.color_of(name).ok_or(format!("no color defines {}",name))),
+                    None => empty,
+                };
+                data.extend_from_slice(&color);
+            }
+        }
+
+        let mut png_data = vec!();
+        {
+            let mut encoder = png::Encoder::new(&mut png_data,width as u32,height as u32);
+            encoder.set(png::ColorType::RGB).set(png::BitDepth::Eight);
+            let mut writer = try!(encoder.write_header().map_err(|e| format!("png encoding error: {}",e)));
+            try!(writer.write_image_data(&data).map_err(|e| format!("png encoding error: {}",e)));
+        }
+
+        // the map is replaced once written so a failure doesn't corrupt it
+        let tmp = configuration::with_suffix(&map_path,".tmp");
+        try!(fs::File::create(&tmp)
+             .and_then(|mut file| file.write_all(&png_data).and_then(|_| file.sync_all()))
+             .and_then(|_| fs::rename(&tmp,&map_path))
+             .map_err(|e| format!("{}: {}",map_path.display(),e)));
+
+        Ok(map_path)
+    }
+}
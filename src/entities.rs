@@ -1,10 +1,15 @@
 use components::*;
+use resource::WorldRng;
 use specs;
 use config;
 use levels;
+use prefab::*;
+use physic::IntoGrid;
 use std::sync::Arc;
 
-pub fn add_character(world: &mut specs::World, pos: [isize;2]) {
+pub fn add_character<P: IntoGrid>(world: &mut specs::World, pos: P) {
+    let config = config.load();
+    let pos = pos.into_grid();
     world.create_now()
         .with::<PhysicState>(PhysicState::new(pos))
         .with::<PhysicDynamic>(PhysicDynamic)
@@ -31,7 +36,8 @@ pub fn add_character(world: &mut specs::World, pos: [isize;2]) {
         .build();
 }
 
-pub fn add_wall(world: &mut specs::World, pos: [isize;2]) {
+pub fn add_wall<P: IntoGrid>(world: &mut specs::World, pos: P) {
+    let config = config.load();
     world.create_now()
         .with::<PhysicState>(PhysicState::new(pos))
         .with::<PhysicStatic>(PhysicStatic)
@@ -45,7 +51,8 @@ pub fn add_wall(world: &mut specs::World, pos: [isize;2]) {
         .build();
 }
 
-pub fn add_column(world: &mut specs::World, pos: [isize;2]) {
+pub fn add_column<P: IntoGrid>(world: &mut specs::World, pos: P) {
+    let config = config.load();
     world.create_now()
         .with::<Column>(Column::new(config.entities.column_spawn_snd))
         .with::<PhysicState>(PhysicState::new(pos))
@@ -61,20 +68,23 @@ pub fn add_column(world: &mut specs::World, pos: [isize;2]) {
 }
 
 pub fn add_ball(world: &mut specs::World, pos: [f32;2], arc: Arc<()>) {
+    let config = config.load();
     world.create_now()
         .with::<PhysicState>(PhysicState::new(pos))
         .with::<Ball>(Ball::new(arc))
         .with::<PhysicDynamic>(PhysicDynamic)
-        .with::<PhysicType>(PhysicType::new_movable(
+        .with::<PhysicType>(PhysicType {
+            continuous: true,
+            ..PhysicType::new_movable(
                 config.entities.ball_group.val,
                 config.entities.ball_mask.val,
                 Shape::Circle(config.entities.ball_radius),
                 CollisionBehavior::Persist,
                 config.entities.ball_velocity,
                 config.entities.ball_time,
-                config.entities.ball_weight))
+                config.entities.ball_weight)
+        })
         .with::<PhysicForce>(PhysicForce::new_full())
-        .with::<PhysicTrigger>(PhysicTrigger::new())
         .with::<Life>(Life::new(config.entities.ball_die_snd))
         .with::<Graphic>(Graphic::new(
                 config.entities.ball_color,
@@ -88,7 +98,9 @@ pub fn add_ball(world: &mut specs::World, pos: [f32;2], arc: Arc<()>) {
         .build();
 }
 
-pub fn add_monster(world: &mut specs::World, pos: [isize;2]) {
+pub fn add_monster<P: IntoGrid>(world: &mut specs::World, pos: P) {
+    let config = config.load();
+    let control = MonsterControl::new(&mut world.write_resource::<WorldRng>().0);
     world.create_now()
         .with::<PhysicState>(PhysicState::new(pos))
         .with::<PhysicDynamic>(PhysicDynamic)
@@ -105,7 +117,7 @@ pub fn add_monster(world: &mut specs::World, pos: [isize;2]) {
         .with::<Graphic>(Graphic::new(
                 config.entities.monster_color,
                 config.entities.monster_layer))
-        .with::<MonsterControl>(MonsterControl::new())
+        .with::<MonsterControl>(control)
         .with::<Killer>(Killer {
             kamikaze: true,
             mask: config.entities.monster_killer_mask.val,
@@ -116,7 +128,8 @@ pub fn add_monster(world: &mut specs::World, pos: [isize;2]) {
         .build();
 }
 
-pub fn add_laser(world: &mut specs::World, pos: [isize;2]) {
+pub fn add_laser<P: IntoGrid>(world: &mut specs::World, pos: P) {
+    let config = config.load();
     world.create_now()
         .with::<PhysicState>(PhysicState::new(pos))
         .with::<PhysicStatic>(PhysicStatic)
@@ -137,7 +150,8 @@ pub fn add_laser(world: &mut specs::World, pos: [isize;2]) {
         .build();
 }
 
-pub fn add_portal(world: &mut specs::World, pos: [isize;2], destination: levels::Level) {
+pub fn add_portal<P: IntoGrid>(world: &mut specs::World, pos: P, destination: levels::Level) {
+    let config = config.load();
     world.create_now()
         .with::<Portal>(Portal::new(destination))
         .with::<GridSquare>(GridSquare::new(pos))
@@ -161,3 +175,78 @@ pub fn add_text(world: &mut specs::World, x: f32, y: f32, scale: f32, text: Stri
         .with::<Text>(Text::new(x,y,scale,text))
         .build();
 }
+
+pub fn add_prefab<P: IntoGrid>(world: &mut specs::World, prefab: &Prefab, pos: P) {
+    let pos = pos.into_grid();
+    let monster_control = match prefab.control {
+        Some(PrefabControl::Monster) => Some(MonsterControl::new(&mut world.write_resource::<WorldRng>().0)),
+        _ => None,
+    };
+    let mut builder = world.create_now();
+
+    if let Some(ref physic) = prefab.physic {
+        builder = builder.with::<PhysicState>(PhysicState::new(pos));
+        builder = match physic.typ {
+            PrefabPhysicType::Static => {
+                builder.with::<PhysicStatic>(PhysicStatic)
+                    .with::<PhysicType>(PhysicType::new_static(
+                            physic.group,
+                            physic.mask,
+                            physic.shape.clone()))
+            },
+            PrefabPhysicType::Dynamic { velocity, time, weight, continuous, ref collision, restitution, friction } => {
+                let force = match prefab.control {
+                    Some(PrefabControl::TowardPlayer) => PhysicForce::new_full(),
+                    _ => PhysicForce::new(),
+                };
+                builder.with::<PhysicDynamic>(PhysicDynamic)
+                    .with::<PhysicType>(PhysicType {
+                        continuous: continuous,
+                        restitution: restitution,
+                        friction: friction,
+                        ..PhysicType::new_movable(
+                            physic.group,
+                            physic.mask,
+                            physic.shape.clone(),
+                            collision.clone(),
+                            velocity,
+                            time,
+                            weight)
+                    })
+                    .with::<PhysicForce>(force)
+            },
+        };
+    } else {
+        builder = builder.with::<GridSquare>(GridSquare::new(pos));
+    }
+
+    if let Some(ref life) = prefab.life {
+        builder = builder.with::<Life>(Life::new(life.die_snd));
+    }
+    if let Some(ref killer) = prefab.killer {
+        builder = builder.with::<Killer>(Killer {
+            kamikaze: killer.kamikaze,
+            mask: killer.mask,
+            kill_snd: killer.kill_snd,
+        });
+    }
+    if let Some(ref graphic) = prefab.graphic {
+        builder = builder.with::<Graphic>(Graphic::new(graphic.color, graphic.layer));
+    }
+    builder = match prefab.control {
+        Some(PrefabControl::Player) => builder.with::<PlayerControl>(PlayerControl),
+        Some(PrefabControl::Monster) => builder.with::<MonsterControl>(monster_control.unwrap()),
+        Some(PrefabControl::TowardPlayer) => builder.with::<TowardPlayerControl>(TowardPlayerControl),
+        None => builder,
+    };
+    if let Some(ref column) = prefab.column {
+        builder = builder.with::<Column>(Column::new(column.spawn_snd));
+    }
+    builder = match prefab.persistent_snd {
+        Some(PrefabPersistentSnd::Static(id)) => builder.with::<StaticPersistentSnd>(StaticPersistentSnd::new(id)),
+        Some(PrefabPersistentSnd::Dynamic(id)) => builder.with::<DynPersistentSnd>(DynPersistentSnd::new(id)),
+        None => builder,
+    };
+
+    builder.build();
+}
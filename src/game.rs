@@ -0,0 +1,419 @@
+//! game logic without rendering nor input: the world, its systems
+//! and the flow of levels driven by controls
+
+use app::{ Control, Effect, UpdateContext };
+use components::*;
+use config;
+use entities;
+use levels;
+use progress::Progress;
+use resource::{ Audio, WorldRng };
+use specs;
+use std::sync::RwLockWriteGuard;
+use specs::Join;
+use std::sync::mpsc;
+use systems::*;
+
+/// what handling controls did, the front end can react to it
+#[derive(Debug,Clone,PartialEq)]
+pub enum Outcome {
+    /// a level has been loaded
+    Loaded,
+    /// the level has been reset, it is also loaded
+    Reset,
+    /// the progress changed and should be saved
+    Progress,
+    /// a level failed to load and a fallback level has been loaded instead
+    Failed(String),
+}
+
+pub struct Game {
+    pub castles: Vec<levels::Castle>,
+    pub current_level: levels::Level,
+    pub progress: Progress,
+    /// the room played from the tiles of the editor instead of its map,
+    /// until another level is loaded
+    play_test: Option<(levels::Level,Vec<([isize;2],String)>)>,
+    planner: specs::Planner<UpdateContext>,
+    control_rx: mpsc::Receiver<Control>,
+    control_tx: mpsc::Sender<Control>,
+    effect_rx: mpsc::Receiver<Effect>,
+    effect_tx: mpsc::Sender<Effect>,
+}
+
+impl Game {
+    /// create the world with the rng seeded and the entry level loaded
+    pub fn new(castles: Vec<levels::Castle>, progress: Progress, audio: Audio, seed: u64) -> Result<Game,String> {
+        let config = config.load();
+        // init world
+        let mut world = specs::World::new();
+        world.register::<PlayerControl>();
+        world.register::<TowardPlayerControl>();
+        world.register::<MonsterControl>();
+
+        world.register::<PhysicState>();
+        world.register::<PhysicForce>();
+        world.register::<PhysicType>();
+        world.register::<PhysicDynamic>();
+        world.register::<PhysicStatic>();
+        world.register::<PhysicTrigger>();
+        world.register::<GridSquare>();
+
+        world.register::<Graphic>();
+
+        world.register::<Life>();
+        world.register::<Killer>();
+        world.register::<Ball>();
+        world.register::<Column>();
+
+        world.register::<Portal>();
+
+        world.register::<FixedCameraText>();
+        world.register::<Text>();
+        world.register::<FixedCamera>();
+
+        world.register::<DynPersistentSnd>();
+        world.register::<StaticPersistentSnd>();
+
+        world.add_resource(WorldRng::new(seed));
+        world.add_resource(audio);
+
+        // load level
+        let level = levels::Level::Entry;
+        try!(levels::load_level(&level, &castles, &progress, &mut world)
+             .map_err(|e| format!("load entry level failed: {}",e)));
+
+        // init planner
+        let mut planner = specs::Planner::new(world,config.general.number_of_thread);
+        planner.add_system(PhysicSystem, "physic", 10);
+        planner.add_system(NavigationSystem, "navigation", 9);
+        planner.add_system(PlayerSystem::default(), "player", 5);
+        planner.add_system(MonsterSystem, "monster", 5);
+        planner.add_system(TowardPlayerSystem, "toward_player", 5);
+        planner.add_system(KillerSystem, "killer", 5);
+        planner.add_system(BallSystem, "ball", 5);
+        planner.add_system(PortalSystem, "portal", 5);
+        planner.add_system(ColumnSystem, "column", 5);
+        planner.add_system(LifeSystem, "life", 1);
+        planner.add_system(PersistentSndSystem::default(), "life", 2);
+
+        let (effect_tx, effect_rx) = mpsc::channel();
+        let (control_tx, control_rx) = mpsc::channel();
+
+        Ok(Game {
+            castles: castles,
+            current_level: level,
+            progress: progress,
+            play_test: None,
+            planner: planner,
+            control_rx: control_rx,
+            control_tx: control_tx,
+            effect_rx: effect_rx,
+            effect_tx: effect_tx,
+        })
+    }
+
+    pub fn world(&mut self) -> &mut specs::World {
+        self.planner.mut_world()
+    }
+
+    pub fn audio(&mut self) -> RwLockWriteGuard<Audio> {
+        self.world().write_resource::<Audio>()
+    }
+
+    pub fn send(&self, control: Control) {
+        self.control_tx.send(control).unwrap();
+    }
+
+    pub fn try_recv_effect(&self) -> Option<Effect> {
+        self.effect_rx.try_recv().ok()
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        *self.world().write_resource::<WorldRng>() = WorldRng::new(seed);
+    }
+
+    /// load every room of every castle
+    pub fn check_levels(&mut self) -> Result<(),String> {
+        for (c,castle) in self.castles.iter().enumerate() {
+            for (d,dungeon) in castle.dungeons.iter().enumerate() {
+                for (r,_) in dungeon.rooms.iter().enumerate() {
+                    let level = levels::Level::Room {
+                        castle: c,
+                        dungeon: d,
+                        room: r,
+                    };
+                    try!(levels::load_level(&level, &self.castles, &self.progress, self.planner.mut_world())
+                         .map_err(|e| format!("load level {}.{}.{} failed: {}",castle.name,dungeon.name,r,e)));
+                }
+            }
+        }
+
+        let level = self.current_level.clone();
+        self.goto_level(level)
+    }
+
+    pub fn level_name(&self, level: &levels::Level) -> String {
+        match *level {
+            levels::Level::Room { castle: c, dungeon: d, room: r } => format!("room (castle: {:?}, dungeon: {:?}, room: {:?})",
+            self.castles.get(c).map(|c| &c.name),
+            self.castles.get(c).and_then(|c| c.dungeons.get(d)).map(|d| &d.name),
+            self.castles.get(c).and_then(|c| c.dungeons.get(d)).and_then(|d| d.rooms.get(r)),
+            ),
+            levels::Level::Corridor { castle: c } => format!("corridor (castle: {:?})",self.castles.get(c).map(|c| &c.name)),
+            levels::Level::Entry => "entry".into(),
+        }
+    }
+
+    pub fn goto_level(&mut self, level: levels::Level) -> Result<(),String> {
+        while let Ok(_) = self.control_rx.try_recv() {}
+        while let Ok(_) = self.effect_rx.try_recv() {}
+
+        let result = match self.play_test {
+            Some((ref room,ref tiles)) if *room == level => levels::load_room_tiles(&level,&self.castles,self.planner.mut_world(),tiles),
+            _ => levels::load_level(&level,&self.castles,&self.progress,self.planner.mut_world()),
+        };
+        if let Some(e) = result.err() {
+            return Err(format!("failed to load level {}: {}",self.level_name(&level),e));
+        }
+
+        if self.play_test.as_ref().map(|&(ref room,_)| *room != level).unwrap_or(false) {
+            self.play_test = None;
+        }
+        self.current_level = level;
+        Ok(())
+    }
+
+    /// replace the castles, the current level is replaced by the entry
+    /// if it isn't in the new castles so indices of levels stay valid
+    pub fn set_castles(&mut self, castles: Vec<levels::Castle>) {
+        self.castles = castles;
+        self.play_test = None;
+        if !self.current_level.exists(&self.castles) {
+            self.current_level = levels::Level::Entry;
+        }
+    }
+
+    /// load the room from the tiles of the editor, deaths and resets
+    /// load these tiles again until another level is loaded
+    pub fn play_test(&mut self, level: levels::Level, tiles: Vec<([isize;2],String)>) -> Result<(),String> {
+        self.play_test = Some((level.clone(),tiles));
+        let result = self.goto_level(level);
+        if result.is_err() {
+            self.play_test = None;
+        }
+        result
+    }
+
+    /// load the room from tiles instead of its map
+    pub fn load_room_tiles(&mut self, level: &levels::Level, tiles: &[([isize;2],String)]) -> Result<(),levels::LoadLevelError> {
+        levels::load_room_tiles(level,&self.castles,self.planner.mut_world(),tiles)
+    }
+
+    /// the levels to go back to if a level fails to load:
+    /// the previous level, the corridor of its castle and the entry
+    fn fallbacks(previous: &levels::Level) -> Vec<levels::Level> {
+        let mut fallbacks = vec!(previous.clone());
+        match *previous {
+            levels::Level::Room { castle, .. } => fallbacks.push(levels::Level::Corridor { castle: castle }),
+            levels::Level::Corridor { .. } | levels::Level::Entry => (),
+        }
+        if *previous != levels::Level::Entry {
+            fallbacks.push(levels::Level::Entry);
+        }
+        fallbacks
+    }
+
+    /// load the level or go back to a fallback level if it fails,
+    /// return the error of the level if it has been recovered,
+    /// fail only if no fallback loads
+    pub fn goto_level_or_back(&mut self, level: levels::Level) -> Result<Option<String>,String> {
+        let previous = self.current_level.clone();
+        let error = match self.goto_level(level) {
+            Ok(()) => return Ok(None),
+            Err(e) => e,
+        };
+
+        for fallback in Game::fallbacks(&previous) {
+            if self.goto_level(fallback).is_ok() {
+                return Ok(Some(error));
+            }
+        }
+        Err(error)
+    }
+
+    fn load(&mut self, level: levels::Level, outcome: Outcome, outcomes: &mut Vec<Outcome>) -> Result<(),String> {
+        match try!(self.goto_level_or_back(level)) {
+            None => outcomes.push(outcome),
+            Some(e) => {
+                self.progress.abort();
+                outcomes.push(Outcome::Loaded);
+                outcomes.push(Outcome::Failed(e));
+            },
+        }
+        Ok(())
+    }
+
+    /// the force of the player or none if there is no player
+    pub fn player_force(&mut self) -> (f32,f32) {
+        let world = self.world();
+        let characters = world.read::<PlayerControl>();
+        let forces = world.read::<PhysicForce>();
+        for (_, force) in (&characters, &forces).iter() {
+            return (force.direction,force.intensity);
+        }
+        (0.,0.)
+    }
+
+    pub fn set_player_force(&mut self, direction: f32, intensity: f32) {
+        let world = self.world();
+        let characters = world.read::<PlayerControl>();
+        let mut forces = world.write::<PhysicForce>();
+        for (_, force) in (&characters, &mut forces).iter() {
+            force.direction = direction;
+            force.intensity = intensity;
+        }
+    }
+
+    /// run the systems of the world for dt
+    pub fn dispatch(&mut self, dt: f32) {
+        let context = UpdateContext {
+            dt: dt,
+            effect_tx: self.effect_tx.clone(),
+            control_tx: self.control_tx.clone(),
+        };
+
+        self.planner.dispatch(context);
+        self.planner.wait();
+    }
+
+    /// fail only if neither the level nor its fallbacks load
+    pub fn handle_controls(&mut self) -> Result<Vec<Outcome>,String> {
+        let mut outcomes = vec!();
+        while let Ok(control) = self.control_rx.try_recv() {
+            match control {
+                Control::GotoLevel(level) => {
+                    if self.progress.travel(&self.current_level,&level,&self.castles) {
+                        outcomes.push(Outcome::Progress);
+                    }
+                    try!(self.load(level,Outcome::Loaded,&mut outcomes));
+                },
+                Control::PlayerDied => {
+                    self.progress.death(&self.current_level,&self.castles);
+                    outcomes.push(Outcome::Progress);
+
+                    let level = self.current_level.clone();
+                    try!(self.load(level,Outcome::Reset,&mut outcomes));
+                },
+                Control::ResetLevel => {
+                    let level = self.current_level.clone();
+                    try!(self.load(level,Outcome::Reset,&mut outcomes));
+                }
+                Control::ResetCastle => {
+                    let level = match self.current_level {
+                        levels::Level::Room { castle, dungeon: _, room: _ } => levels::Level::Corridor { castle: castle },
+                        levels::Level::Corridor { castle } => levels::Level::Corridor { castle: castle },
+                        levels::Level::Entry => levels::Level::Entry,
+                    };
+                    try!(self.load(level,Outcome::Reset,&mut outcomes));
+                }
+                Control::ResetGame => {
+                    try!(self.load(levels::Level::Entry,Outcome::Reset,&mut outcomes));
+                }
+                Control::CreateBall(pos,arc) => entities::add_ball(self.world(),pos,arc),
+            }
+        }
+        Ok(outcomes)
+    }
+
+    /// dispatch and handle the controls, dt is not scaled by difficulty
+    pub fn step(&mut self, dt: f32, difficulty: f32) -> Result<Vec<Outcome>,String> {
+        self.dispatch(dt * difficulty);
+        self.progress.update(dt);
+        self.handle_controls()
+    }
+}
+
+#[test]
+fn goto_level_or_back_test() {
+    use audio::NullAudio;
+
+    let (castles,_) = levels::load_castles(vec!()).unwrap();
+    let mut game = Game::new(castles,Progress::new(),Box::new(NullAudio::new()),0).unwrap();
+    game.goto_level(levels::Level::Corridor { castle: 0 }).unwrap();
+
+    let missing = levels::Level::Room { castle: 0, dungeon: 999, room: 0 };
+    assert!(game.goto_level_or_back(missing).unwrap().is_some());
+    assert_eq!(game.current_level,levels::Level::Corridor { castle: 0 });
+}
+
+#[test]
+fn play_test_test() {
+    use audio::NullAudio;
+
+    let (castles,_) = levels::load_castles(vec!()).unwrap();
+    let mut game = Game::new(castles,Progress::new(),Box::new(NullAudio::new()),0).unwrap();
+    let level = levels::Level::Room { castle: 0, dungeon: 0, room: 0 };
+    let walls = |game: &mut Game| (&game.world().read::<PhysicStatic>()).iter().count();
+
+    let tiles = vec!(([0,0],String::from("character")),([2,0],String::from("wall")));
+    game.play_test(level.clone(),tiles).unwrap();
+    assert_eq!(walls(&mut game),1);
+
+    // the reset loads the tiles of the editor, not the map of the room
+    game.send(Control::ResetLevel);
+    assert_eq!(game.handle_controls().unwrap(),vec!(Outcome::Reset));
+    assert_eq!(walls(&mut game),1);
+
+    game.goto_level(levels::Level::Entry).unwrap();
+    game.goto_level(level).unwrap();
+    assert!(walls(&mut game) != 1);
+}
+
+#[test]
+fn set_castles_test() {
+    use audio::NullAudio;
+
+    let (castles,_) = levels::load_castles(vec!()).unwrap();
+    let mut game = Game::new(castles,Progress::new(),Box::new(NullAudio::new()),0).unwrap();
+    game.goto_level(levels::Level::Corridor { castle: 0 }).unwrap();
+
+    game.set_castles(levels::load_castles(vec!()).unwrap().0);
+    assert_eq!(game.current_level,levels::Level::Corridor { castle: 0 });
+
+    game.set_castles(vec!());
+    assert_eq!(game.current_level,levels::Level::Entry);
+}
+
+#[test]
+fn move_static_test() {
+    use audio::NullAudio;
+    use resource::PhysicWorld;
+
+    let (castles,_) = levels::load_castles(vec!()).unwrap();
+    let mut game = Game::new(castles,Progress::new(),Box::new(NullAudio::new()),0).unwrap();
+    let level = levels::Level::Room { castle: 0, dungeon: 0, room: 0 };
+    let tiles = vec!(([0,0],String::from("character")),([4,0],String::from("wall")));
+    game.play_test(level,tiles).unwrap();
+
+    let (wall,from) = {
+        let world = game.world();
+        let statics = world.read::<PhysicStatic>();
+        let states = world.read::<PhysicState>();
+        let entities = world.entities();
+        let (_,state,entity) = (&statics, &states, &entities).iter().next().unwrap();
+        (entity,state.position)
+    };
+    let to = [from[0], from[1]+10.];
+    game.world().write::<PhysicState>().get_mut(wall).unwrap().set_position(to);
+    game.dispatch(1./60.);
+
+    let touched = |game: &mut Game, pos: [f32;2]| {
+        let mut entities = vec!();
+        game.world().read_resource::<PhysicWorld>()
+            .apply_on_shape(&pos, !0, &Shape::Square(0.1), &mut |entity,_| entities.push(*entity));
+        entities
+    };
+    assert_eq!(touched(&mut game,from),vec!());
+    assert_eq!(touched(&mut game,to),vec!(wall));
+}
@@ -0,0 +1,124 @@
+//! simulation of the game without window nor audio device,
+//! the game runs at the fixed update rate of the event loop
+
+use audio::NullAudio;
+use config;
+use game::{ Game, Outcome };
+use levels::{ Castle, Level };
+use progress::Progress;
+use replay::{ Recording, Replay };
+use solver;
+
+/// what happened to a room simulated without input
+#[derive(Debug,Clone,PartialEq)]
+pub enum RoomReport {
+    LoadError(String),
+    Died,
+    Left,
+    Ok,
+}
+
+/// every room of every castle
+fn rooms(castles: &Vec<Castle>) -> Vec<Level> {
+    let mut levels = vec!();
+    for (c,castle) in castles.iter().enumerate() {
+        for (d,dungeon) in castle.dungeons.iter().enumerate() {
+            for r in 0..dungeon.rooms.len() {
+                levels.push(Level::Room { castle: c, dungeon: d, room: r });
+            }
+        }
+    }
+    levels
+}
+
+/// step the room for the number of updates without input
+pub fn simulate_room(game: &mut Game, level: Level, updates: usize) -> RoomReport {
+    let config = config.load();
+    let dt = 1. / config.event_loop.ups as f32;
+
+    if let Err(e) = game.goto_level(level) {
+        return RoomReport::LoadError(e);
+    }
+
+    for _ in 0..updates {
+        match game.step(dt,1.) {
+            Ok(outcomes) => {
+                if let Some(e) = outcomes.iter().filter_map(|o| if let Outcome::Failed(ref e) = *o { Some(e.clone()) } else { None }).next() {
+                    return RoomReport::LoadError(e);
+                }
+                if outcomes.contains(&Outcome::Reset) {
+                    return RoomReport::Died;
+                }
+                if outcomes.contains(&Outcome::Loaded) {
+                    return RoomReport::Left;
+                }
+            },
+            Err(e) => return RoomReport::LoadError(e),
+        }
+    }
+    RoomReport::Ok
+}
+
+/// simulate every room, return whether all rooms loaded
+pub fn run_rooms(castles: Vec<Castle>, updates: usize) -> Result<bool,String> {
+    let levels = rooms(&castles);
+    let mut game = try!(Game::new(castles,Progress::new(),Box::new(NullAudio::new()),0));
+    let mut success = true;
+    for level in levels {
+        let name = game.level_name(&level);
+        match simulate_room(&mut game,level,updates) {
+            RoomReport::LoadError(e) => {
+                success = false;
+                println!("{}: load error: {}",name,e);
+            },
+            RoomReport::Died => println!("{}: died",name),
+            RoomReport::Left => println!("{}: left",name),
+            RoomReport::Ok => println!("{}: ok",name),
+        }
+    }
+    Ok(success)
+}
+
+/// run the solver bot on every room, return whether all rooms are solved
+pub fn run_solver(castles: Vec<Castle>) -> Result<bool,String> {
+    let levels = rooms(&castles);
+    let mut game = try!(Game::new(castles,Progress::new(),Box::new(NullAudio::new()),0));
+    let mut success = true;
+    for level in levels {
+        let verdict = solver::solve_level(&mut game,&level);
+        success &= verdict.is_solved();
+        println!("{}: {}",game.level_name(&level),verdict);
+    }
+    Ok(success)
+}
+
+/// replay the recording, at most for the number of updates if any
+pub fn run_replay(castles: Vec<Castle>, recording: &Recording, updates: Option<usize>) -> Result<(),String> {
+    let mut game = try!(Game::new(castles,Progress::new(),Box::new(NullAudio::new()),recording.seed));
+    game.reseed(recording.seed);
+    try!(game.goto_level(recording.level.clone()));
+
+    let mut replay = Replay::new(recording);
+    let mut count = 0;
+    while updates.map(|updates| count < updates).unwrap_or(true) {
+        let (direction,intensity) = match replay.next_force() {
+            Some(force) => force,
+            None => break,
+        };
+        game.set_player_force(direction,intensity);
+        try!(game.step(replay.dt as f32,replay.difficulty));
+        count += 1;
+    }
+
+    let level = game.current_level.clone();
+    println!("replay finished after {} updates in {}",count,game.level_name(&level));
+    Ok(())
+}
+
+#[test]
+fn headless_test() {
+    use levels;
+
+    let (castles,_) = levels::load_castles(vec!()).unwrap();
+    assert!(run_rooms(castles,10).unwrap());
+}
@@ -0,0 +1,81 @@
+//! watch the files of castles and of the current room
+//! so they can be reloaded while playing
+
+use config;
+use configuration::FileWatcher;
+use levels::{ self, Castle, Level };
+use std::path::Path;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Change {
+    /// a castle has been added, removed or its config.toml modified
+    Castles,
+    /// the map or text of the current room has been modified
+    Room,
+}
+
+pub struct LevelWatcher {
+    castles: Vec<FileWatcher>,
+    room: Vec<FileWatcher>,
+    level: Option<Level>,
+}
+
+/// whether any file changed, every watcher is updated
+fn changed(watchers: &mut Vec<FileWatcher>) -> bool {
+    watchers.iter_mut().fold(false, |changed, watcher| watcher.changed() || changed)
+}
+
+impl LevelWatcher {
+    pub fn new(castles: &Vec<Castle>) -> Self {
+        let mut watcher = LevelWatcher {
+            castles: vec!(),
+            room: vec!(),
+            level: None,
+        };
+        watcher.watch_castles(castles);
+        watcher
+    }
+
+    /// watch the levels directory and the config.toml of castles
+    pub fn watch_castles(&mut self, castles: &Vec<Castle>) {
+        let config = config.load();
+        let dir = Path::new(&*config.levels.dir.val);
+        self.castles = vec!(FileWatcher::new(dir));
+        for castle in castles {
+            self.castles.push(FileWatcher::new(dir.join(&*castle.name).join("config.toml")));
+        }
+        self.level = None;
+    }
+
+    fn watch_room(&mut self, level: &Level, castles: &Vec<Castle>) {
+        self.room.clear();
+        if let Level::Room { castle, dungeon, room } = *level {
+            // the room may have been removed from castles
+            if let Some(castle) = castles.get(castle) {
+                if let Some(room) = castle.dungeons.get(dungeon).and_then(|d| d.rooms.get(room)) {
+                    self.room.push(FileWatcher::new(levels::room_text_path(castle,room)));
+                    self.room.push(FileWatcher::new(levels::room_map_path(castle,room)));
+                }
+            }
+        }
+        self.level = Some(level.clone());
+    }
+
+    /// the change since the last poll, a change of castles hides a change of room
+    pub fn poll(&mut self, level: &Level, castles: &Vec<Castle>) -> Option<Change> {
+        if self.level.as_ref() != Some(level) {
+            self.watch_room(level,castles);
+        }
+
+        let castles_changed = changed(&mut self.castles);
+        let room_changed = changed(&mut self.room);
+
+        if castles_changed {
+            Some(Change::Castles)
+        } else if room_changed {
+            Some(Change::Room)
+        } else {
+            None
+        }
+    }
+}
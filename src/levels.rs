@@ -5,16 +5,29 @@ use config;
 use std::path::Path;
 use std::path::PathBuf;
 use specs::Join;
-use physic;
+use physic::{ self, IntoGrid };
+use pathfinding;
+use tiled;
 use toml;
-use baal;
 use configuration;
 use std::fs;
 use std::fmt;
 use std::io::Read;
-use configuration::FromToml;
+use configuration::{ ConfigError, FromToml, TomlError };
 use std::io;
 use png;
+use prefab::Prefabs;
+use progress::Progress;
+use std::collections::{ BTreeMap, HashMap };
+
+pub type Colors = HashMap<[u8;3],String>;
+pub type Characters = HashMap<char,String>;
+type ColorTable = BTreeMap<String,[u8;3]>;
+type CharacterTable = BTreeMap<String,String>;
+type ExitTable = BTreeMap<String,BTreeMap<String,String>>;
+
+/// destinations of the tagged portals of a room
+pub type Exits = BTreeMap<String,Level>;
 
 type VecDungeonSetting = Vec<DungeonSetting>;
 type VecString = Vec<String>;
@@ -41,11 +54,11 @@ pub enum LoadCastlesError {
     IoError(io::Error),
     FileNameInvalidUTF8,
     FileContentInvalidUTF8,
-    TomlError(Vec<toml::ParserError>),
-    InvalidTomlValue(String),
+    /// error of the config.toml of a castle
+    Config(ConfigError),
     UnexpectedFile,
-    OpenConfigError(io::Error),
     ReadDirError(io::Error),
+    PaletteError(String),
 }
 
 impl fmt::Display for LoadCastlesError {
@@ -55,17 +68,10 @@ impl fmt::Display for LoadCastlesError {
             IoError(ref e) => write!(fmt,"io error: {}",e),
             FileNameInvalidUTF8 => write!(fmt,"file name is invalid utf-8"),
             FileContentInvalidUTF8 => write!(fmt,"file content is invalid utf-8"),
-            TomlError(ref error_vec) => {
-                try!(write!(fmt,"toml errors"));
-                for e in error_vec {
-                    try!(write!(fmt,"\n\t[{},{}] {}",e.lo,e.hi,e.desc));
-                }
-                write!(fmt,"")
-            },
-            InvalidTomlValue(ref e) => write!(fmt,"invalid toml value: {}",e),
+            Config(ref e) => write!(fmt,"{}",e),
             UnexpectedFile => write!(fmt,"unexpected file in levels root directory"),
-            OpenConfigError(ref e) => write!(fmt,"open config failed: {}",e),
             ReadDirError(ref e) => write!(fmt,"read dir failed: {}",e),
+            PaletteError(ref e) => write!(fmt,"palette invalid: {}",e),
         }
     }
 }
@@ -81,6 +87,7 @@ impl From<io::Error> for LoadCastlesError {
 /// the order of music name in the vector correspond to the music id
 /// in the castles definitions
 pub fn load_castles(mut musics: Vec<String>) -> Result<(Vec<Castle>,Vec<String>),LoadCastlesError> {
+    let config = config.load();
     let mut castles = Vec::new();
 
     for dir_entry in try!(fs::read_dir(config.levels.dir.val.clone()).map_err(|e| LoadCastlesError::ReadDirError(e))) {
@@ -91,19 +98,83 @@ pub fn load_castles(mut musics: Vec<String>) -> Result<(Vec<Castle>,Vec<String>)
         if !try!(dir_entry.file_type()).is_dir() {
             return Err(LoadCastlesError::UnexpectedFile);
         }
-        let mut file = try!(fs::File::open(dir_entry.path().join(String::from("config.toml"))).
-                        map_err(|e| LoadCastlesError::OpenConfigError(e)));
+        let file = dir_entry.path().join("config.toml").to_string_lossy().into_owned();
+        let value_error = |e: TomlError| LoadCastlesError::Config(ConfigError::value(&*file,e));
 
-        let mut file_string = String::new();
+        let mut toml_table = try!(configuration::read_table(&*file).map_err(LoadCastlesError::Config));
 
-        try!(file.read_to_string(&mut file_string)
-             .map_err(|_| LoadCastlesError::FileContentInvalidUTF8));
+        let prefabs = match toml_table.remove("prefabs") {
+            Some(value) => try!(Prefabs::from_toml(&value)
+                .map_err(|e| value_error(e.key("prefabs")))),
+            None => Prefabs::new(),
+        };
 
-        let mut file_parser = toml::Parser::new(&*file_string);
-        let toml_table = try!(file_parser.parse() .ok_or(LoadCastlesError::TomlError(file_parser.errors)));
+        // colors of maps: default colors overridden by the palette
+        // overridden by the colors table
+        let mut colors = default_colors();
+        if let Some(value) = toml_table.remove("palette") {
+            let palette = try!(String::from_toml(&value)
+                .map_err(|e| value_error(e.key("palette"))));
+            let palette_path = dir_entry.path().join("maps").join(palette);
+
+            let mut palette_string = String::new();
+            try!(try!(fs::File::open(&palette_path)).read_to_string(&mut palette_string)
+                 .map_err(|_| LoadCastlesError::FileContentInvalidUTF8));
+
+            for (color,name) in try!(parse_palette(&*palette_string)
+                                     .map_err(|e| LoadCastlesError::PaletteError(format!("{}: {}",palette_path.display(),e)))) {
+                colors.insert(color,name);
+            }
+        }
+        if let Some(value) = toml_table.remove("colors") {
+            let color_table = try!(ColorTable::from_toml(&value)
+                .map_err(|e| value_error(e.key("colors"))));
+            for (name,color) in color_table {
+                colors.insert(color,name);
+            }
+        }
+
+        // characters of ascii maps: default characters overridden by
+        // the characters table
+        let mut characters = default_characters();
+        if let Some(value) = toml_table.remove("characters") {
+            let character_table = try!(CharacterTable::from_toml(&value)
+                .map_err(|e| value_error(e.key("characters"))));
+            for (name,chars) in character_table {
+                for character in chars.chars() {
+                    characters.insert(character,name.clone());
+                }
+            }
+        }
+
+        // exits of dungeons: room name to exit name to destination room
+        // and requirements of dungeons: names of dungeons to complete before
+        let mut exit_tables = vec!();
+        let mut requirements = vec!();
+        if let Some(&mut toml::Value::Array(ref mut dungeons)) = toml_table.get_mut("dungeons") {
+            for (i,dungeon) in dungeons.iter_mut().enumerate() {
+                let (exit_table,requires) = match *dungeon {
+                    toml::Value::Table(ref mut dungeon) => (
+                        match dungeon.remove("exits") {
+                            Some(value) => try!(ExitTable::from_toml(&value)
+                                .map_err(|e| value_error(e.key("exits").index(i).key("dungeons"))))),
+                            None => ExitTable::new(),
+                        },
+                        match dungeon.remove("requires") {
+                            Some(value) => try!(VecString::from_toml(&value)
+                                .map_err(|e| value_error(e.key("requires").index(i).key("dungeons"))))),
+                            None => VecString::new(),
+                        },
+                    ),
+                    _ => (ExitTable::new(),VecString::new()),
+                };
+                exit_tables.push(exit_table);
+                requirements.push(requires);
+            }
+        }
 
         let castle_setting = try!(CastleSetting::from_toml(&toml::Value::Table(toml_table))
-            .map_err(|e| LoadCastlesError::InvalidTomlValue(e)));
+            .map_err(&value_error));
 
         let castle_music = PathBuf::new()
             .join(Path::new(&*config.levels.dir.val))
@@ -120,9 +191,14 @@ pub fn load_castles(mut musics: Vec<String>) -> Result<(Vec<Castle>,Vec<String>)
             music: musics.len()-1,
             name: castle_name,
             dungeons: vec!(),
+            prefabs: prefabs,
+            colors: colors,
+            characters: characters,
         };
 
-        for dungeon in castle_setting.dungeons {
+        let castle_id = castles.len();
+        let dungeon_names = castle_setting.dungeons.iter().map(|d| d.name.clone()).collect::<Vec<_>>();
+        for (dungeon_id,((dungeon,exit_table),requires)) in castle_setting.dungeons.into_iter().zip(exit_tables).zip(requirements).enumerate() {
             let dungeon_music = PathBuf::new()
                 .join(Path::new(&*config.levels.dir.val))
                 .join(Path::new(&*castle.name))
@@ -138,10 +214,23 @@ pub fn load_castles(mut musics: Vec<String>) -> Result<(Vec<Castle>,Vec<String>)
                 musics.push(dungeon_music);
                 musics.len()-1
             };
+
+            let exits = try!(dungeon_exits(castle_id,dungeon_id,&dungeon.rooms,exit_table)
+                .map_err(|e| value_error(e.key("exits").index(dungeon_id).key("dungeons"))));
+
+            let mut required_dungeons = vec!();
+            for name in requires {
+                required_dungeons.push(try!(dungeon_names.iter().position(|n| *n == name)
+                    .ok_or_else(|| value_error(TomlError::invalid(format!("unknown dungeon {}",name))
+                        .key("requires").index(dungeon_id).key("dungeons")))));
+            }
+
             castle.dungeons.push(Dungeon {
                 music: index,
                 name: dungeon.name,
                 rooms: dungeon.rooms,
+                exits: exits,
+                requires: required_dungeons,
             });
         }
 
@@ -151,8 +240,114 @@ pub fn load_castles(mut musics: Vec<String>) -> Result<(Vec<Castle>,Vec<String>)
     Ok((castles,musics))
 }
 
+/// the exits of each room of the dungeon,
+/// exits are keyed by room name so a room with exits can't be repeated
+fn dungeon_exits(castle_id: usize, dungeon_id: usize, rooms: &[String], exit_table: ExitTable) -> Result<Vec<Exits>,TomlError> {
+    let mut exits = vec![Exits::new(); rooms.len()];
+    for (room,exit_destinations) in exit_table {
+        let room_id = match rooms.iter().filter(|r| **r == room).count() {
+            0 => return Err(TomlError::invalid(format!("unknown room {}",room)).key(&*room)),
+            1 => rooms.iter().position(|r| *r == room).unwrap(),
+            _ => return Err(TomlError::invalid(format!("room {} has exits but appears several times in the dungeon",room)).key(&*room)),
+        };
+        for (exit,destination) in exit_destinations {
+            let destination = try!(room_destination(castle_id,dungeon_id,rooms,&*destination)
+                .ok_or_else(|| TomlError::invalid(format!("unknown room {}",destination)).key(&*exit).key(&*room)));
+            exits[room_id].insert(exit,destination);
+        }
+    }
+    Ok(exits)
+}
 
-#[derive(Debug,Clone)]
+#[test]
+fn dungeon_exits_test() {
+    let rooms = vec!(String::from("a.png"),String::from("b.png"),String::from("a.png"));
+    let table = |room: &str, destination: &str| {
+        let mut exits = BTreeMap::new();
+        exits.insert(String::from("left"),String::from(destination));
+        let mut table = ExitTable::new();
+        table.insert(String::from(room),exits);
+        table
+    };
+
+    let exits = dungeon_exits(0,0,&rooms,table("b.png","a.png")).unwrap();
+    assert_eq!(exits[1].get("left"),Some(&Level::Room { castle: 0, dungeon: 0, room: 0 }));
+    assert!(exits[0].is_empty() && exits[2].is_empty());
+
+    assert_eq!(dungeon_exits(0,0,&rooms,table("a.png","b.png")).unwrap_err().path,"a.png");
+    assert_eq!(dungeon_exits(0,0,&rooms,table("c.png","b.png")).unwrap_err().path,"c.png");
+    assert_eq!(dungeon_exits(0,0,&rooms,table("b.png","c.png")).unwrap_err().path,"b.png.left");
+}
+
+fn default_colors() -> Colors {
+    let config = config.load();
+    let mut colors = Colors::new();
+    colors.insert(config.levels.empty_col,"empty".into());
+    colors.insert(config.levels.char_col,"character".into());
+    colors.insert(config.levels.portal_col,"portal".into());
+    colors.insert(config.levels.laser_col,"laser".into());
+    colors.insert(config.levels.monster_col,"monster".into());
+    colors.insert(config.levels.column_col,"column".into());
+    colors.insert(config.levels.wall_col,"wall".into());
+    colors
+}
+
+fn default_characters() -> Characters {
+    let config = config.load();
+    let mut characters = Characters::new();
+    for (name,chars) in config.levels.characters.iter() {
+        for character in chars.chars() {
+            characters.insert(character,name.clone());
+        }
+    }
+    characters
+}
+
+/// parse a gimp palette, the name of each color is the name
+/// of the entity it defines
+fn parse_palette(palette: &str) -> Result<Vec<([u8;3],String)>,String> {
+    let mut lines = palette.lines().enumerate();
+
+    match lines.next() {
+        Some((_,line)) if line.trim() == "GIMP Palette" => (),
+        _ => return Err("expect \"GIMP Palette\" header".into()),
+    }
+
+    let mut colors = vec!();
+    for (i,line) in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("Name:") || line.starts_with("Columns:") {
+            continue;
+        }
+
+        let mut words = line.split_whitespace();
+        let mut color = [0u8;3];
+        for channel in color.iter_mut() {
+            *channel = try!(words.next()
+                            .and_then(|w| w.parse::<u8>().ok())
+                            .ok_or(format!("line {}: expect three values between 0 and 255",i+1)));
+        }
+        let name = words.collect::<Vec<&str>>().join(" ");
+        if name.is_empty() {
+            return Err(format!("line {}: expect color name",i+1));
+        }
+        colors.push((color,name));
+    }
+    Ok(colors)
+}
+
+#[test]
+fn parse_palette_test() {
+    let palette = "GIMP Palette\nName: palette\nColumns: 16\n#\n  0  43  54\tempty\n147 161 161\tbig wall\n";
+    assert_eq!(parse_palette(palette),Ok(vec!(([0,43,54],"empty".into()),([147,161,161],"big wall".into()))));
+
+    assert!(parse_palette("Name: palette\n").is_err());
+    assert!(parse_palette("GIMP Palette\n0 256 0\twall\n").is_err());
+    assert!(parse_palette("GIMP Palette\n0 0 0\n").is_err());
+}
+
+
+#[derive(Debug,Clone,PartialEq)]
 pub enum Level {
     Room {
         castle: usize,
@@ -165,12 +360,28 @@ pub enum Level {
     Entry,
 }
 impl Level {
+    /// whether the castles contain the level
+    pub fn exists(&self, castles: &Vec<Castle>) -> bool {
+        match *self {
+            Level::Room { castle, dungeon, room } => castles.get(castle)
+                .and_then(|c| c.dungeons.get(dungeon))
+                .map(|d| room < d.rooms.len())
+                .unwrap_or(false),
+            Level::Corridor { castle } => castle < castles.len(),
+            Level::Entry => true,
+        }
+    }
+
     fn next(&self, castles: &Vec<Castle>) -> Self {
         if let Level::Room { castle: castle_id, dungeon: dungeon_id, room: room_id } = *self {
             let dungeon = castles.get(castle_id).expect("INTERN ERROR: false castle").dungeons.get(dungeon_id).expect("INTERN ERROR: false dungeon)");
 
             if room_id >= dungeon.rooms.len() { panic!("INTERN ERROR: false room") }
 
+            if let Some(next) = dungeon.exits[room_id].get("next") {
+                return next.clone();
+            }
+
             if room_id + 1 == dungeon.rooms.len() {
                 Level::Corridor { castle: castle_id }
             } else {
@@ -184,6 +395,32 @@ impl Level {
             panic!("INTERN ERROR: cannot call next on entry dungeon");
         }
     }
+    /// the destination of the portals of the room tagged with exit
+    fn exit(&self, castles: &Vec<Castle>, exit: &str) -> Result<Self,LoadLevelError> {
+        if let Level::Room { castle: castle_id, dungeon: dungeon_id, room: room_id } = *self {
+            castles.get(castle_id)
+                .and_then(|castle| castle.dungeons.get(dungeon_id))
+                .and_then(|dungeon| dungeon.exits.get(room_id))
+                .and_then(|exits| exits.get(exit))
+                .cloned()
+                .ok_or(LoadLevelError::UnknownExit(exit.into()))
+        } else {
+            Err(LoadLevelError::UnknownExit(exit.into()))
+        }
+    }
+}
+
+/// the level named by a destination in a dungeon:
+/// a room of the dungeon or "corridor" for the corridor of the castle
+fn room_destination(castle: usize, dungeon: usize, rooms: &[String], name: &str) -> Option<Level> {
+    if name == "corridor" {
+        return Some(Level::Corridor { castle: castle });
+    }
+    rooms.iter().position(|r| r == name).map(|room| Level::Room {
+        castle: castle,
+        dungeon: dungeon,
+        room: room,
+    })
 }
 
 #[derive(Debug)]
@@ -191,18 +428,29 @@ pub struct Castle {
     pub name: String,
     pub music: usize,
     pub dungeons: Vec<Dungeon>,
+    pub prefabs: Prefabs,
+    pub colors: Colors,
+    pub characters: Characters,
+}
+impl Castle {
+    /// the color that defines the entity in png maps,
+    /// the smallest one if many colors define it
+    pub fn color_of(&self, name: &str) -> Option<[u8;3]> {
+        self.colors.iter()
+            .filter(|&(_,n)| n == name)
+            .map(|(color,_)| *color)
+            .min()
+    }
 }
 #[derive(Debug,Clone)]
 pub struct Dungeon {
     pub name: String,
     pub music: usize,
     pub rooms: Vec<String>,
+    pub exits: Vec<Exits>,
+    /// dungeons of the castle to complete before entering
+    pub requires: Vec<usize>,
 }
-impl_from_into_toml_for_struct!( Dungeon {
-    name: String,
-    music: usize,
-    rooms: VecString,
-});
 
 #[derive(Debug)]
 pub enum LoadLevelError {
@@ -213,7 +461,23 @@ pub enum LoadLevelError {
     NoLevelDefinition,
     InvalidUTF8,
     PngDecodingError(png::DecodingError),
-    UnexpectedColor,
+    UnexpectedColor {
+        x: u32,
+        y: u32,
+        color: [u8;3],
+    },
+    UnexpectedCharacter {
+        line: usize,
+        column: usize,
+        character: char,
+    },
+    UnknownPrefab(String),
+    UnknownExit(String),
+    TiledError {
+        file: PathBuf,
+        object: Option<u64>,
+        description: String,
+    },
     IoError(io::Error),
 }
 impl fmt::Display for LoadLevelError {
@@ -223,11 +487,16 @@ impl fmt::Display for LoadLevelError {
             GetCastleError => write!(fmt,"castle id out of bounds"),
             GetDungeonError => write!(fmt,"dungeon id out of bounds"),
             GetRoomError => write!(fmt,"room id out of bounds"),
-            UnexpectedColor => write!(fmt,"unexpected color in png file"),
+            UnexpectedColor { x, y, color } => write!(fmt,"unexpected color [{},{},{}] at pixel ({},{}) in png file",color[0],color[1],color[2],x,y),
+            UnexpectedCharacter { line, column, character } => write!(fmt,"unexpected character {:?} at line {} column {} in map file",character,line,column),
+            UnknownExit(ref name) => write!(fmt,"unknown exit of portal: {}",name),
+            UnknownPrefab(ref name) => write!(fmt,"unknown prefab: {}",name),
+            TiledError { ref file, object: Some(object), ref description } => write!(fmt,"tiled map {} object {}: {}",file.display(),object,description),
+            TiledError { ref file, object: None, ref description } => write!(fmt,"tiled map {}: {}",file.display(),description),
             IoError(ref e) => write!(fmt,"io error: {}",e),
             PngDecodingError(ref e) => write!(fmt,"png decoding error: {}",e),
-            AmbiguousLevelDefinition => write!(fmt,"ambiguous level definition: both .txt and .png file exists"),
-            InvalidUTF8 => write!(fmt,"text level invalid UTF-8"),
+            AmbiguousLevelDefinition => write!(fmt,"ambiguous level definition: both text and map file exists"),
+            InvalidUTF8 => write!(fmt,"text or map level invalid UTF-8"),
             NoLevelDefinition => write!(fmt,"level doesn't exist"),
         }
     }
@@ -239,17 +508,133 @@ impl From<io::Error> for LoadLevelError {
     }
 }
 
-pub fn load_level<'l>(level: &Level, castles: &Vec<Castle>, world: &mut specs::World) -> Result<(),LoadLevelError> {
-    // flush world
+/// path of the text file of a room
+pub fn room_text_path(castle: &Castle, room: &str) -> PathBuf {
+    let config = config.load();
+    PathBuf::new()
+        .join(Path::new(&*config.levels.dir.val))
+        .join(Path::new(&*castle.name))
+        .join(Path::new("texts"))
+        .join(Path::new(room))
+}
+
+/// path of the map file of a room
+pub fn room_map_path(castle: &Castle, room: &str) -> PathBuf {
+    let config = config.load();
+    PathBuf::new()
+        .join(Path::new(&*config.levels.dir.val))
+        .join(Path::new(&*castle.name))
+        .join(Path::new("maps"))
+        .join(Path::new(room))
+}
+
+/// decode a png map into the entities it defines,
+/// -y because opengl and image editor are usually inverted
+pub fn read_png_map(path: &Path, castle: &Castle) -> Result<Vec<([isize;2],String)>,LoadLevelError> {
+    let decoder = png::Decoder::new(try!(fs::File::open(path)));
+    let (info,mut reader) = try!(decoder.read_info().map_err(|e| LoadLevelError::PngDecodingError(e)));
+    let mut data = vec![0; 3 * info.width as usize * info.height as usize];
+    try!(reader.next_frame(&mut data).map_err(|e| LoadLevelError::PngDecodingError(e)));
+
+    let mut tiles = vec!();
+    for x in 0..info.width {
+        for y in 0..info.height {
+            let offset = ((x + y*info.width)*3) as usize;
+            let col = [data[offset],data[offset+1],data[offset+2]];
+
+            let name = try!(castle.colors.get(&col).ok_or(LoadLevelError::UnexpectedColor {
+                x: x,
+                y: y,
+                color: col,
+            }));
+
+            tiles.push(([x as isize,-(y as isize)],name.clone()));
+        }
+    }
+    Ok(tiles)
+}
+
+fn flush_world(world: &mut specs::World) {
+    let config = config.load();
     for entity in world.entities().iter() {
         world.delete_later(entity);
     }
     world.maintain();
 
-    baal::effect::short::stop_all();
-    baal::effect::persistent::clear_positions_for_all();
-    baal::effect::persistent::update_volume_for_all();
-    baal::effect::short::play(config.entities.portal_snd, baal::effect::listener());
+    let mut audio = world.write_resource::<resource::Audio>();
+    audio.stop_all_effects();
+    audio.clear_persistent_positions_for_all();
+    audio.update_persistent_volume_for_all();
+    audio.play_effect_on_listener(config.entities.portal_snd);
+}
+
+/// play the music unless it is already playing
+fn play_music(world: &mut specs::World, music: usize) {
+    let mut audio = world.write_resource::<resource::Audio>();
+    if let Some(current) = audio.music_index() {
+        if current != music {
+            audio.play_music(music);
+        }
+    }
+}
+
+/// parse an ascii map into the entities it defines,
+/// -y because the first line is the top of the map
+pub fn read_text_map(map: &str, castle: &Castle) -> Result<Vec<([isize;2],String)>,LoadLevelError> {
+    let mut tiles = vec!();
+    for (y,line) in map.lines().enumerate() {
+        for (x,character) in line.chars().enumerate() {
+            let name = try!(castle.characters.get(&character).ok_or(LoadLevelError::UnexpectedCharacter {
+                line: y+1,
+                column: x+1,
+                character: character,
+            }));
+
+            tiles.push(([x as isize,-(y as isize)],name.clone()));
+        }
+    }
+    Ok(tiles)
+}
+
+#[test]
+fn text_and_png_map_test() {
+    use png::HasParameters;
+
+    let castle = Castle {
+        name: "test".into(),
+        music: 0,
+        dungeons: vec!(),
+        prefabs: Prefabs::new(),
+        colors: default_colors(),
+        characters: default_characters(),
+    };
+
+    let path = ::std::env::temp_dir().join(format!("ruga_map_test_{}.png",::std::process::id()));
+    {
+        let mut data = vec!();
+        for name in &["wall","character","empty","monster"] {
+            data.extend_from_slice(&castle.color_of(name).unwrap());
+        }
+        let file = fs::File::create(&path).unwrap();
+        let mut encoder = png::Encoder::new(io::BufWriter::new(file),2,2);
+        encoder.set(png::ColorType::RGB).set(png::BitDepth::Eight);
+        encoder.write_header().unwrap().write_image_data(&data).unwrap();
+    }
+
+    let mut png_tiles = read_png_map(&path,&castle).unwrap();
+    fs::remove_file(&path).unwrap();
+    let mut text_tiles = read_text_map("#@\n.M\n",&castle).unwrap();
+    png_tiles.sort();
+    text_tiles.sort();
+    assert_eq!(text_tiles,png_tiles);
+    assert_eq!(text_tiles.len(),4);
+
+    assert!(read_text_map("#?",&castle).is_err());
+}
+
+pub fn load_level<'l>(level: &Level, castles: &Vec<Castle>, progress: &Progress, world: &mut specs::World) -> Result<(),LoadLevelError> {
+    let config = config.load();
+    flush_world(world);
 
     // read level file
     match level {
@@ -257,27 +642,14 @@ pub fn load_level<'l>(level: &Level, castles: &Vec<Castle>, world: &mut specs::W
             let castle = try!(castles.get(castle_id).ok_or(LoadLevelError::GetCastleError));
             let dungeon = try!(castle.dungeons.get(dungeon_id).ok_or(LoadLevelError::GetDungeonError));
 
-            if let Some(music) = baal::music::index() {
-                if music != dungeon.music {
-                    baal::music::play(dungeon.music);
-                }
-            }
+            play_music(world,dungeon.music);
 
             let room = try!(dungeon.rooms.get(room_id).ok_or(LoadLevelError::GetRoomError));
 
-            let txt_path = PathBuf::new()
-                .join(Path::new(&*config.levels.dir.val))
-                .join(Path::new(&*castle.name))
-                .join(Path::new("texts"))
-                .join(Path::new(&*room));
-
-            let png_path = PathBuf::new()
-                .join(Path::new(&*config.levels.dir.val))
-                .join(Path::new(&*castle.name))
-                .join(Path::new("maps"))
-                .join(Path::new(&*room));
+            let txt_path = room_text_path(castle,room);
+            let map_path = room_map_path(castle,room);
 
-            match (txt_path.exists(),png_path.exists()) {
+            match (txt_path.exists(),map_path.exists()) {
                 (true,true) => return Err(LoadLevelError::AmbiguousLevelDefinition),
                 (false,false) => return Err(LoadLevelError::NoLevelDefinition),
                 (true,false) => {
@@ -287,35 +659,50 @@ pub fn load_level<'l>(level: &Level, castles: &Vec<Castle>, world: &mut specs::W
                     create_text_level(level.next(castles),text,world);
                 },
                 (false,true) => {
-                    let decoder = png::Decoder::new(try!(fs::File::open(png_path)));
-                    let (info,mut reader) = try!(decoder.read_info().map_err(|e| LoadLevelError::PngDecodingError(e)));
-                    let mut data = vec![0; 3 * info.width as usize * info.height as usize];
-                    try!(reader.next_frame(&mut data).map_err(|e| LoadLevelError::PngDecodingError(e)));
-
-                    for x in 0..info.width {
-                        for y in 0..info.height {
-                            let offset = ((x + y*info.width)*3) as usize;
-                            let col = [data[offset],data[offset+1],data[offset+2]];
-                            // -y because opengl and image editor are usually inverted
-                            let pos = [x as isize,-(y as isize)];
-
-                            if col == config.levels.empty_col {
-                            } else if col == config.levels.char_col {
-                                entities::add_character(world,pos);
-                            } else if col == config.levels.portal_col {
-                                entities::add_portal(world,pos,level.next(castles));
-                            } else if col == config.levels.laser_col {
-                                entities::add_laser(world,pos);
-                            } else if col == config.levels.monster_col {
-                                entities::add_monster(world,pos);
-                            } else if col == config.levels.column_col {
-                                entities::add_column(world,pos);
-                            } else if col == config.levels.wall_col {
-                                entities::add_wall(world,pos);
-                            } else {
-                                return Err(LoadLevelError::UnexpectedColor);
+                    match map_path.extension().and_then(|e| e.to_str()) {
+                        Some("map") => {
+                            let mut map = String::new();
+                            try!(try!(fs::File::open(map_path)).read_to_string(&mut map).map_err(|_| LoadLevelError::InvalidUTF8));
+
+                            for (pos,name) in try!(read_text_map(&*map,castle)) {
+                                try!(add_entity(world,&*name,pos,castle,level,castles));
                             }
-                        }
+                        },
+                        Some(format @ "json") | Some(format @ "tmx") => {
+                            let mut map = String::new();
+                            try!(try!(fs::File::open(&map_path)).read_to_string(&mut map).map_err(|_| LoadLevelError::InvalidUTF8));
+
+                            let tiled_entities = if format == "json" { tiled::parse(&*map) } else { tiled::parse_tmx(&*map) };
+                            let tiled_entities = try!(tiled_entities.map_err(|e| LoadLevelError::TiledError {
+                                file: map_path.clone(),
+                                object: e.object,
+                                description: e.description,
+                            }));
+
+                            for entity in tiled_entities {
+                                match entity.destination {
+                                    Some(ref destination) if entity.name == "portal" => {
+                                        let destination = try!(room_destination(castle_id,dungeon_id,&dungeon.rooms,destination).ok_or(LoadLevelError::TiledError {
+                                            file: map_path.clone(),
+                                            object: entity.object,
+                                            description: format!("unknown destination room: {}",destination),
+                                        }));
+                                        entities::add_portal(world,entity.position,destination);
+                                    },
+                                    Some(_) => return Err(LoadLevelError::TiledError {
+                                        file: map_path.clone(),
+                                        object: entity.object,
+                                        description: "only portals can have a destination".into(),
+                                    }),
+                                    None => try!(add_entity(world,&*entity.name,entity.position,castle,level,castles)),
+                                }
+                            }
+                        },
+                        _ => {
+                            for (pos,name) in try!(read_png_map(&map_path,castle)) {
+                                try!(add_entity(world,&*name,pos,castle,level,castles));
+                            }
+                        },
                     }
                 },
             }
@@ -323,38 +710,38 @@ pub fn load_level<'l>(level: &Level, castles: &Vec<Castle>, world: &mut specs::W
         &Level::Corridor { castle: castle_id } => {
             let castle = try!(castles.get(castle_id).ok_or(LoadLevelError::GetCastleError));
 
-            if let Some(music) = baal::music::index() {
-                if music != castle.music {
-                    baal::music::play(castle.music);
-                }
-            }
+            play_music(world,castle.music);
 
             let levels = castle.dungeons.iter().enumerate().map(|(i,dungeon)| {
+                let name = match progress.dungeon(&*castle.name,&*dungeon.name) {
+                    Some(p) if p.completions > 0 => format!("{} {} {:.1}s",dungeon.name,config.levels.completed_marker,p.best_time),
+                    _ => dungeon.name.clone(),
+                };
+                let open = dungeon.requires.iter().all(|&r| progress.completed(&*castle.name,&*castle.dungeons[r].name));
                 (
-                    dungeon.name.clone(),
+                    name,
                     Level::Room {
                         castle: castle_id,
                         dungeon: i,
                         room: 0,
-                    }
+                    },
+                    open,
                 )
             }).collect();
 
             create_corridor(Some(Level::Entry),levels,world);
         },
         &Level::Entry => {
-            if let Some(music) = baal::music::index() {
-                if music != 0 {
-                    baal::music::play(0);
-                }
-            }
+            play_music(world,0);
 
             let levels = castles.iter().enumerate().map(|(i,castle)| {
+                let completed = castle.dungeons.iter().all(|d| progress.completed(&*castle.name,&*d.name));
                 (
-                    castle.name.clone(),
+                    if completed { format!("{} {}",castle.name,config.levels.completed_marker) } else { castle.name.clone() },
                     Level::Corridor {
                         castle: i,
-                    }
+                    },
+                    true,
             )
             }).collect();
 
@@ -362,19 +749,88 @@ pub fn load_level<'l>(level: &Level, castles: &Vec<Castle>, world: &mut specs::W
         },
     }
 
-    // add physic_world resource if not present
+    fill_resources(world);
+
+    Ok(())
+}
+
+/// load a room from the entities given instead of its file,
+/// used to play-test the room edited
+pub fn load_room_tiles(level: &Level, castles: &Vec<Castle>, world: &mut specs::World, tiles: &[([isize;2],String)]) -> Result<(),LoadLevelError> {
+    let castle = match level {
+        &Level::Room { castle: castle_id, .. } => try!(castles.get(castle_id).ok_or(LoadLevelError::GetCastleError)),
+        _ => return Err(LoadLevelError::GetRoomError),
+    };
+
+    flush_world(world);
+
+    for &(pos,ref name) in tiles {
+        try!(add_entity(world,&*name,pos,castle,level,castles));
+    }
+
+    fill_resources(world);
+
+    Ok(())
+}
+
+fn fill_resources(world: &mut specs::World) {
+    let config = config.load();
+    // add physic_world, contacts and navigation_grid resources if not present
     if !world.has_resource::<resource::PhysicWorld>() {
         world.add_resource(physic::PhysicWorld::new())
     }
+    if !world.has_resource::<resource::Contacts>() {
+        world.add_resource(physic::Contacts::new())
+    }
+
+    // contacts of the previous level
+    world.write_resource::<physic::Contacts>().clear();
+    if !world.has_resource::<resource::NavigationGrid>() {
+        world.add_resource(pathfinding::NavigationGrid::new())
+    }
 
     // fill physic_world
     let mut physic_world = world.write_resource::<physic::PhysicWorld>();
     physic_world.fill(&world);
 
+    // fill navigation_grid
+    let mut navigation_grid = world.write_resource::<pathfinding::NavigationGrid>();
+    navigation_grid.fill(&physic_world, config.entities.monster_navigation_mask.val);
+}
+
+/// add the entity defined by name, castle prefabs override global prefabs
+/// which override builtin entities,
+/// "portal.exit" is a portal to the exit of the room
+fn add_entity<P: IntoGrid>(world: &mut specs::World, name: &str, pos: P, castle: &Castle, level: &Level, castles: &Vec<Castle>) -> Result<(),LoadLevelError> {
+    let config = config.load();
+    let pos = pos.into_grid();
+
+    if let Some(prefab) = castle.prefabs.get(name).or_else(|| config.entities.prefabs.get(name)) {
+        entities::add_prefab(world,prefab,pos);
+        return Ok(());
+    }
+
+    if name.starts_with("portal.") {
+        let destination = try!(level.exit(castles,&name["portal.".len()..]));
+        entities::add_portal(world,pos,destination);
+        return Ok(());
+    }
+
+    match name {
+        "empty" => (),
+        "character" => entities::add_character(world,pos),
+        "portal" => entities::add_portal(world,pos,level.next(castles)),
+        "laser" => entities::add_laser(world,pos),
+        "monster" => entities::add_monster(world,pos),
+        "column" => entities::add_column(world,pos),
+        "wall" => entities::add_wall(world,pos),
+        _ => return Err(LoadLevelError::UnknownPrefab(name.into())),
+    }
     Ok(())
 }
 
 fn create_text_level(next: Level, text: String, world: &mut specs::World) {
+    let config = config.load();
     let bottom = config.text.bottom as isize;
     let left = config.text.left as isize;
     let right = config.text.right as isize;
@@ -393,7 +849,9 @@ fn create_text_level(next: Level, text: String, world: &mut specs::World) {
     }
 }
 
-fn create_corridor(back: Option<Level>, mut levels: Vec<(String,Level)>, world: &mut specs::World) {
+/// levels are the name, the level and whether its portal is open
+fn create_corridor(back: Option<Level>, mut levels: Vec<(String,Level,bool)>, world: &mut specs::World) {
+    let config = config.load();
     let corridor_length = config.levels.corridor_length as isize;
     let hall_length = config.levels.hall_length as isize;
 
@@ -462,7 +920,7 @@ fn create_corridor(back: Option<Level>, mut levels: Vec<(String,Level)>, world:
         }
     }
 
-    for (i,(name,level)) in levels.drain(..).enumerate() {
+    for (i,(name,level,open)) in levels.drain(..).enumerate() {
         let y = -((i*2) as isize);
 
         entities::add_text(world, hall_length as f32+corridor_length as f32+2.5, y as f32 + 0.25, config.graphics.font_scale, name);
@@ -476,6 +934,10 @@ fn create_corridor(back: Option<Level>, mut levels: Vec<(String,Level)>, world:
         }
         entities::add_wall(world,[hall_length+corridor_length+1,y]);
 
-        entities::add_portal(world,[hall_length+corridor_length,y],level);
+        if open {
+            entities::add_portal(world,[hall_length+corridor_length,y],level);
+        } else {
+            entities::add_wall(world,[hall_length+corridor_length,y]);
+        }
     }
 }
@@ -5,8 +5,8 @@ use resource::*;
 use specs::Join;
 use specs;
 use utils::Into3D;
-use baal;
 use config;
+use physic::ContactKind;
 use std::sync::Arc;
 
 pub struct Life {
@@ -33,10 +33,14 @@ impl Life {
 pub struct LifeSystem;
 impl specs::System<app::UpdateContext> for LifeSystem {
     fn run(&mut self, arg: specs::RunArg, context: app::UpdateContext) {
-        let (mut lives, mut states, entities) = arg.fetch(|world| {
+        let config = config.load();
+        let (mut lives, mut states, statics, mut physic_world, mut audio, entities) = arg.fetch(|world| {
             (
                 world.write::<Life>(),
                 world.write::<PhysicState>(),
+                world.read::<PhysicStatic>(),
+                world.write_resource::<PhysicWorld>(),
+                world.write_resource::<Audio>(),
                 world.entities(),
             )
         });
@@ -61,7 +65,10 @@ impl specs::System<app::UpdateContext> for LifeSystem {
                     }).unwrap();
                 }
 
-                baal::effect::short::play(life.die_snd,state.position.into_3d());
+                audio.play_effect(life.die_snd,state.position.into_3d());
+                if statics.get(entity).is_some() {
+                    physic_world.remove_static(entity);
+                }
                 arg.delete(entity);
             }
         }
@@ -77,15 +84,19 @@ impl specs::Component for Killer {
     type Storage = specs::VecStorage<Self>;
 }
 
+/// killers query the physic world with their killer mask instead of reading `Contacts`:
+/// contacts are collisions of dynamic entities while killers overlap entities
+/// they don't collide with, like a monster and the character it kills
 pub struct KillerSystem;
 impl specs::System<app::UpdateContext> for KillerSystem {
     fn run(&mut self, arg: specs::RunArg, _context: app::UpdateContext) {
-        let (mut lives, states, types, physic_world, killers, entities) = arg.fetch(|world| {
+        let (mut lives, states, types, physic_world, mut audio, killers, entities) = arg.fetch(|world| {
             (
                 world.write::<Life>(),
                 world.read::<PhysicState>(),
                 world.read::<PhysicType>(),
                 world.read_resource::<PhysicWorld>(),
+                world.write_resource::<Audio>(),
                 world.read::<Killer>(),
                 world.entities(),
             )
@@ -93,13 +104,20 @@ impl specs::System<app::UpdateContext> for KillerSystem {
 
         for (killer, state, typ, entity) in (&killers, &states, &types, &entities).iter() {
             let mut kill = false;
-            physic_world.apply_on_shape(&state.position, killer.mask, &typ.shape, &mut |other_entity,_| {
-                if let Some(life) = lives.get_mut(*other_entity) {
-                    baal::effect::short::play(killer.kill_snd,state.position.into_3d());
-                    life.kill();
-                    kill = true;
+            {
+                let mut hit = |other_entity: &specs::Entity| {
+                    if let Some(life) = lives.get_mut(*other_entity) {
+                        audio.play_effect(killer.kill_snd,state.position.into_3d());
+                        life.kill();
+                        kill = true;
+                    }
+                };
+                physic_world.apply_on_shape(&state.position, killer.mask, &typ.shape, &mut |other_entity,_| hit(other_entity));
+                // fast killers can go past an entity during one update
+                if typ.continuous {
+                    physic_world.apply_on_sweep(&state.previous_position, &state.position, killer.mask, &typ.shape, &mut |other_entity,_| hit(other_entity));
                 }
-            });
+            }
             if kill && killer.kamikaze {
                 lives.get_mut(entity).expect("killer kamikaze expect life component").kill();
             }
@@ -128,18 +146,29 @@ impl specs::System<app::UpdateContext> for BallSystem {
     fn run(&mut self, arg: specs::RunArg, _context: app::UpdateContext) {
         use std::ops::Mul;
 
-        let (mut lives, states, mut balls, triggers, entities) = arg.fetch(|world| {
+        let config = config.load();
+
+        let (mut lives, states, mut balls, contacts, mut audio, entities) = arg.fetch(|world| {
             (
                 world.write::<Life>(),
                 world.read::<PhysicState>(),
                 world.write::<Ball>(),
-                world.read::<PhysicTrigger>(),
+                world.read_resource::<Contacts>(),
+                world.write_resource::<Audio>(),
                 world.entities(),
             )
         });
+
+        // balls die on contact
+        for contact in contacts.iter().filter(|c| c.kind != ContactKind::End) {
+            for &entity in &[contact.entity, contact.other] {
+                if balls.get(entity).is_some() {
+                    lives.get_mut(entity).expect("ball component expect life component").kill();
+                }
+            }
+        }
+
         for (ball, entity) in (&mut balls, &entities).iter() {
-            let trigger = triggers.get(entity).expect("ball component expect trigger component");
-            let life = lives.get_mut(entity).expect("ball component expect life component");
             let state = states.get(entity).expect("ball component expect life component");
 
             ball.snd_timer -= (state.velocity[0].powi(2)+state.velocity[1].powi(2))
@@ -148,11 +177,7 @@ impl specs::System<app::UpdateContext> for BallSystem {
 
             if ball.snd_timer <= 0. {
                 ball.snd_timer += 1.0;
-                baal::effect::short::play(config.entities.ball_vel_snd,state.position.into_3d());
-            }
-
-            if trigger.active {
-                life.kill();
+                audio.play_effect(config.entities.ball_vel_snd,state.position.into_3d());
             }
         }
     }
@@ -168,6 +193,7 @@ impl specs::Component for Column {
 }
 impl Column {
     pub fn new(snd: usize) -> Column {
+        let config = config.load();
         Column {
             spawn_snd: snd,
             cooldown: Some(config.entities.column_cooldown),
@@ -178,10 +204,12 @@ impl Column {
 pub struct ColumnSystem;
 impl specs::System<app::UpdateContext> for ColumnSystem {
     fn run(&mut self, arg: specs::RunArg, context: app::UpdateContext) {
-        let (mut columns, states, entities) = arg.fetch(|world| {
+        let config = config.load();
+        let (mut columns, states, mut audio, entities) = arg.fetch(|world| {
             (
                 world.write::<Column>(),
                 world.read::<PhysicState>(),
+                world.write_resource::<Audio>(),
                 world.entities(),
             )
         });
@@ -192,7 +220,7 @@ impl specs::System<app::UpdateContext> for ColumnSystem {
                 } else {
                     let state = states.get(entity).expect("column component expect state component");
                     context.control_tx.send(app::Control::CreateBall(state.position,column.arc.clone())).unwrap();
-                    baal::effect::short::play(column.spawn_snd,state.position.into_3d());
+                    audio.play_effect(column.spawn_snd,state.position.into_3d());
                     None
                 }
             } else if let Some(_) = Arc::get_mut(&mut column.arc) {
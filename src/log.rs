@@ -0,0 +1,26 @@
+//! errors the game recovers from, written to stderr and to the log file
+
+use config;
+use std::fs::{ self, OpenOptions };
+use std::io::{ self, Write };
+use std::path::Path;
+use time;
+
+pub fn error(message: &str) {
+    let config = config.load();
+    let line = format!("{} ERROR {}\n",time::now().rfc3339(),message);
+
+    io::stderr().write_all(line.as_bytes()).unwrap();
+
+    let path = Path::new(&*config.general.log_file.val);
+    let result = path.parent().map(|dir| fs::create_dir_all(dir)).unwrap_or(Ok(()))
+        .and_then(|_| OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path))
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+
+    if let Err(e) = result {
+        writeln!(&mut io::stderr(), "ERROR failed to write log_file: {}", e).unwrap();
+    }
+}
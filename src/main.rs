@@ -1,4 +1,7 @@
+#![cfg_attr(feature = "bench", feature(test))]
+
 #[macro_use] extern crate configuration;
+#[macro_use] extern crate configuration_derive;
 #[macro_use] extern crate lazy_static;
 extern crate baal;
 extern crate graphics;
@@ -10,6 +13,9 @@ extern crate rand;
 extern crate fnv;
 extern crate png;
 extern crate gilrs;
+extern crate serde_json;
+extern crate xml;
+#[cfg(feature = "bench")] extern crate test;
 
 mod persistent_snd;
 mod levels;
@@ -23,6 +29,18 @@ mod utils;
 mod life;
 mod portal;
 mod text;
+mod pathfinding;
+mod prefab;
+mod tiled;
+mod editor;
+mod progress;
+mod replay;
+mod audio;
+mod game;
+mod headless;
+mod solver;
+mod level_watcher;
+mod log;
 
 mod components {
     pub use control::{
@@ -61,7 +79,13 @@ mod components {
     };
 }
 mod resource {
-    pub use physic::PhysicWorld;
+    pub use physic::{
+        PhysicWorld,
+        Contacts,
+    };
+    pub use pathfinding::NavigationGrid;
+    pub use utils::WorldRng;
+    pub use audio::Audio;
 }
 mod systems {
     pub use physic::PhysicSystem;
@@ -76,6 +100,7 @@ mod systems {
         MonsterSystem,
         TowardPlayerSystem,
     };
+    pub use pathfinding::NavigationSystem;
     pub use portal::PortalSystem;
     pub use persistent_snd::PersistentSndSystem;
 }
@@ -94,11 +119,13 @@ use event_loop::{
 fn init() -> Result<(app::App,glium::backend::glutin_backend::GlutinFacade,event_loop::WindowEvents,gilrs::Gilrs),String> {
     use glium::DisplayBuild;
 
+    let config = config.load();
+
     let mut musics = vec!();
     musics.push(config.levels.entry_music.val.clone());
 
     // load casltes
-    let (castles,mut musics) = try!(levels::load_castles(musics).map_err(|e| format!("ERROR: levels castles load failed: {}",e)));
+    let (castles,musics) = try!(levels::load_castles(musics).map_err(|e| format!("ERROR: levels castles load failed: {}",e)));
 
     // init baal
     try!(baal::init(&baal::Setting {
@@ -107,19 +134,17 @@ fn init() -> Result<(app::App,glium::backend::glutin_backend::GlutinFacade,event
         global_volume: config.audio.global_volume,
         music_volume: config.audio.music_volume,
         effect_volume: config.audio.effect_volume,
-        distance_model: match &*config.audio.distance_model {
-            "linear" => baal::effect::DistanceModel::Linear(config.audio.distance_model_min,config.audio.distance_model_max),
-            "pow2" => baal::effect::DistanceModel::Pow2(config.audio.distance_model_min,config.audio.distance_model_max),
-            _ => unreachable!(),
+        distance_model: match config.audio.distance_model {
+            conf::DistanceModel::Linear => baal::effect::DistanceModel::Linear(config.audio.distance_model_min,config.audio.distance_model_max),
+            conf::DistanceModel::Pow2 => baal::effect::DistanceModel::Pow2(config.audio.distance_model_min,config.audio.distance_model_max),
         },
         short_effects: config.audio.short_effects.iter().cloned().map(|n| n.val.into()).collect(),
         persistent_effects: config.audio.persistent_effects.iter().cloned().map(|n| n.val.into()).collect(),
-        musics: musics.drain(..).map(|music| music.into()).collect(),
-        music_transition: match &*config.audio.transition_type {
-            "instant" => baal::music::MusicTransition::Instant,
-            "smooth" => baal::music::MusicTransition::Smooth(Duration::from_millis(config.audio.transition_time)),
-            "overlap" => baal::music::MusicTransition::Overlap(Duration::from_millis(config.audio.transition_time)),
-            _ => unreachable!(),
+        musics: musics.iter().cloned().map(|music| music.into()).collect(),
+        music_transition: match config.audio.transition_type {
+            conf::TransitionType::Instant => baal::music::MusicTransition::Instant,
+            conf::TransitionType::Smooth => baal::music::MusicTransition::Smooth(Duration::from_millis(config.audio.transition_time)),
+            conf::TransitionType::Overlap => baal::music::MusicTransition::Overlap(Duration::from_millis(config.audio.transition_time)),
         },
     }).map_err(|e| format!("ERROR: audio init failed: {}",e)));
 
@@ -150,7 +175,7 @@ fn init() -> Result<(app::App,glium::backend::glutin_backend::GlutinFacade,event
     window.get_window().unwrap().set_cursor_state(glium::glutin::CursorState::Hide).unwrap();
 
     // init app
-    let app = try!(app::App::new(&window,castles).map_err(|e| format!("ERROR: app creation failed: {}",e)));
+    let app = try!(app::App::new(&window,castles,musics).map_err(|e| format!("ERROR: app creation failed: {}",e)));
 
     // init event loop
     let window_events = window.events(&event_loop::Setting {
@@ -162,7 +187,86 @@ fn init() -> Result<(app::App,glium::backend::glutin_backend::GlutinFacade,event
     Ok((app,window,window_events,gilrs::Gilrs::new()))
 }
 
+/// run without window nor audio, return the exit status
+fn run_headless(replay: Option<replay::Recording>, updates: Option<usize>, solve: bool) -> i32 {
+    let config = config.load();
+    let (castles,_) = match levels::load_castles(vec!()) {
+        Ok(t) => t,
+        Err(err) => {
+            println!("ERROR: levels castles load failed: {}",err);
+            return 1;
+        },
+    };
+
+    let result = match replay {
+        Some(recording) => headless::run_replay(castles,&recording,updates).map(|_| true),
+        None if solve => headless::run_solver(castles),
+        None => headless::run_rooms(castles,updates.unwrap_or(config.event_loop.ups as usize * 10)),
+    };
+
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(err) => {
+            println!("ERROR: {}",err);
+            1
+        },
+    }
+}
+
 fn main() {
+    // parse arguments
+    let mut record = None;
+    let mut replay = None;
+    let mut headless = false;
+    let mut solve = false;
+    let mut updates = None;
+    let mut overrides = vec!();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--headless" => {
+                headless = true;
+                continue;
+            },
+            "--solve" => {
+                solve = true;
+                continue;
+            },
+            _ => (),
+        }
+        match (&*arg,args.next()) {
+            ("--record",Some(path)) => record = Some(path),
+            ("--replay",Some(path)) => replay = Some(path),
+            ("--set",Some(value)) => overrides.push(value),
+            ("--updates",Some(n)) => match n.parse::<usize>() {
+                Ok(n) => updates = Some(n),
+                Err(_) => {
+                    println!("ERROR: invalid number of updates: {}",n);
+                    std::process::exit(1);
+                },
+            },
+            _ => {
+                println!("usage: ruga [--set TABLE.KEY=VALUE]... [--record FILE] [--replay FILE] [--headless [--updates N] [--solve]]");
+                std::process::exit(1);
+            },
+        }
+    }
+    conf::set_overrides(overrides);
+
+    let replay = match replay.map(|path| replay::Recording::load(path)) {
+        Some(Ok(recording)) => Some(recording),
+        Some(Err(err)) => {
+            println!("ERROR: replay load failed: {}",err);
+            std::process::exit(1);
+        },
+        None => None,
+    };
+
+    if headless {
+        std::process::exit(run_headless(replay,updates,solve));
+    }
+
     // init
     let (mut app,mut window,mut window_events, mut gamepad) = match init() {
         Ok(t) => t,
@@ -172,12 +276,22 @@ fn main() {
         },
     };
 
+    if let Some(recording) = replay {
+        app.start_replay(recording);
+    }
+    if let Some(path) = record {
+        app.start_recording(path.into());
+    }
+
     // game loop
     while let Some(event) = window_events.next(&mut window, &mut gamepad) {
         match event {
             Event::Update(args) => app.update(args),
             Event::Render(args) => app.render(args),
-            Event::GlutinEvent(glutin::Event::Closed) => break,
+            Event::GlutinEvent(glutin::Event::Closed) => {
+                app.stop_recording();
+                break;
+            },
             Event::GlutinEvent(glutin::Event::KeyboardInput(state,keycode,_)) => {
                 if state == glutin::ElementState::Pressed {
                     app.key_pressed(keycode);
@@ -196,6 +310,7 @@ fn main() {
         }
 
         if app.quit {
+            app.stop_recording();
             baal::close();
             return;
         }
@@ -0,0 +1,268 @@
+use app;
+use physic::PhysicWorld;
+use physic::Shape;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::cmp::Ordering;
+use std::hash::BuildHasherDefault;
+use fnv::FnvHasher;
+use specs;
+use std::f32;
+
+const NEIGHBOURS: [([i32;2],f32);8] = [
+    ([1,0],1.),
+    ([-1,0],1.),
+    ([0,1],1.),
+    ([0,-1],1.),
+    ([1,1],f32::consts::SQRT_2),
+    ([1,-1],f32::consts::SQRT_2),
+    ([-1,1],f32::consts::SQRT_2),
+    ([-1,-1],f32::consts::SQRT_2),
+];
+
+/// the cell of the grid that contains the position
+#[inline]
+pub fn cell_of(pos: [f32;2]) -> [i32;2] {
+    [pos[0].floor() as i32, pos[1].floor() as i32]
+}
+
+/// the center of the cell
+#[inline]
+pub fn center_of(cell: [i32;2]) -> [f32;2] {
+    [cell[0] as f32 + 0.5, cell[1] as f32 + 0.5]
+}
+
+#[derive(Clone,Copy)]
+struct Node {
+    cost: f32,
+    estimation: f32,
+    cell: [i32;2],
+}
+impl PartialEq for Node {
+    fn eq(&self, other: &Node) -> bool {
+        self.estimation == other.estimation
+    }
+}
+impl Eq for Node {}
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Node) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Node {
+    // reversed so the binary heap pop the lowest estimation
+    fn cmp(&self, other: &Node) -> Ordering {
+        other.estimation.partial_cmp(&self.estimation).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// grid of the cells that movable entities cannot walk through,
+/// it is built from the static entities of the physic world
+pub struct NavigationGrid {
+    blocked: HashSet<[i32;2],BuildHasherDefault<FnvHasher>>,
+    min: [i32;2],
+    max: [i32;2],
+    /// the mask and the statics version of the last fill
+    mask: u32,
+    statics_version: u32,
+}
+
+impl NavigationGrid {
+    pub fn new() -> Self {
+        let fnv = BuildHasherDefault::<FnvHasher>::default();
+        NavigationGrid {
+            blocked: HashSet::with_hasher(fnv),
+            min: [0,0],
+            max: [0,0],
+            mask: 0,
+            statics_version: 0,
+        }
+    }
+
+    /// block every cell covered by a static entity whose group is in mask
+    pub fn fill(&mut self, physic_world: &PhysicWorld, mask: u32) {
+        self.mask = mask;
+        self.statics_version = physic_world.statics_version();
+        self.blocked.clear();
+        self.min = [i32::max_value(),i32::max_value()];
+        self.max = [i32::min_value(),i32::min_value()];
+
+        let mut cells = vec!();
+        physic_world.apply_on_statics(&mut |_,pos,group,shape| {
+            if group & mask == 0 { return; }
+
+            let radius = match *shape {
+                Shape::Circle(r) => r,
+                Shape::Square(r) => r,
+            };
+            let min = cell_of([pos[0]-radius+f32::EPSILON, pos[1]-radius+f32::EPSILON]);
+            let max = cell_of([pos[0]+radius-f32::EPSILON, pos[1]+radius-f32::EPSILON]);
+            for x in min[0]..max[0]+1 {
+                for y in min[1]..max[1]+1 {
+                    cells.push([x,y]);
+                }
+            }
+        });
+
+        if cells.is_empty() {
+            self.min = [0,0];
+            self.max = [0,0];
+        }
+        for cell in cells {
+            self.block(cell);
+        }
+    }
+
+    /// fill again with the same mask if static entities changed since the last fill
+    pub fn update(&mut self, physic_world: &PhysicWorld) {
+        if physic_world.statics_version() != self.statics_version {
+            let mask = self.mask;
+            self.fill(physic_world,mask);
+        }
+    }
+
+    fn block(&mut self, cell: [i32;2]) {
+        self.min[0] = self.min[0].min(cell[0]);
+        self.min[1] = self.min[1].min(cell[1]);
+        self.max[0] = self.max[0].max(cell[0]);
+        self.max[1] = self.max[1].max(cell[1]);
+        self.blocked.insert(cell);
+    }
+
+    #[inline]
+    pub fn is_blocked(&self, cell: [i32;2]) -> bool {
+        self.blocked.contains(&cell)
+    }
+
+    /// A* search from the cell of start to the cell of goal,
+    /// return the waypoints in reverse order (the next one is the last)
+    /// without the start cell, the last waypoint is goal itself
+    ///
+    /// diagonal moves are only allowed if both adjacent cells are free
+    /// so bodies do not get stuck on corners
+    pub fn find_path(&self, start: [f32;2], goal: [f32;2]) -> Option<Vec<[f32;2]>> {
+        let start_cell = cell_of(start);
+        let goal_cell = cell_of(goal);
+
+        if self.is_blocked(goal_cell) {
+            return None;
+        }
+        if start_cell == goal_cell {
+            return Some(vec!(goal));
+        }
+
+        // the search is restricted to the bounding box of the grid
+        // plus a margin so it terminates in open space
+        let min = [
+            self.min[0].min(start_cell[0]).min(goal_cell[0]) - 1,
+            self.min[1].min(start_cell[1]).min(goal_cell[1]) - 1,
+        ];
+        let max = [
+            self.max[0].max(start_cell[0]).max(goal_cell[0]) + 1,
+            self.max[1].max(start_cell[1]).max(goal_cell[1]) + 1,
+        ];
+
+        let heuristic = |cell: [i32;2]| {
+            let dx = (cell[0] - goal_cell[0]).abs() as f32;
+            let dy = (cell[1] - goal_cell[1]).abs() as f32;
+            dx.max(dy) + (f32::consts::SQRT_2 - 1.)*dx.min(dy)
+        };
+
+        let mut came_from: HashMap<[i32;2],[i32;2],BuildHasherDefault<FnvHasher>> = HashMap::default();
+        let mut costs: HashMap<[i32;2],f32,BuildHasherDefault<FnvHasher>> = HashMap::default();
+        let mut open = BinaryHeap::new();
+
+        costs.insert(start_cell,0.);
+        open.push(Node {
+            cost: 0.,
+            estimation: heuristic(start_cell),
+            cell: start_cell,
+        });
+
+        while let Some(node) = open.pop() {
+            if node.cell == goal_cell {
+                let mut path = vec!(goal);
+                let mut cell = goal_cell;
+                while let Some(&previous) = came_from.get(&cell) {
+                    if previous == start_cell { break; }
+                    path.push(center_of(previous));
+                    cell = previous;
+                }
+                return Some(path);
+            }
+
+            if costs.get(&node.cell).map_or(false, |&c| c < node.cost) {
+                continue;
+            }
+
+            for &(delta,length) in &NEIGHBOURS {
+                let next = [node.cell[0]+delta[0], node.cell[1]+delta[1]];
+
+                if next[0] < min[0] || next[0] > max[0] || next[1] < min[1] || next[1] > max[1] {
+                    continue;
+                }
+                if self.is_blocked(next) {
+                    continue;
+                }
+                if delta[0] != 0 && delta[1] != 0
+                    && (self.is_blocked([node.cell[0]+delta[0],node.cell[1]])
+                        || self.is_blocked([node.cell[0],node.cell[1]+delta[1]])) {
+                    continue;
+                }
+
+                let cost = node.cost + length;
+                if costs.get(&next).map_or(true, |&c| cost < c) {
+                    costs.insert(next,cost);
+                    came_from.insert(next,node.cell);
+                    open.push(Node {
+                        cost: cost,
+                        estimation: cost + heuristic(next),
+                        cell: next,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// fill the navigation grid again when static entities change
+pub struct NavigationSystem;
+impl specs::System<app::UpdateContext> for NavigationSystem {
+    fn run(&mut self, arg: specs::RunArg, _context: app::UpdateContext) {
+        let (physic_world, mut navigation_grid) = arg.fetch(|world| {
+            (
+                world.read_resource::<PhysicWorld>(),
+                world.write_resource::<NavigationGrid>(),
+            )
+        });
+
+        navigation_grid.update(&physic_world);
+    }
+}
+
+#[test]
+fn find_path_test() {
+    let mut grid = NavigationGrid::new();
+    grid.min = [i32::max_value(),i32::max_value()];
+    grid.max = [i32::min_value(),i32::min_value()];
+
+    // vertical wall between start and goal
+    for y in -2..3 {
+        grid.block([0,y]);
+    }
+
+    let path = grid.find_path([-1.5,0.5],[1.5,0.5]).unwrap();
+    assert_eq!(path[0],[1.5,0.5]);
+    for waypoint in &path {
+        assert!(!grid.is_blocked(cell_of(*waypoint)));
+    }
+    // the path goes around the wall
+    assert!(path.iter().any(|w| w[1] > 2. || w[1] < -2.));
+
+    // goal enclosed
+    for &cell in &[[4,1],[4,-1],[3,0],[5,0]] {
+        grid.block(cell);
+    }
+    assert!(grid.find_path([-1.5,0.5],[4.5,0.5]).is_none());
+}
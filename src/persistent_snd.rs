@@ -1,6 +1,6 @@
 use specs;
 use config;
-use baal;
+use resource::Audio;
 use app;
 use components::*;
 use specs::Join;
@@ -47,10 +47,12 @@ impl Default for PersistentSndSystem {
 
 impl specs::System<app::UpdateContext> for PersistentSndSystem {
     fn run(&mut self, arg: specs::RunArg, _context: app::UpdateContext) {
-        let (dyn_persistent_snds, states) = arg.fetch(|world| {
+        let config = config.load();
+        let (dyn_persistent_snds, states, mut audio) = arg.fetch(|world| {
             (
                 world.read::<DynPersistentSnd>(),
                 world.read::<PhysicState>(),
+                world.write_resource::<Audio>(),
             )
         });
 
@@ -68,11 +70,11 @@ impl specs::System<app::UpdateContext> for PersistentSndSystem {
 
             vec.retain(|&(_,ref v)| !v.is_empty());
 
-            baal::effect::persistent::add_positions_for_all(vec);
-            baal::effect::persistent::update_volume_for_all();
+            audio.add_persistent_positions_for_all(vec);
+            audio.update_persistent_volume_for_all();
 
             for id in dyn_persistent_snd_ids {
-                baal::effect::persistent::clear_positions(id);
+                audio.clear_persistent_positions(id);
             }
         } else {
             self.cooldown -= 1;
@@ -83,11 +85,12 @@ impl specs::System<app::UpdateContext> for PersistentSndSystem {
 pub fn reset_static_persistent_snd(world: &specs::World) {
     let states =  world.read::<PhysicState>();
     let static_persistent_snds = world.read::<StaticPersistentSnd>();
+    let mut audio = world.write_resource::<Audio>();
     let entities = world.entities();
 
     for (static_persistent_snd, entity) in (&static_persistent_snds, &entities).iter() {
         let state = states.get(entity).expect("static persistent snd expect state component");
-        baal::effect::persistent::add_position(
+        audio.add_persistent_position(
             static_persistent_snd.id,
             state.position.into_3d());
     }
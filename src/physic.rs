@@ -7,6 +7,9 @@ use std::collections::HashSet;
 use std::hash::BuildHasherDefault;
 use fnv::FnvHasher;
 use std::f32;
+use std::mem;
+use std::slice;
+use std::sync::Mutex;
 
 pub trait IntoGrid {
     fn into_grid(&self) -> [f32;2];
@@ -42,18 +45,22 @@ impl GridSquare {
     }
 }
 
-#[derive(Debug,Clone)]
+#[derive(Debug,Clone,PartialEq)]
 pub enum Shape {
     Circle(f32),
     Square(f32),
 }
 
-#[derive(Debug,Clone)]
+/// the velocity after a contact
+#[derive(Debug,Clone,PartialEq)]
 pub enum CollisionBehavior {
-    #[allow(dead_code)] Bounce,
-    #[allow(dead_code)] Back,
+    /// reflected about the normal of the contact
+    Bounce,
+    /// reversed
+    Back,
+    /// unchanged, the body slides along the other one
     Persist,
-    #[allow(dead_code)] Stop,
+    Stop,
 }
 
 #[derive(Debug,Clone)]
@@ -74,17 +81,32 @@ impl PhysicTrigger {
 #[derive(Debug,Clone)]
 pub struct PhysicState {
     pub position: [f32;2],
+    /// position at the start of the last physic update
+    pub previous_position: [f32;2],
     pub velocity: [f32;2],
     pub acceleration: [f32;2],
+    /// whether the physic world must update the static entity
+    moved: bool,
 }
 impl PhysicState {
     pub fn new<T: IntoGrid>(pos: T) -> Self {
+        let pos = pos.into_grid();
         PhysicState{
-            position: pos.into_grid(),
+            position: pos,
+            previous_position: pos,
             velocity: [0.,0.],
             acceleration: [0.,0.],
+            moved: true,
         }
     }
+
+    /// move the entity, static entities must be moved with it
+    /// so the physic world updates them
+    #[allow(dead_code)]
+    pub fn set_position(&mut self, position: [f32;2]) {
+        self.position = position;
+        self.moved = true;
+    }
 }
 impl specs::Component for PhysicState {
     type Storage = specs::VecStorage<Self>;
@@ -99,9 +121,17 @@ pub struct PhysicType {
     pub weight: f32,
     pub group: u32,
     pub mask: u32,
+    /// the movement is swept so fast bodies don't go through thin ones,
+    /// the body stops at the first time of impact
+    pub continuous: bool,
+    /// ratio of the normal velocity kept by bounce and back
+    pub restitution: f32,
+    /// ratio of the tangent velocity lost by bounce
+    pub friction: f32,
 }
 impl PhysicType {
     pub fn new_movable(group: u32, mask: u32, shape: Shape, collision: CollisionBehavior, velocity: f32, time_to_reach_v_max: f32, weight: f32) -> Self {
+        let config = config.load();
         let damping = -weight * (1.-config.physic.rate).ln() / time_to_reach_v_max;
         let force = velocity * damping;
         PhysicType {
@@ -112,6 +142,9 @@ impl PhysicType {
             force: force,
             group: group,
             mask: mask,
+            continuous: false,
+            restitution: 1.,
+            friction: 0.,
         }
     }
     pub fn new_static(group: u32, mask: u32, shape: Shape) -> Self {
@@ -123,6 +156,34 @@ impl PhysicType {
             damping: 0.,
             group: group,
             mask: mask,
+            continuous: false,
+            restitution: 1.,
+            friction: 0.,
+        }
+    }
+
+    /// the velocity after a contact that pushed the body by delta
+    pub fn collision_velocity(&self, velocity: [f32;2], delta: [f32;2]) -> [f32;2] {
+        let norm = (delta[0].powi(2) + delta[1].powi(2)).sqrt();
+        if norm == 0. {
+            return velocity;
+        }
+        let normal = [delta[0]/norm, delta[1]/norm];
+        let normal_velocity = velocity[0]*normal[0] + velocity[1]*normal[1];
+
+        match self.collision_behavior {
+            // the body already moves away from the contact
+            CollisionBehavior::Bounce if normal_velocity >= 0. => velocity,
+            CollisionBehavior::Bounce => {
+                let tangent = [velocity[0] - normal_velocity*normal[0], velocity[1] - normal_velocity*normal[1]];
+                [
+                    tangent[0]*(1.-self.friction) - self.restitution*normal_velocity*normal[0],
+                    tangent[1]*(1.-self.friction) - self.restitution*normal_velocity*normal[1],
+                ]
+            },
+            CollisionBehavior::Back => [-self.restitution*velocity[0], -self.restitution*velocity[1]],
+            CollisionBehavior::Stop => [0.,0.],
+            CollisionBehavior::Persist => velocity,
         }
     }
 }
@@ -180,10 +241,304 @@ pub struct Collision {
     delta_y: f32,
 }
 
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum ContactKind {
+    /// the entities didn't touch on the previous update
+    Begin,
+    Persist,
+    /// the entities touched on the previous update but don't anymore,
+    /// normal and depth are the ones of the previous update
+    End,
+}
+
+/// a contact between a dynamic entity and another entity of its mask
+/// whose mask contains the group of the dynamic entity
+#[derive(Debug,Clone)]
+pub struct Contact {
+    pub kind: ContactKind,
+    pub entity: specs::Entity,
+    /// the entity may have been deleted for end contacts
+    pub other: specs::Entity,
+    /// unit vector to move entity out of other
+    pub normal: [f32;2],
+    pub depth: f32,
+}
+
+type Pairs = HashSet<(specs::Entity,specs::Entity),BuildHasherDefault<FnvHasher>>;
+
+fn contains_pair(pairs: &Pairs, a: specs::Entity, b: specs::Entity) -> bool {
+    pairs.contains(&(a,b)) || pairs.contains(&(b,a))
+}
+
+/// the contacts of the last physic update
+pub struct Contacts {
+    events: Vec<Contact>,
+    /// the pairs of entities that begin or persist a contact
+    touching: Pairs,
+}
+
+impl Contacts {
+    pub fn new() -> Self {
+        Contacts {
+            events: vec!(),
+            touching: HashSet::with_hasher(BuildHasherDefault::<FnvHasher>::default()),
+        }
+    }
+
+    pub fn iter(&self) -> slice::Iter<Contact> {
+        self.events.iter()
+    }
+
+    /// replace the events by the contacts of the current update,
+    /// their kind is set from the contacts of the previous update
+    fn update(&mut self, contacts: Vec<Contact>) {
+        let mut touching = HashSet::with_hasher(BuildHasherDefault::<FnvHasher>::default());
+        let mut events = Vec::with_capacity(contacts.len());
+
+        for mut contact in contacts {
+            contact.kind = if contains_pair(&self.touching,contact.entity,contact.other) {
+                ContactKind::Persist
+            } else {
+                ContactKind::Begin
+            };
+            touching.insert((contact.entity,contact.other));
+            events.push(contact);
+        }
+        for contact in self.events.iter().filter(|c| c.kind != ContactKind::End) {
+            if !contains_pair(&touching,contact.entity,contact.other) {
+                events.push(Contact {
+                    kind: ContactKind::End,
+                    .. contact.clone()
+                });
+            }
+        }
+
+        self.events = events;
+        self.touching = touching;
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.touching.clear();
+    }
+}
+
+/// the cells covered by a shape, max excluded
+#[derive(Debug,Clone,Copy,PartialEq)]
+struct Cells {
+    min: [i32;2],
+    max: [i32;2],
+}
+
+impl Cells {
+    fn of_shape(unit: f32, pos: &[f32;2], shape: &Shape) -> Cells {
+        let radius = match *shape {
+            Shape::Circle(r) => r,
+            Shape::Square(r) => r,
+        };
+        Cells {
+            min: [
+                ((pos[0]-radius+f32::EPSILON)/unit).floor() as i32,
+                ((pos[1]-radius+f32::EPSILON)/unit).floor() as i32,
+            ],
+            max: [
+                ((pos[0]+radius-f32::EPSILON)/unit).ceil() as i32,
+                ((pos[1]+radius-f32::EPSILON)/unit).ceil() as i32,
+            ],
+        }
+    }
+
+    fn len(&self) -> usize {
+        ((self.max[0]-self.min[0]).max(0) * (self.max[1]-self.min[1]).max(0)) as usize
+    }
+
+    fn contains(&self, cell: [i32;2]) -> bool {
+        self.min[0] <= cell[0] && cell[0] < self.max[0] && self.min[1] <= cell[1] && cell[1] < self.max[1]
+    }
+
+    /// the first cell of both, an entity is visited on this cell only
+    /// so it is visited once without remembering it
+    fn first_common(&self, other: &Cells) -> [i32;2] {
+        [self.min[0].max(other.min[0]), self.min[1].max(other.min[1])]
+    }
+}
+
+#[derive(Debug)]
+struct Body {
+    entity: specs::Entity,
+    position: [f32;2],
+    group: u32,
+    shape: Shape,
+    cells: Cells,
+    /// the physic step of the last update of the body
+    step: u32,
+}
+
+/// bodies indexed by the cells their shape covers,
+/// a body is moved between cells only when its cells change
+struct SpatialHash {
+    cells: HashMap<[i32;2],Vec<usize>,BuildHasherDefault<FnvHasher>>,
+    bodies: Vec<Option<Body>>,
+    /// indices of the removed bodies, reused by the next insertions
+    free: Vec<usize>,
+    indices: HashMap<specs::Entity,usize,BuildHasherDefault<FnvHasher>>,
+}
+
+impl SpatialHash {
+    fn new() -> Self {
+        SpatialHash {
+            cells: HashMap::with_hasher(BuildHasherDefault::<FnvHasher>::default()),
+            bodies: vec!(),
+            free: vec!(),
+            indices: HashMap::with_hasher(BuildHasherDefault::<FnvHasher>::default()),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.cells.clear();
+        self.bodies.clear();
+        self.free.clear();
+        self.indices.clear();
+    }
+
+    fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn get(&self, entity: specs::Entity) -> Option<&Body> {
+        self.indices.get(&entity).and_then(|&i| self.bodies[i].as_ref())
+    }
+
+    fn link(&mut self, index: usize, cells: Cells) {
+        for x in cells.min[0]..cells.max[0] {
+            for y in cells.min[1]..cells.max[1] {
+                self.cells.entry([x,y]).or_insert_with(Vec::new).push(index);
+            }
+        }
+    }
+
+    fn unlink(&mut self, index: usize, cells: Cells) {
+        for x in cells.min[0]..cells.max[0] {
+            for y in cells.min[1]..cells.max[1] {
+                if let Some(indices) = self.cells.get_mut(&[x,y]) {
+                    if let Some(i) = indices.iter().position(|&i| i == index) {
+                        indices.swap_remove(i);
+                    }
+                }
+            }
+        }
+    }
+
+    /// insert the body or update it if its entity is already in,
+    /// return whether the body is new or has changed
+    fn update(&mut self, body: Body) -> bool {
+        match self.indices.get(&body.entity).cloned() {
+            Some(index) => {
+                let changed = {
+                    let previous = self.bodies[index].as_ref().expect("indexed body");
+                    previous.position != body.position || previous.group != body.group || previous.shape != body.shape
+                };
+                let previous = self.bodies[index].as_ref().expect("indexed body").cells;
+                if previous != body.cells {
+                    self.unlink(index,previous);
+                    self.link(index,body.cells);
+                }
+                self.bodies[index] = Some(body);
+                changed
+            },
+            None => {
+                let entity = body.entity;
+                let cells = body.cells;
+                let index = match self.free.pop() {
+                    Some(index) => {
+                        self.bodies[index] = Some(body);
+                        index
+                    },
+                    None => {
+                        self.bodies.push(Some(body));
+                        self.bodies.len()-1
+                    },
+                };
+                self.indices.insert(entity,index);
+                self.link(index,cells);
+                true
+            },
+        }
+    }
+
+    fn remove(&mut self, entity: specs::Entity) -> Option<Body> {
+        self.indices.remove(&entity).map(|index| {
+            let body = self.bodies[index].take().expect("indexed body");
+            self.unlink(index,body.cells);
+            self.free.push(index);
+            body
+        })
+    }
+
+    /// remove the bodies not updated at the step, return whether any was removed
+    fn retain_step(&mut self, step: u32) -> bool {
+        let mut removed = false;
+        for index in 0..self.bodies.len() {
+            let outdated = match self.bodies[index] {
+                Some(ref body) if body.step != step => Some(body.entity),
+                _ => None,
+            };
+            if let Some(entity) = outdated {
+                self.remove(entity);
+                removed = true;
+            }
+        }
+        removed
+    }
+
+    /// call callback once on every body in the cells
+    fn apply_on_cells<F: FnMut(&Body)>(&self, cells: Cells, callback: &mut F) {
+        for x in cells.min[0]..cells.max[0] {
+            for y in cells.min[1]..cells.max[1] {
+                if let Some(indices) = self.cells.get(&[x,y]) {
+                    for &index in indices {
+                        let body = self.bodies[index].as_ref().expect("indexed body");
+                        if body.cells.first_common(&cells) == [x,y] {
+                            callback(body);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// call callback on every body in the cell
+    fn apply_on_cell<F: FnMut(&Body)>(&self, cell: [i32;2], callback: &mut F) {
+        if let Some(indices) = self.cells.get(&cell) {
+            for &index in indices {
+                callback(self.bodies[index].as_ref().expect("indexed body"));
+            }
+        }
+    }
+
+    fn apply_on_bodies<F: FnMut(&Body)>(&self, callback: &mut F) {
+        for body in self.bodies.iter().filter_map(|body| body.as_ref()) {
+            callback(body);
+        }
+    }
+}
+
 pub struct PhysicWorld {
     unit: f32,
-    static_hashmap: HashMap<[i32;2],Vec<(specs::Entity,[f32;2],u32,Shape)>,BuildHasherDefault<FnvHasher>>,
-    movable_hashmap: HashMap<[i32;2],Vec<(specs::Entity,[f32;2],u32,Shape)>,BuildHasherDefault<FnvHasher>>,
+    /// incremented on each physic update
+    step: u32,
+    /// incremented when a static entity is inserted, moved or removed
+    statics_version: u32,
+    statics: SpatialHash,
+    movables: SpatialHash,
+    raycast_buffers: Mutex<RaycastBuffers>,
+}
+
+/// buffers reused by the raycasts so they don't allocate
+#[derive(Default)]
+struct RaycastBuffers {
+    cells: Vec<[i32;2]>,
+    bodies: Vec<(specs::Entity,f32,f32)>,
 }
 
 #[derive(Debug)]
@@ -202,16 +557,17 @@ impl Resolution {
 pub struct PhysicSystem;
 impl specs::System<app::UpdateContext> for PhysicSystem {
     fn run(&mut self, arg: specs::RunArg, context: app::UpdateContext) {
-        use std::f32::consts::PI;
         use specs::Join;
 
-        let (dynamics,mut states,forces,types,mut physic_world,mut triggers,entities) = arg.fetch(|world| {
+        let (dynamics,statics,mut states,forces,types,mut physic_world,mut contacts,mut triggers,entities) = arg.fetch(|world| {
             (
                 world.read::<PhysicDynamic>(),
+                world.read::<PhysicStatic>(),
                 world.write::<PhysicState>(),
                 world.read::<PhysicForce>(),
                 world.read::<PhysicType>(),
                 world.write_resource::<PhysicWorld>(),
+                world.write_resource::<Contacts>(),
                 world.write::<PhysicTrigger>(),
                 world.entities(),
             )
@@ -220,17 +576,36 @@ impl specs::System<app::UpdateContext> for PhysicSystem {
         let dt = context.dt;
 
         let mut resolutions = HashMap::<specs::Entity,Resolution>::new();
+        let mut new_contacts = vec!();
 
         for trigger in (&mut triggers).iter() {
             trigger.active = false;
         }
-        let fnv = BuildHasherDefault::<FnvHasher>::default();
-        physic_world.movable_hashmap = HashMap::with_hasher(fnv);
+        physic_world.step = physic_world.step.wrapping_add(1);
+
+        // statics created or moved since the last update
+        for (_,state,typ,entity) in (&statics, &mut states, &types, &entities).iter() {
+            if state.moved {
+                state.moved = false;
+                physic_world.update_static(entity, &state.position, typ.group, &typ.shape);
+            }
+        }
+        // statics deleted without remove_static
+        if physic_world.statics.len() != (&statics).iter().count() {
+            for (_,state,typ,entity) in (&statics, &states, &types, &entities).iter() {
+                physic_world.update_static(entity, &state.position, typ.group, &typ.shape);
+            }
+            physic_world.retain_statics();
+        }
+
         for (_,entity) in (&dynamics, &entities).iter() {
             let state = states.get_mut(entity).expect("dynamic entity expect state component");
             let force = forces.get(entity).expect("dynamic entity expect force component");
             let typ = types.get(entity).expect("dynamic entity expect type component");
 
+            // dynamic entities are updated on every physic update
+            state.moved = false;
+
             state.acceleration[0] = (typ.force*force.intensity*force.direction.cos()
                                      - typ.damping*state.velocity[0])/typ.weight;
 
@@ -240,59 +615,96 @@ impl specs::System<app::UpdateContext> for PhysicSystem {
             state.velocity[0] += dt*state.acceleration[0];
             state.velocity[1] += dt*state.acceleration[1];
 
+            state.previous_position = state.position;
             state.position[0] += dt*state.velocity[0];
             state.position[1] += dt*state.velocity[1];
 
-            if typ.mask == 0 { continue }
+            if typ.mask != 0 {
+                if typ.continuous {
+                    let mut impact: Option<f32> = None;
+                    physic_world.apply_on_sweep(&state.previous_position, &state.position, typ.mask, &typ.shape, &mut |other_entity,time| {
+                        if physic_world.is_pending(*other_entity) { return; }
+                        let other_type = types.get(*other_entity).expect("physic entity expect type component");
+                        if other_type.mask & typ.group != 0 && impact.map(|impact| time < impact).unwrap_or(true) {
+                            impact = Some(time);
+                        }
+                    });
+
+                    if let Some(time) = impact {
+                        // stop slightly inside the other body so the contact is resolved below
+                        let from = state.previous_position;
+                        let length = ((state.position[0]-from[0]).powi(2) + (state.position[1]-from[1]).powi(2)).sqrt();
+                        let time = (time + CONTINUOUS_PENETRATION/length).min(1.);
+                        state.position[0] = from[0] + time*(state.position[0]-from[0]);
+                        state.position[1] = from[1] + time*(state.position[1]-from[1]);
+                    }
+                }
 
-            physic_world.apply_on_shape(&state.position, typ.mask, &typ.shape, &mut |other_entity,collision| {
-                let other_type = types.get(*other_entity).expect("physic entity expect type component");
+                physic_world.apply_on_shape(&state.position, typ.mask, &typ.shape, &mut |other_entity,collision| {
+                    // a pair of dynamic entities is resolved once, by the last one updated
+                    if physic_world.is_pending(*other_entity) { return; }
+                    let other_type = types.get(*other_entity).expect("physic entity expect type component");
 
-                if other_type.mask & typ.group != 0 {
+                    if other_type.mask & typ.group != 0 {
 
-                    if let Some(trigger) = triggers.get_mut(entity) {
-                        trigger.active = true;
-                    }
-                    if let Some(trigger) = triggers.get_mut(*other_entity) {
-                        trigger.active = true;
-                    }
+                        let depth = (collision.delta_x.powi(2) + collision.delta_y.powi(2)).sqrt();
+                        new_contacts.push(Contact {
+                            kind: ContactKind::Begin,
+                            entity: entity,
+                            other: *other_entity,
+                            normal: if depth > 0. { [collision.delta_x/depth, collision.delta_y/depth] } else { [0.,0.] },
+                            depth: depth,
+                        });
 
-                    let rate = {
-                        if other_type.weight == f32::MAX {
-                            0.
-                        } else if typ.weight == f32::MAX {
-                            1.
-                        } else {
-                            typ.weight/(typ.weight+other_type.weight)
+                        if let Some(trigger) = triggers.get_mut(entity) {
+                            trigger.active = true;
+                        }
+                        if let Some(trigger) = triggers.get_mut(*other_entity) {
+                            trigger.active = true;
                         }
-                    };
 
-                    if rate != 1. {
-                        let resolution = Resolution {
-                            dx: collision.delta_x*(1.-rate),
-                            dy: collision.delta_y*(1.-rate),
+                        let rate = {
+                            if other_type.weight == f32::MAX {
+                                0.
+                            } else if typ.weight == f32::MAX {
+                                1.
+                            } else {
+                                typ.weight/(typ.weight+other_type.weight)
+                            }
                         };
-                        match resolutions.entry(entity) {
-                            Entry::Occupied(mut entry) => entry.get_mut().push(resolution),
-                            Entry::Vacant(entry) => {entry.insert(resolution);},
+
+                        if rate != 1. {
+                            let resolution = Resolution {
+                                dx: collision.delta_x*(1.-rate),
+                                dy: collision.delta_y*(1.-rate),
+                            };
+                            match resolutions.entry(entity) {
+                                Entry::Occupied(mut entry) => entry.get_mut().push(resolution),
+                                Entry::Vacant(entry) => {entry.insert(resolution);},
+                            }
                         }
-                    }
-                    if rate != 0. {
-                        let resolution = Resolution {
-                            dx: -collision.delta_x*rate,
-                            dy: -collision.delta_y*rate,
-                        };
-                        match resolutions.entry(entity) {
-                            Entry::Occupied(mut entry) => entry.get_mut().push(resolution),
-                            Entry::Vacant(entry) => {entry.insert(resolution);},
+                        if rate != 0. {
+                            let resolution = Resolution {
+                                dx: -collision.delta_x*rate,
+                                dy: -collision.delta_y*rate,
+                            };
+                            match resolutions.entry(*other_entity) {
+                                Entry::Occupied(mut entry) => entry.get_mut().push(resolution),
+                                Entry::Vacant(entry) => {entry.insert(resolution);},
+                            }
                         }
-                    }
 
-                }
-            });
+                    }
+                });
+            }
 
-            physic_world.insert_movable(entity, &state.position, typ.group, &typ.shape);
+            physic_world.update_movable(entity, &state.position, typ.group, &typ.shape);
         }
+        // deleted entities
+        let step = physic_world.step;
+        physic_world.movables.retain_step(step);
+
+        contacts.update(new_contacts);
 
         for (entity,res) in resolutions {
             let state = states.get_mut(entity).unwrap();
@@ -300,42 +712,25 @@ impl specs::System<app::UpdateContext> for PhysicSystem {
 
             state.position[0] += res.dx;
             state.position[1] += res.dy;
-
-            match typ.collision_behavior {
-                CollisionBehavior::Bounce => {
-                    let angle = state.velocity[1].atan2(state.velocity[0]) + PI;
-                    state.velocity[0] = angle.cos();
-                    state.velocity[1] = angle.sin();
-                },
-                CollisionBehavior::Stop => state.velocity = [0.,0.],
-                CollisionBehavior::Back => {
-                    state.velocity[0] = -state.velocity[0];
-                    state.velocity[1] = -state.velocity[1];
-                },
-                CollisionBehavior::Persist => (),
-            }
-        }
-
-        let fnv = BuildHasherDefault::<FnvHasher>::default();
-        physic_world.movable_hashmap = HashMap::with_hasher(fnv);
-        for (_,state,typ,entity) in (&dynamics, &mut states, &types, &entities).iter() {
-            physic_world.insert_movable(entity, &state.position, typ.group, &typ.shape);
+            state.velocity = typ.collision_velocity(state.velocity, [res.dx,res.dy]);
+            physic_world.update_movable(entity, &state.position, typ.group, &typ.shape);
         }
     }
 }
 
 impl PhysicWorld {
     pub fn new() -> Self {
-        let fnv0 = BuildHasherDefault::<FnvHasher>::default();
-        let fnv1 = BuildHasherDefault::<FnvHasher>::default();
-
+        let config = config.load();
         let physic_world = PhysicWorld {
             unit: config.physic.unit,
-            static_hashmap: HashMap::with_hasher(fnv0),
-            movable_hashmap: HashMap::with_hasher(fnv1),
+            step: 0,
+            statics_version: 0,
+            statics: SpatialHash::new(),
+            movables: SpatialHash::new(),
+            raycast_buffers: Mutex::new(RaycastBuffers::default()),
         };
-        debug_assert_eq!(physic_world.cells_of_shape(&[0.5,0.5], &Shape::Square(0.5 + f32::EPSILON)).len(),1);
-        debug_assert_eq!(physic_world.cells_of_shape(&[0.5,0.5], &Shape::Circle(0.5 + f32::EPSILON)).len(),1);
+        debug_assert_eq!(physic_world.cells(&[0.5,0.5], &Shape::Square(0.5 + f32::EPSILON)).len(),1);
+        debug_assert_eq!(physic_world.cells(&[0.5,0.5], &Shape::Circle(0.5 + f32::EPSILON)).len(),1);
 
         physic_world
     }
@@ -347,86 +742,111 @@ impl PhysicWorld {
         let types = world.read::<PhysicType>();
         let entities = world.entities();
 
-        self.static_hashmap.clear();
-        self.movable_hashmap.clear();
+        self.statics.clear();
+        self.movables.clear();
+        self.statics_version = self.statics_version.wrapping_add(1);
 
         for (_,state,typ,entity) in (&dynamics, &states, &types, &entities).iter() {
-            self.insert_movable(entity, &state.position, typ.group, &typ.shape);
+            self.update_movable(entity, &state.position, typ.group, &typ.shape);
         }
         for (_,state,typ,entity) in (&statics, &states, &types, &entities).iter() {
-            self.insert_static(entity, &state.position, typ.group, &typ.shape);
+            self.update_static(entity, &state.position, typ.group, &typ.shape);
+        }
+    }
+
+    /// compare it to the version of a previous call to know if static entities changed since
+    pub fn statics_version(&self) -> u32 {
+        self.statics_version
+    }
+
+    fn cells(&self, pos: &[f32;2], shape: &Shape) -> Cells {
+        Cells::of_shape(self.unit,pos,shape)
+    }
+
+    fn body(&self, entity: specs::Entity, pos: &[f32;2], group: u32, shape: &Shape) -> Body {
+        Body {
+            entity: entity,
+            position: *pos,
+            group: group,
+            shape: shape.clone(),
+            cells: self.cells(pos,shape),
+            step: self.step,
         }
     }
 
-    fn cells_of_shape(&self, pos: &[f32;2], shape: &Shape) -> Vec<[i32;2]> {
+    /// whether the entity is a movable not updated yet during the current physic update
+    fn is_pending(&self, entity: specs::Entity) -> bool {
+        self.movables.get(entity).map(|body| body.step != self.step).unwrap_or(false)
+    }
+
+    /// call callback once on every body of the mask in the cells
+    fn apply_on_cells<F: FnMut(&Body)>(&self, cells: Cells, mask: u32, callback: &mut F) {
+        let mut in_mask = |body: &Body| if body.group & mask != 0 { callback(body) };
+        self.movables.apply_on_cells(cells,&mut in_mask);
+        self.statics.apply_on_cells(cells,&mut in_mask);
+    }
+
+    /// call callback on the entities of the mask that the shape moving from `from` to `to`
+    /// starts to touch, with the time of impact between 0 and 1,
+    /// entities that already overlap the shape at `from` are ignored
+    pub fn apply_on_sweep<F: FnMut(&specs::Entity,f32)>(&self, from: &[f32;2], to: &[f32;2], mask: u32, shape: &Shape, callback: &mut F) {
         let radius = match *shape {
             Shape::Circle(r) => r,
             Shape::Square(r) => r,
         };
+        let center = [(from[0]+to[0])/2., (from[1]+to[1])/2.];
+        let bounds = Shape::Square(radius + (to[0]-from[0]).abs().max((to[1]-from[1]).abs())/2.);
 
-        let min_x = ((pos[0]-radius+f32::EPSILON)/self.unit).floor() as i32;
-        let max_x = ((pos[0]+radius-f32::EPSILON)/self.unit).ceil() as i32;
-        let min_y = ((pos[1]-radius+f32::EPSILON)/self.unit).floor() as i32;
-        let max_y = ((pos[1]+radius-f32::EPSILON)/self.unit).ceil() as i32;
-
-        let mut cells = Vec::new();
-        for x in min_x..max_x {
-            for y in min_y..max_y {
-                cells.push([x,y]);
+        self.apply_on_cells(self.cells(&center,&bounds), mask, &mut |body| {
+            if let Some(time) = time_of_impact(from,to,shape,&body.position,&body.shape) {
+                callback(&body.entity,time);
             }
-        }
-        cells
+        });
     }
 
     pub fn apply_on_shape<F: FnMut(&specs::Entity,&Collision)>(&self, pos: &[f32;2], mask: u32, shape: &Shape, callback: &mut F) {
-        let mut visited = HashSet::new();
-
-        for cell in self.cells_of_shape(pos,shape) {
-            self.apply_on_index(cell, mask, &mut |other_entity, other_pos, other_shape| {
-                if visited.contains(other_entity) { return; }
-                visited.insert(*other_entity);
-                if let Some(collision) = shape_collide(pos,shape,other_pos,other_shape) {
-                    callback(other_entity,&collision);
-                }
-            });
-        }
+        self.apply_on_cells(self.cells(pos,shape), mask, &mut |body| {
+            if let Some(collision) = shape_collide(pos,shape,&body.position,&body.shape) {
+                callback(&body.entity,&collision);
+            }
+        });
     }
 
-    fn apply_on_index<F: FnMut(&specs::Entity,&[f32;2],&Shape)>(&self, cell: [i32;2], mask: u32, callback: &mut F) {
-        let empty_vec = vec!();
-        let vec = self.movable_hashmap.get(&cell).unwrap_or(&empty_vec).iter()
-            .chain(self.static_hashmap.get(&cell).unwrap_or(&empty_vec));
+    /// insert the static or move it if it changed
+    fn update_static(&mut self, entity: specs::Entity, pos: &[f32;2], group: u32, shape: &Shape) {
+        let body = self.body(entity,pos,group,shape);
+        if self.statics.update(body) {
+            self.statics_version = self.statics_version.wrapping_add(1);
+        }
+    }
 
-        for &(ref entity,ref pos, group, ref shape) in vec {
-            if (group & mask) != 0 {
-                callback(entity,pos,shape);
-            }
+    /// remove the static of a deleted entity
+    pub fn remove_static(&mut self, entity: specs::Entity) {
+        if self.statics.remove(entity).is_some() {
+            self.statics_version = self.statics_version.wrapping_add(1);
         }
     }
 
-    pub fn insert_static(&mut self, entity: specs::Entity, pos: &[f32;2], group: u32, shape: &Shape) {
-        for cell in self.cells_of_shape(pos,shape) {
-            self.static_hashmap.entry(cell).or_insert(Vec::new()).push((entity,pos.clone(),group,shape.clone()));
+    /// remove the statics not updated during the current physic update
+    fn retain_statics(&mut self) {
+        if self.statics.retain_step(self.step) {
+            self.statics_version = self.statics_version.wrapping_add(1);
         }
     }
 
-    // pub fn remove_static(&mut self, entity: specs::Entity, pos:&[f32;2], shape: &Shape) {
-    //     for cell in self.cells_of_shape(pos,shape) {
-    //         let vec = self.static_hashmap.get_mut(&cell).expect("remove static in an unexisting cell");
-    //         let i = vec.iter().position(|&(e,_,_,_,_)| e == entity).expect("remove unfindable entity");
-    //         vec.swap_remove(i);
-    //     }
-    // }
+    /// call callback once on every static entity
+    pub fn apply_on_statics<F: FnMut(&specs::Entity,&[f32;2],u32,&Shape)>(&self, callback: &mut F) {
+        self.statics.apply_on_bodies(&mut |body| callback(&body.entity,&body.position,body.group,&body.shape));
+    }
 
-    fn insert_movable(&mut self, entity: specs::Entity, pos: &[f32;2], group: u32, shape: &Shape) {
-        for cell in self.cells_of_shape(pos,shape) {
-            self.movable_hashmap.entry(cell).or_insert(Vec::new()).push((entity,pos.clone(),group,shape.clone()));
-        }
+    /// insert the movable or move it if its cells changed
+    fn update_movable(&mut self, entity: specs::Entity, pos: &[f32;2], group: u32, shape: &Shape) {
+        let body = self.body(entity,pos,group,shape);
+        self.movables.update(body);
     }
 
     pub fn raycast<F: FnMut((specs::Entity,f32,f32)) -> bool>(&self, ray: &Ray, callback: &mut F) {
         use std::f32::consts::PI;
-        use std::cmp::Ordering;
         use utils::minus_pi_pi;
 
         let angle = minus_pi_pi(ray.angle);
@@ -435,7 +855,10 @@ impl PhysicWorld {
         let y0 = ray.origin[1];
         let x1 = x0+ray.length*angle.cos();
         let y1 = y0+ray.length*angle.sin();
-        let cells = grid_raycast(x0/self.unit, y0/self.unit, x1/self.unit, y1/self.unit);
+
+        // the buffers are taken so a raycast in the callback doesn't wait for them
+        let mut buffers = mem::replace(&mut *self.raycast_buffers.lock().unwrap(), RaycastBuffers::default());
+        grid_raycast(x0/self.unit, y0/self.unit, x1/self.unit, y1/self.unit, &mut buffers.cells);
 
         // equation ax + by + c = 0
         let (a,b,c) = if angle.abs() == PI || angle == 0. {
@@ -448,79 +871,398 @@ impl PhysicWorld {
         let line_start = x0.min(x1);
         let line_end = x0.max(x1);
 
-        let mut visited: HashSet<specs::Entity> = HashSet::new();
-
-        for cell in cells {
-            // abscisse of start and end the segment of
-            // the line that is in the current square
+        // abscisse of start and end the segment of
+        // the line that is in the cell
+        let segment = |cell: [i32;2]| (
+            ((cell[0] as f32)*self.unit).max(line_start),
+            (((cell[0]+1) as f32)*self.unit).min(line_end),
+        );
 
-            let segment_start = ((cell[0] as f32)*self.unit).max(line_start);
-            let segment_end = (((cell[0]+1) as f32)*self.unit).min(line_end);
+        let mut previous: Option<[i32;2]> = None;
 
-            let mut bodies: Vec<(specs::Entity,f32,f32)> = Vec::new();
+        'cells: for &cell in &buffers.cells {
+            let (segment_start,segment_end) = segment(cell);
+            let bodies = &mut buffers.bodies;
+            bodies.clear();
 
             {
-                let null_vec = vec!();
-                let entities = self.movable_hashmap.get(&cell).unwrap_or(&null_vec).iter()
-                    .chain(self.static_hashmap.get(&cell).unwrap_or(&null_vec).iter());
-
-                for &(entity,ref pos,group,ref shape) in entities {
-                    if (group & ray.mask) == 0 { continue; }
-                    if visited.contains(&entity) { continue; }
+                let mut check = |body: &Body| {
+                    let entity = body.entity;
+                    let pos = body.position;
+                    if (body.group & ray.mask) == 0 { return; }
 
-                    let intersections = match *shape {
+                    let intersections = match body.shape {
                         Shape::Circle(radius) => circle_raycast(pos[0],pos[1],radius,a,b,c),
                         Shape::Square(radius) => bounding_box_raycast(pos[0],pos[1],radius*2.,radius*2.,a,b,c),
                     };
 
                     if let Some((x_min,y_min,x_max,y_max)) = intersections {
-                        // println!("intersection\nstart:{},end:{},min:{},max:{}",segment_start,segment_end,x_min,x_max);
+                        if !(segment_start <= x_max && x_min <= segment_end) { return; }
+
+                        // the cells of the ray that are in the body and whose segment
+                        // intersects it follow each other: the body is visited on the first one
+                        if let Some(previous) = previous {
+                            let (previous_start,previous_end) = segment(previous);
+                            if body.cells.contains(previous) && previous_start <= x_max && x_min <= previous_end {
+                                return;
+                            }
+                        }
 
                         // angle is between minus_pi and pi
-                        if angle.abs() > PI/2. {
-                            if segment_start <= x_max && x_min<= segment_end {
-                                visited.insert(entity);
-                                // println!("intersection in segment");
-                                let max = ((x0-x_min).powi(2) + (y0-y_min).powi(2)).sqrt();
-                                let mut min = ((x0-x_max).powi(2) + (y0-y_max).powi(2)).sqrt();
-                                if x_max > segment_end {
-                                    min = -min;
-                                }
-                                bodies.push((entity,min,max));
+                        let (min,max) = if angle.abs() > PI/2. {
+                            let max = ((x0-x_min).powi(2) + (y0-y_min).powi(2)).sqrt();
+                            let mut min = ((x0-x_max).powi(2) + (y0-y_max).powi(2)).sqrt();
+                            if x_max > segment_end {
+                                min = -min;
                             }
+                            (min,max)
                         } else {
-                            if segment_start <= x_max && x_min <= segment_end {
-                                visited.insert(entity);
-                                // println!("intersection in segment");
-                                let mut min = ((x0-x_min).powi(2) + (y0-y_min).powi(2)).sqrt();
-                                let max = ((x0-x_max).powi(2) + (y0-y_max).powi(2)).sqrt();
-                                if x_min < segment_start {
-                                    min = -min;
-                                }
-                                bodies.push((entity,min,max));
+                            let mut min = ((x0-x_min).powi(2) + (y0-y_min).powi(2)).sqrt();
+                            let max = ((x0-x_max).powi(2) + (y0-y_max).powi(2)).sqrt();
+                            if x_min < segment_start {
+                                min = -min;
                             }
-                        }
+                            (min,max)
+                        };
+
+                        // sorted by min, bodies with the same min in the order they are visited
+                        let index = bodies.iter().position(|&(_,other_min,_)| other_min > min).unwrap_or(bodies.len());
+                        bodies.insert(index,(entity,min,max));
                     }
-                }
+                };
+                self.movables.apply_on_cell(cell,&mut check);
+                self.statics.apply_on_cell(cell,&mut check);
             }
+            previous = Some(cell);
 
-            bodies.sort_by(|&(_,min_a,_),&(_,min_b,_)| {
-                if min_a > min_b {
-                    Ordering::Greater
-                } else if min_a == min_b {
-                    Ordering::Equal
-                } else {
-                    Ordering::Less
+            for &body in bodies.iter() {
+                if callback(body) {
+                    break 'cells;
                 }
-            });
+            }
+        }
+
+        *self.raycast_buffers.lock().unwrap() = buffers;
+    }
+}
+
+#[test]
+fn spatial_hash_test() {
+    let mut world = specs::World::new();
+    let a = world.create_now().build();
+    let b = world.create_now().build();
+    let c = world.create_now().build();
+    let body = |entity, position: [f32;2], step| Body {
+        entity: entity,
+        position: position,
+        group: 1,
+        shape: Shape::Square(1.5),
+        cells: Cells::of_shape(2.,&position,&Shape::Square(1.5)),
+        step: step,
+    };
+    let visit = |hash: &SpatialHash, min: [i32;2], max: [i32;2]| {
+        let mut entities = vec!();
+        hash.apply_on_cells(Cells { min: min, max: max }, &mut |body: &Body| entities.push(body.entity));
+        entities
+    };
+
+    let mut hash = SpatialHash::new();
+    hash.update(body(a,[1.,1.],0));
+    hash.update(body(b,[10.,10.],0));
+    // a covers 9 cells but is visited once
+    assert_eq!(visit(&hash,[-10,-10],[10,10]).len(),2);
+    assert_eq!(visit(&hash,[0,0],[1,1]),vec!(a));
+
+    hash.update(body(a,[10.,1.],0));
+    assert_eq!(visit(&hash,[0,0],[1,1]),vec!());
+    assert_eq!(visit(&hash,[5,0],[6,1]),vec!(a));
+
+    // b isn't updated on step 1 so it is removed and its index is reused
+    hash.update(body(a,[10.,1.],1));
+    hash.retain_step(1);
+    assert!(hash.get(b).is_none());
+    hash.update(body(c,[1.,1.],1));
+    assert_eq!(hash.bodies.len(),2);
+    assert_eq!(visit(&hash,[0,0],[1,1]),vec!(c));
+    assert_eq!(visit(&hash,[-10,-10],[10,10]).len(),2);
+}
+
+#[test]
+fn statics_sync_test() {
+    let mut world = specs::World::new();
+    let wall = world.create_now().build();
+    let mut physic_world = PhysicWorld {
+        unit: 1.,
+        step: 0,
+        statics_version: 0,
+        statics: SpatialHash::new(),
+        movables: SpatialHash::new(),
+        raycast_buffers: Mutex::new(RaycastBuffers::default()),
+    };
+    let touched = |physic_world: &PhysicWorld, pos: [f32;2]| {
+        let mut entities = vec!();
+        physic_world.apply_on_shape(&pos, !0, &Shape::Square(0.4), &mut |entity,_| entities.push(*entity));
+        entities
+    };
+
+    physic_world.update_static(wall, &[0.5,0.5], 1, &Shape::Square(0.5));
+    let version = physic_world.statics_version();
+    assert!(version != 0);
+
+    // unchanged
+    physic_world.step += 1;
+    physic_world.update_static(wall, &[0.5,0.5], 1, &Shape::Square(0.5));
+    physic_world.retain_statics();
+    assert_eq!(physic_world.statics_version(),version);
+
+    // moved
+    physic_world.step += 1;
+    physic_world.update_static(wall, &[3.5,0.5], 1, &Shape::Square(0.5));
+    physic_world.retain_statics();
+    assert!(physic_world.statics_version() != version);
+    assert_eq!(touched(&physic_world,[0.5,0.5]),vec!());
+    assert_eq!(touched(&physic_world,[3.5,0.5]),vec!(wall));
+
+    // deleted
+    let version = physic_world.statics_version();
+    physic_world.step += 1;
+    physic_world.retain_statics();
+    assert!(physic_world.statics_version() != version);
+    assert_eq!(touched(&physic_world,[3.5,0.5]),vec!());
+
+    // removed
+    physic_world.update_static(wall, &[0.5,0.5], 1, &Shape::Square(0.5));
+    let version = physic_world.statics_version();
+    physic_world.remove_static(wall);
+    assert!(physic_world.statics_version() != version);
+    assert_eq!(physic_world.statics.len(),0);
+    assert_eq!(touched(&physic_world,[0.5,0.5]),vec!());
+}
+
+#[cfg(feature = "bench")]
+#[bench]
+fn crowded_room_bench(bencher: &mut ::test::Bencher) {
+    use audio::NullAudio;
+    use game::Game;
+    use levels::{ self, Level };
+    use progress::Progress;
+
+    let (castles,_) = levels::load_castles(vec!()).unwrap();
+    let level = Level::Room { castle: 0, dungeon: 0, room: 0 };
+    let mut game = Game::new(castles,Progress::new(),Box::new(NullAudio::new()),0).unwrap();
+
+    // 30x30 room with 300 monsters and columns spawning balls
+    let mut room = vec!();
+    for i in -1..31 {
+        room.push(([i,-1],String::from("wall")));
+        room.push(([i,30],String::from("wall")));
+        room.push(([-1,i],String::from("wall")));
+        room.push(([30,i],String::from("wall")));
+    }
+    for x in 0..30 {
+        for y in 0..30 {
+            if (x+y) % 3 == 0 {
+                room.push(([x,y],String::from("monster")));
+            } else if x % 6 == 1 && y % 6 == 1 {
+                room.push(([x,y],String::from("column")));
+            }
+        }
+    }
+    game.load_room_tiles(&level,&room).unwrap();
+
+    bencher.iter(|| game.step(1. / 60.,1.).unwrap());
+}
 
-            for (entity,min,max) in bodies {
-                if callback((entity,min,max)) {
-                    return;
+#[test]
+fn raycast_test() {
+    let mut world = specs::World::new();
+    let wall = world.create_now().build();
+    let ball = world.create_now().build();
+    let hidden = world.create_now().build();
+    let mut physic_world = PhysicWorld {
+        unit: 1.,
+        step: 0,
+        statics_version: 0,
+        statics: SpatialHash::new(),
+        movables: SpatialHash::new(),
+        raycast_buffers: Mutex::new(RaycastBuffers::default()),
+    };
+    physic_world.update_static(wall, &[2.5,0.5], 1, &Shape::Square(1.5));
+    physic_world.update_movable(ball, &[5.5,0.5], 1, &Shape::Circle(0.4));
+    physic_world.update_movable(hidden, &[7.5,0.5], 2, &Shape::Circle(0.4));
+
+    for &(origin,angle) in &[([0.,0.5],0.), ([9.,0.5],f32::consts::PI)] {
+        let ray = Ray { origin: origin, angle: angle, length: 9., mask: 1 };
+        let mut entities = vec!();
+        physic_world.raycast(&ray, &mut |(entity,_,_)| { entities.push(entity); false });
+        if angle == 0. {
+            assert_eq!(entities,vec!(wall,ball));
+        } else {
+            assert_eq!(entities,vec!(ball,wall));
+        }
+
+        let mut entities = vec!();
+        physic_world.raycast(&ray, &mut |(entity,_,_)| { entities.push(entity); true });
+        assert_eq!(entities.len(),1);
+    }
+}
+
+#[test]
+fn contacts_test() {
+    let mut world = specs::World::new();
+    let a = world.create_now().build();
+    let b = world.create_now().build();
+    let c = world.create_now().build();
+    let contact = |entity, other| Contact {
+        kind: ContactKind::Begin,
+        entity: entity,
+        other: other,
+        normal: [1.,0.],
+        depth: 0.1,
+    };
+    let kinds = |contacts: &Contacts| contacts.iter().map(|c| (c.entity,c.other,c.kind)).collect::<Vec<_>>();
+
+    let mut contacts = Contacts::new();
+    contacts.update(vec!(contact(a,b)));
+    assert_eq!(kinds(&contacts),vec!((a,b,ContactKind::Begin)));
+
+    // the order of the entities doesn't matter
+    contacts.update(vec!(contact(b,a),contact(a,c)));
+    assert_eq!(kinds(&contacts),vec!((b,a,ContactKind::Persist),(a,c,ContactKind::Begin)));
+
+    contacts.update(vec!(contact(a,c)));
+    assert_eq!(kinds(&contacts),vec!((a,c,ContactKind::Persist),(b,a,ContactKind::End)));
+
+    contacts.update(vec!());
+    assert_eq!(kinds(&contacts),vec!((a,c,ContactKind::End)));
+
+    contacts.update(vec!());
+    assert!(contacts.iter().next().is_none());
+}
+
+#[test]
+fn collision_velocity_test() {
+    let typ = |behavior: CollisionBehavior, restitution: f32, friction: f32| PhysicType {
+        collision_behavior: behavior,
+        restitution: restitution,
+        friction: friction,
+        ..PhysicType::new_static(0,0,Shape::Circle(0.5))
+    };
+    let delta = |a_pos: [f32;2], a_shape: Shape, b_pos: [f32;2], b_shape: Shape| {
+        let collision = shape_collide(&a_pos,&a_shape,&b_pos,&b_shape).unwrap();
+        [collision.delta_x, collision.delta_y]
+    };
+    let close = |a: [f32;2], b: [f32;2]| (a[0]-b[0]).abs() < 1e-4 && (a[1]-b[1]).abs() < 1e-4;
+
+    // on the corner of a wall the ball bounces back along the diagonal
+    let corner = delta([1.75,1.75],Shape::Circle(0.5),[1.,1.],Shape::Square(0.5));
+    assert!(close(typ(CollisionBehavior::Bounce,1.,0.).collision_velocity([-1.,-1.],corner),[1.,1.]));
+    assert!(close(typ(CollisionBehavior::Bounce,0.5,0.).collision_velocity([-1.,-1.],corner),[0.5,0.5]));
+
+    // on the side of a wall the tangent velocity is kept
+    let side = delta([1.,1.9],Shape::Circle(0.5),[1.,1.],Shape::Square(0.5));
+    assert!(close(typ(CollisionBehavior::Bounce,1.,0.).collision_velocity([1.,-1.],side),[1.,1.]));
+    assert!(close(typ(CollisionBehavior::Bounce,0.5,0.5).collision_velocity([1.,-1.],side),[0.5,0.5]));
+    // moving away from the wall
+    assert!(close(typ(CollisionBehavior::Bounce,1.,0.).collision_velocity([1.,1.],side),[1.,1.]));
+
+    // circles reflect about the line of their centers
+    let circles = delta([0.,0.],Shape::Circle(0.5),[0.54,0.72],Shape::Circle(0.5));
+    assert!(close(typ(CollisionBehavior::Bounce,1.,0.).collision_velocity([1.,0.],circles),[0.28,-0.96]));
+    assert!(close(typ(CollisionBehavior::Back,0.5,0.).collision_velocity([1.,0.],circles),[-0.5,0.]));
+    assert!(close(typ(CollisionBehavior::Stop,1.,0.).collision_velocity([1.,0.],circles),[0.,0.]));
+    assert!(close(typ(CollisionBehavior::Persist,1.,0.).collision_velocity([1.,0.],circles),[1.,0.]));
+}
+
+/// depth a continuous body goes into the body it hits
+const CONTINUOUS_PENETRATION: f32 = 0.001;
+
+/// the times along the segment of the intersections given by a raycast, in order
+fn raycast_times(from: &[f32;2], to: &[f32;2], intersections: Option<(f32,f32,f32,f32)>) -> Option<(f32,f32)> {
+    intersections.map(|(x1,y1,x2,y2)| {
+        let dx = to[0]-from[0];
+        let dy = to[1]-from[1];
+        let length2 = dx.powi(2) + dy.powi(2);
+        let t1 = ((x1-from[0])*dx + (y1-from[1])*dy)/length2;
+        let t2 = ((x2-from[0])*dx + (y2-from[1])*dy)/length2;
+        (t1.min(t2),t1.max(t2))
+    })
+}
+
+/// the time between 0 and 1 at which the shape moving from `from` to `to`
+/// starts to touch the other shape, none if it doesn't or if they already overlap at `from`
+fn time_of_impact(from: &[f32;2], to: &[f32;2], shape: &Shape, other_pos: &[f32;2], other_shape: &Shape) -> Option<f32> {
+    // shapes are symmetric so the axes are swapped to keep the line far from vertical
+    if (to[1]-from[1]).abs() > (to[0]-from[0]).abs() {
+        return time_of_impact(&[from[1],from[0]],&[to[1],to[0]],shape,&[other_pos[1],other_pos[0]],other_shape);
+    }
+    if from == to {
+        return None;
+    }
+
+    // the moving center against the minkowski sum of both shapes
+    let (x,y) = (other_pos[0],other_pos[1]);
+    let a = to[1]-from[1];
+    let b = from[0]-to[0];
+    let c = -a*from[0] - b*from[1];
+
+    let entries = match (shape,other_shape) {
+        (&Shape::Circle(r1),&Shape::Circle(r2)) => {
+            vec!(raycast_times(from,to,circle_raycast(x,y,r1+r2,a,b,c)))
+        },
+        (&Shape::Square(r1),&Shape::Square(r2)) => {
+            vec!(raycast_times(from,to,bounding_box_raycast(x,y,2.*(r1+r2),2.*(r1+r2),a,b,c)))
+        },
+        (&Shape::Circle(r),&Shape::Square(s)) | (&Shape::Square(s),&Shape::Circle(r)) => {
+            // a square with rounded corners: the entry in the square
+            // is valid if it isn't in a corner, otherwise it is in a corner circle
+            let mut entries = vec!();
+            if let Some((t_in,t_out)) = raycast_times(from,to,bounding_box_raycast(x,y,2.*(s+r),2.*(s+r),a,b,c)) {
+                let t = t_in.max(0.);
+                let px = from[0] + t*(to[0]-from[0]);
+                let py = from[1] + t*(to[1]-from[1]);
+                if (px-x).abs() <= s || (py-y).abs() <= s {
+                    entries.push(Some((t_in,t_out)));
                 }
             }
+            for &(cx,cy) in &[(x-s,y-s),(x-s,y+s),(x+s,y-s),(x+s,y+s)] {
+                entries.push(raycast_times(from,to,circle_raycast(cx,cy,r,a,b,c)));
+            }
+            entries
+        },
+    };
+
+    let mut impact: Option<f32> = None;
+    for &(t_in,t_out) in entries.iter().flat_map(|entry| entry.iter()) {
+        if t_in < 0. && t_out > 0. {
+            return None;
+        }
+        if 0. <= t_in && t_in <= 1. && impact.map(|impact| t_in < impact).unwrap_or(true) {
+            impact = Some(t_in);
         }
     }
+    impact
+}
+
+#[test]
+fn time_of_impact_test() {
+    let wall = Shape::Square(0.5);
+    let ball = Shape::Circle(0.5);
+    let close = |a: Option<f32>, b: f32| (a.unwrap() - b).abs() < 1e-4;
+
+    // through a one tile wall
+    assert!(close(time_of_impact(&[0.,0.5],&[10.,0.5],&ball,&[3.,0.5],&wall),0.2));
+    assert!(close(time_of_impact(&[0.5,10.],&[0.5,0.],&ball,&[0.5,3.],&wall),0.6));
+    assert!(close(time_of_impact(&[0.,0.],&[10.,0.],&wall,&[3.,0.],&wall),0.2));
+    // on a corner of the wall
+    assert!(close(time_of_impact(&[-2.,-2.],&[2.,2.],&ball,&[1.,1.],&wall),(3. - 0.5/2f32.sqrt())/4. - 0.5/4.));
+    assert!(close(time_of_impact(&[-2.,1.9],&[2.,1.9],&ball,&[1.,1.],&wall),0.55));
+    assert_eq!(time_of_impact(&[-2.,2.1],&[2.,2.1],&ball,&[1.,1.],&wall),None);
+    // circles
+    assert!(close(time_of_impact(&[0.,0.],&[0.,8.],&ball,&[0.,5.],&ball),0.5));
+    assert_eq!(time_of_impact(&[0.,0.],&[0.,3.],&ball,&[0.,5.],&ball),None);
+    // already overlapping
+    assert_eq!(time_of_impact(&[2.8,0.5],&[10.,0.5],&ball,&[3.,0.5],&wall),None);
 }
 
 const MOVE: f32 = 7./8.;
@@ -629,14 +1371,18 @@ fn shape_collide(a_pos: &[f32;2], a_shape: &Shape, b_pos: &[f32;2], b_shape: &Sh
     }
 }
 
-fn grid_raycast(x0: f32, y0: f32, x1: f32, y1: f32) -> Vec<[i32;2]> {
+/// set the cells crossed by the segment in order in the vector
+fn grid_raycast(x0: f32, y0: f32, x1: f32, y1: f32, vec: &mut Vec<[i32;2]>) {
     if (x1-x0).abs() < (y1-y0).abs() {
-        grid_raycast(y0,x0,y1,x1).iter().map(|s| [s[1],s[0]]).collect::<Vec<[i32;2]>>()
+        grid_raycast(y0,x0,y1,x1,vec);
+        for cell in vec.iter_mut() {
+            *cell = [cell[1],cell[0]];
+        }
     } else if x0 == x1 {
         let x0_i32 = x0.floor() as i32;
         let y0_i32 = y0.floor() as i32;
         let y1_i32 = y1.floor() as i32;
-        let mut vec = Vec::new();
+        vec.clear();
 
         if y0 > y1 {
             for y in y1_i32..y0_i32+1 {
@@ -648,12 +1394,9 @@ fn grid_raycast(x0: f32, y0: f32, x1: f32, y1: f32) -> Vec<[i32;2]> {
                 vec.push([x0_i32,y]);
             }
         }
-
-        vec
     } else if x0 > x1 {
-        let mut vec = grid_raycast(x1,y1,x0,y0);
+        grid_raycast(x1,y1,x0,y0,vec);
         vec.reverse();
-        vec
     } else {
         // x0 < x1
         //println!("x0:{},y0:{},x1:{},y1:{}",x0,y0,x1,y1);
@@ -690,7 +1433,7 @@ fn grid_raycast(x0: f32, y0: f32, x1: f32, y1: f32) -> Vec<[i32;2]> {
         //println!("debut: error: {}",error);
         //println!("error end : {}", error_end);
 
-        let mut vec = Vec::new();
+        vec.clear();
         let mut y = y0_i32;
 
         for x in x0_i32..x1_i32+1 {
@@ -710,7 +1453,6 @@ fn grid_raycast(x0: f32, y0: f32, x1: f32, y1: f32) -> Vec<[i32;2]> {
         //}
 
         //println!("result: {:?}",vec);
-        vec
     }
 }
 
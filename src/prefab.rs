@@ -0,0 +1,263 @@
+use components::{ CollisionBehavior, Shape };
+use configuration::{ check_keys, optional, required, FromToml, BitflagU32, TomlError };
+use graphics::{ Color, Layer };
+use std::collections::BTreeMap;
+use toml;
+
+pub type Prefabs = BTreeMap<String,Prefab>;
+
+/// a named set of components and their parameters,
+/// defined in the global configuration or in a castle configuration
+#[derive(Debug,Clone)]
+pub struct Prefab {
+    pub physic: Option<PrefabPhysic>,
+    pub life: Option<PrefabLife>,
+    pub killer: Option<PrefabKiller>,
+    pub graphic: Option<PrefabGraphic>,
+    pub control: Option<PrefabControl>,
+    pub column: Option<PrefabColumn>,
+    pub persistent_snd: Option<PrefabPersistentSnd>,
+}
+
+#[derive(Debug,Clone)]
+pub enum PrefabPhysicType {
+    Static,
+    Dynamic {
+        velocity: f32,
+        time: f32,
+        weight: f32,
+        /// swept movement, see `PhysicType::continuous`
+        continuous: bool,
+        collision: CollisionBehavior,
+        restitution: f32,
+        friction: f32,
+    },
+}
+
+#[derive(Debug,Clone)]
+pub struct PrefabPhysic {
+    pub typ: PrefabPhysicType,
+    pub group: u32,
+    pub mask: u32,
+    pub shape: Shape,
+}
+
+#[derive(Debug,Clone)]
+pub struct PrefabLife {
+    pub die_snd: usize,
+}
+
+#[derive(Debug,Clone)]
+pub struct PrefabKiller {
+    pub kamikaze: bool,
+    pub mask: u32,
+    pub kill_snd: usize,
+}
+
+#[derive(Debug,Clone)]
+pub struct PrefabGraphic {
+    pub color: Color,
+    pub layer: Layer,
+}
+
+#[derive(Debug,Clone)]
+pub enum PrefabControl {
+    Player,
+    Monster,
+    TowardPlayer,
+}
+
+#[derive(Debug,Clone)]
+pub struct PrefabColumn {
+    pub spawn_snd: usize,
+}
+
+#[derive(Debug,Clone)]
+pub enum PrefabPersistentSnd {
+    Static(usize),
+    Dynamic(usize),
+}
+
+impl FromToml for Prefab {
+    fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+        let table = try!(val.as_table().ok_or_else(|| TomlError::expect("table",val)));
+        try!(check_keys(table, &["physic","life","killer","graphic","control","column","persistent_snd"]));
+
+        let prefab = Prefab {
+            physic: try!(optional(table,"physic")),
+            life: try!(optional(table,"life")),
+            killer: try!(optional(table,"killer")),
+            graphic: try!(optional(table,"graphic")),
+            control: try!(optional(table,"control")),
+            column: try!(optional(table,"column")),
+            persistent_snd: try!(optional(table,"persistent_snd")),
+        };
+
+        // systems of these components expect the physic components
+        let dynamic = match prefab.physic {
+            Some(PrefabPhysic { typ: PrefabPhysicType::Dynamic { .. }, .. }) => true,
+            _ => false,
+        };
+        if prefab.control.is_some() && !dynamic {
+            return Err(TomlError::invalid("control requires physic of type \"dynamic\"").key("control"));
+        }
+        if prefab.killer.is_some() && prefab.physic.is_none() {
+            return Err(TomlError::invalid("killer requires physic").key("killer"));
+        }
+
+        Ok(prefab)
+    }
+}
+
+/// an optional value between 0 and 1
+fn optional_rate(table: &toml::Table, key: &str) -> Result<Option<f32>,TomlError> {
+    match try!(optional::<f32>(table,key)) {
+        Some(rate) if rate < 0. || rate > 1. => Err(TomlError::invalid(format!("{} is not between 0 and 1",rate)).key(key)),
+        rate => Ok(rate),
+    }
+}
+
+impl FromToml for PrefabPhysic {
+    fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+        let table = try!(val.as_table().ok_or_else(|| TomlError::expect("table",val)));
+
+        let typ: String = try!(required(table,"type"));
+        let typ = match &*typ {
+            "static" => {
+                try!(check_keys(table, &["type","group","mask","shape","radius"]));
+                PrefabPhysicType::Static
+            },
+            "dynamic" => {
+                try!(check_keys(table, &["type","group","mask","shape","radius","velocity","time","weight","continuous","collision","restitution","friction"]));
+                PrefabPhysicType::Dynamic {
+                    velocity: try!(required(table,"velocity")),
+                    time: try!(required(table,"time")),
+                    weight: try!(required(table,"weight")),
+                    continuous: try!(optional(table,"continuous")).unwrap_or(false),
+                    collision: try!(optional(table,"collision")).unwrap_or(CollisionBehavior::Persist),
+                    restitution: try!(optional_rate(table,"restitution")).unwrap_or(1.),
+                    friction: try!(optional_rate(table,"friction")).unwrap_or(0.),
+                }
+            },
+            _ => return Err(TomlError::expect("\"static\" or \"dynamic\"",&table["type"]).key("type")),
+        };
+
+        let shape: String = try!(required(table,"shape"));
+        let radius: f32 = try!(required(table,"radius"));
+        let shape = match &*shape {
+            "circle" => Shape::Circle(radius),
+            "square" => Shape::Square(radius),
+            _ => return Err(TomlError::expect("\"circle\" or \"square\"",&table["shape"]).key("shape")),
+        };
+
+        Ok(PrefabPhysic {
+            typ: typ,
+            group: try!(required::<BitflagU32>(table,"group")).val,
+            mask: try!(required::<BitflagU32>(table,"mask")).val,
+            shape: shape,
+        })
+    }
+}
+
+impl FromToml for PrefabLife {
+    fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+        let table = try!(val.as_table().ok_or_else(|| TomlError::expect("table",val)));
+        try!(check_keys(table, &["die_snd"]));
+
+        Ok(PrefabLife {
+            die_snd: try!(required(table,"die_snd")),
+        })
+    }
+}
+
+impl FromToml for PrefabKiller {
+    fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+        let table = try!(val.as_table().ok_or_else(|| TomlError::expect("table",val)));
+        try!(check_keys(table, &["kamikaze","mask","kill_snd"]));
+
+        Ok(PrefabKiller {
+            kamikaze: try!(required(table,"kamikaze")),
+            mask: try!(required::<BitflagU32>(table,"mask")).val,
+            kill_snd: try!(required(table,"kill_snd")),
+        })
+    }
+}
+
+impl FromToml for PrefabGraphic {
+    fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+        let table = try!(val.as_table().ok_or_else(|| TomlError::expect("table",val)));
+        try!(check_keys(table, &["color","layer"]));
+
+        Ok(PrefabGraphic {
+            color: try!(required(table,"color")),
+            layer: try!(required(table,"layer")),
+        })
+    }
+}
+
+impl FromToml for PrefabControl {
+    fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+        match val.as_str() {
+            Some("player") => Ok(PrefabControl::Player),
+            Some("monster") => Ok(PrefabControl::Monster),
+            Some("toward_player") => Ok(PrefabControl::TowardPlayer),
+            _ => Err(TomlError::expect("\"player\" or \"monster\" or \"toward_player\"",val)),
+        }
+    }
+}
+
+impl FromToml for CollisionBehavior {
+    fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+        match val.as_str() {
+            Some("bounce") => Ok(CollisionBehavior::Bounce),
+            Some("back") => Ok(CollisionBehavior::Back),
+            Some("persist") => Ok(CollisionBehavior::Persist),
+            Some("stop") => Ok(CollisionBehavior::Stop),
+            _ => Err(TomlError::expect("\"bounce\" or \"back\" or \"persist\" or \"stop\"",val)),
+        }
+    }
+}
+
+impl FromToml for PrefabColumn {
+    fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+        let table = try!(val.as_table().ok_or_else(|| TomlError::expect("table",val)));
+        try!(check_keys(table, &["spawn_snd"]));
+
+        Ok(PrefabColumn {
+            spawn_snd: try!(required(table,"spawn_snd")),
+        })
+    }
+}
+
+impl FromToml for PrefabPersistentSnd {
+    fn from_toml(val: &toml::Value) -> Result<Self,TomlError> {
+        let table = try!(val.as_table().ok_or_else(|| TomlError::expect("table",val)));
+        try!(check_keys(table, &["type","id"]));
+
+        let typ: String = try!(required(table,"type"));
+        let id = try!(required(table,"id"));
+        match &*typ {
+            "static" => Ok(PrefabPersistentSnd::Static(id)),
+            "dynamic" => Ok(PrefabPersistentSnd::Dynamic(id)),
+            _ => Err(TomlError::expect("\"static\" or \"dynamic\"",&table["type"]).key("type")),
+        }
+    }
+}
+
+#[test]
+fn prefab_requirements_test() {
+    let parse = |source: &str| Prefab::from_toml(&toml::Value::Table(toml::Parser::new(source).parse().unwrap()));
+    let dynamic = "[physic]\ntype = \"dynamic\"\ngroup = \"1\"\nmask = \"1\"\nshape = \"circle\"\nradius = 0.5\nvelocity = 1.0\ntime = 1.0\nweight = 1.0\n";
+    let fixed = "[physic]\ntype = \"static\"\ngroup = \"1\"\nmask = \"1\"\nshape = \"square\"\nradius = 0.5\n";
+    let killer = "[killer]\nkamikaze = false\nmask = \"1\"\nkill_snd = 0\n";
+
+    assert_eq!(parse("control = \"monster\"").unwrap_err().path,"control");
+    assert_eq!(parse(&*format!("control = \"monster\"\n{}",fixed)).unwrap_err().path,"control");
+    assert!(parse(&*format!("control = \"monster\"\n{}",dynamic)).is_ok());
+    assert_eq!(parse(killer).unwrap_err().path,"killer");
+    assert!(parse(&*format!("{}{}",killer,fixed)).is_ok());
+
+    assert_eq!(parse(&*format!("{}restitution = 1.5",dynamic)).unwrap_err().path,"physic.restitution");
+    assert_eq!(parse(&*format!("{}friction = -0.1",dynamic)).unwrap_err().path,"physic.friction");
+    assert!(parse(&*format!("{}restitution = 0.0\nfriction = 1.0",dynamic)).is_ok());
+}
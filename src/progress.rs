@@ -0,0 +1,171 @@
+//! progression of the player saved in the progress file:
+//! completions and best time of dungeons and deaths per room
+
+use config;
+use configuration::{ self, ConfigError, FromToml, IntoToml };
+use levels::{ Castle, Level };
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use toml;
+
+/// room name to number of deaths
+pub type Deaths = BTreeMap<String,u32>;
+
+/// castle name to dungeon name to progression
+type Castles = BTreeMap<String,BTreeMap<String,DungeonProgress>>;
+
+#[derive(Debug,Clone,Default)]
+pub struct DungeonProgress {
+    pub completions: u32,
+    /// in seconds, meaningless if not completed
+    pub best_time: f32,
+    pub deaths: Deaths,
+}
+impl_from_into_toml_for_struct!( DungeonProgress {
+    completions: u32,
+    best_time: f32,
+    deaths: Deaths,
+});
+
+/// a dungeon being played since the corridor
+struct Run {
+    castle: usize,
+    dungeon: usize,
+    time: f32,
+}
+
+pub struct Progress {
+    castles: Castles,
+    run: Option<Run>,
+}
+
+impl Progress {
+    /// an empty progression
+    pub fn new() -> Progress {
+        Progress {
+            castles: Castles::new(),
+            run: None,
+        }
+    }
+
+    /// load the progress file, a missing file is an empty progression
+    pub fn load() -> Result<Progress,String> {
+        let config = config.load();
+        let file = &*config.general.progress_file.val;
+
+        let castles = if Path::new(file).exists() {
+            let table = try!(configuration::read_table(file).map_err(|e| format!("{}",e)));
+            try!(Castles::from_toml(&toml::Value::Table(table))
+                 .map_err(|e| format!("{}",ConfigError::value(file,e))))
+        } else {
+            Castles::new()
+        };
+
+        Ok(Progress {
+            castles: castles,
+            run: None,
+        })
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let config = config.load();
+        configuration::write_toml(&*config.general.progress_file.val,IntoToml::into_toml(self.castles.clone()))
+    }
+
+    pub fn dungeon(&self, castle: &str, dungeon: &str) -> Option<&DungeonProgress> {
+        self.castles.get(castle).and_then(|dungeons| dungeons.get(dungeon))
+    }
+
+    pub fn completed(&self, castle: &str, dungeon: &str) -> bool {
+        self.dungeon(castle,dungeon).map(|d| d.completions > 0).unwrap_or(false)
+    }
+
+    fn dungeon_mut(&mut self, castle: &str, dungeon: &str) -> &mut DungeonProgress {
+        self.castles.entry(castle.into()).or_insert(BTreeMap::new())
+            .entry(dungeon.into()).or_insert(DungeonProgress::default())
+    }
+
+    /// time spent in the game state
+    pub fn update(&mut self, dt: f32) {
+        if let Some(ref mut run) = self.run {
+            run.time += dt;
+        }
+    }
+
+    /// the player goes through a portal, return whether the progression changed:
+    /// entering a dungeon from outside starts a run and coming back to the corridor
+    /// of its castle completes it
+    pub fn travel(&mut self, from: &Level, to: &Level, castles: &Vec<Castle>) -> bool {
+        match (from, to) {
+            (&Level::Room { castle, dungeon, .. }, &Level::Room { castle: to_castle, dungeon: to_dungeon, .. })
+                if castle == to_castle && dungeon == to_dungeon => false,
+            (_, &Level::Room { castle, dungeon, .. }) => {
+                self.run = Some(Run {
+                    castle: castle,
+                    dungeon: dungeon,
+                    time: 0.,
+                });
+                false
+            },
+            (&Level::Room { castle, dungeon, .. }, &Level::Corridor { castle: to_castle }) if castle == to_castle => {
+                match self.run.take() {
+                    Some(run) => {
+                        if run.castle != castle || run.dungeon != dungeon {
+                            return false;
+                        }
+                        let castle = &castles[castle];
+                        let progress = self.dungeon_mut(&*castle.name,&*castle.dungeons[dungeon].name);
+                        if progress.completions == 0 || run.time < progress.best_time {
+                            progress.best_time = run.time;
+                        }
+                        progress.completions += 1;
+                        true
+                    },
+                    None => false,
+                }
+            },
+            _ => {
+                self.run = None;
+                false
+            },
+        }
+    }
+
+    /// the run is no longer valid, for example the room has been edited
+    pub fn abort(&mut self) {
+        self.run = None;
+    }
+
+    pub fn death(&mut self, level: &Level, castles: &Vec<Castle>) {
+        if let Level::Room { castle, dungeon, room } = *level {
+            let castle = &castles[castle];
+            let dungeon = &castle.dungeons[dungeon];
+            *self.dungeon_mut(&*castle.name,&*dungeon.name).deaths
+                .entry(dungeon.rooms[room].clone()).or_insert(0) += 1;
+        }
+    }
+}
+
+#[test]
+fn dungeon_progress_toml_test() {
+    let mut deaths = Deaths::new();
+    deaths.insert("map.png".into(),3);
+    let mut dungeons = BTreeMap::new();
+    dungeons.insert("Example".into(),DungeonProgress {
+        completions: 2,
+        best_time: 12.5,
+        deaths: deaths,
+    });
+    let mut castles = Castles::new();
+    castles.insert("3 - Your castle".into(),dungeons);
+
+    let string = format!("{}",IntoToml::into_toml(castles));
+    let table = toml::Parser::new(&*string).parse().unwrap();
+    let castles = Castles::from_toml(&toml::Value::Table(table)).unwrap();
+
+    let progress = &castles["3 - Your castle"]["Example"];
+    assert_eq!(progress.completions,2);
+    assert_eq!(progress.best_time,12.5);
+    assert_eq!(progress.deaths["map.png"],3);
+}
@@ -0,0 +1,191 @@
+//! recording of play sessions: the level, the seed of the world rng
+//! and the force of the player at each update
+//!
+//! the file is text, the first line is the header, then each line is
+//! a run of identical updates: `count direction intensity`,
+//! the version in the header changes with the simulation so recordings
+//! of another version are rejected instead of replaying differently
+
+use levels::Level;
+use std::fs;
+use std::io::{ self, Read, Write };
+use std::path::Path;
+
+static MAGIC: &'static str = "ruga-replay 2";
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Recording {
+    pub level: Level,
+    pub seed: u64,
+    /// dt of update args
+    pub dt: f64,
+    pub difficulty: f32,
+    /// runs of identical updates: count, direction and intensity
+    runs: Vec<(u32,f32,f32)>,
+}
+
+fn format_level(level: &Level) -> String {
+    match *level {
+        Level::Entry => "entry".into(),
+        Level::Corridor { castle } => format!("corridor {}",castle),
+        Level::Room { castle, dungeon, room } => format!("room {} {} {}",castle,dungeon,room),
+    }
+}
+
+fn parse_level(words: &[&str]) -> Result<Level,String> {
+    let numbers = try!(words[1..].iter()
+        .map(|w| w.parse::<usize>().map_err(|_| format!("invalid number {}",w)))
+        .collect::<Result<Vec<usize>,String>>());
+
+    match (words[0],numbers.len()) {
+        ("entry",0) => Ok(Level::Entry),
+        ("corridor",1) => Ok(Level::Corridor { castle: numbers[0] }),
+        ("room",3) => Ok(Level::Room { castle: numbers[0], dungeon: numbers[1], room: numbers[2] }),
+        _ => Err(String::from("invalid level")),
+    }
+}
+
+impl Recording {
+    pub fn new(level: Level, seed: u64, dt: f64, difficulty: f32) -> Self {
+        Recording {
+            level: level,
+            seed: seed,
+            dt: dt,
+            difficulty: difficulty,
+            runs: vec!(),
+        }
+    }
+
+    pub fn push(&mut self, direction: f32, intensity: f32) {
+        if let Some(&mut (ref mut count, d, i)) = self.runs.last_mut() {
+            if d == direction && i == intensity {
+                *count += 1;
+                return;
+            }
+        }
+        self.runs.push((1,direction,intensity));
+    }
+
+    /// forces of the player for each update in order
+    pub fn forces<'a>(&'a self) -> Box<Iterator<Item=(f32,f32)> + 'a> {
+        Box::new(self.runs.iter().flat_map(|&(count,d,i)| (0..count).map(move |_| (d,i))))
+    }
+
+    pub fn to_string(&self) -> String {
+        let mut string = format!("{}\n{}\n{} {} {}\n",MAGIC,format_level(&self.level),self.seed,self.dt,self.difficulty);
+        for &(count,direction,intensity) in &self.runs {
+            string.push_str(&*format!("{} {} {}\n",count,direction,intensity));
+        }
+        string
+    }
+
+    pub fn from_str(string: &str) -> Result<Recording,String> {
+        let mut lines = string.lines().enumerate();
+
+        match lines.next() {
+            Some((_,line)) if line == MAGIC => (),
+            _ => return Err(format!("line 1: expect {}",MAGIC)),
+        }
+
+        let level = match lines.next() {
+            Some((n,line)) => {
+                let words = line.split_whitespace().collect::<Vec<_>>();
+                if words.is_empty() {
+                    return Err(format!("line {}: expect level",n+1));
+                }
+                try!(parse_level(&*words).map_err(|e| format!("line {}: {}",n+1,e)))
+            },
+            None => return Err(String::from("line 2: expect level")),
+        };
+
+        let (seed,dt,difficulty) = match lines.next() {
+            Some((n,line)) => {
+                let words = line.split_whitespace().collect::<Vec<_>>();
+                let error = format!("line {}: expect seed, dt and difficulty",n+1);
+                if words.len() != 3 {
+                    return Err(error);
+                }
+                (
+                    try!(words[0].parse::<u64>().map_err(|_| error.clone())),
+                    try!(words[1].parse::<f64>().map_err(|_| error.clone())),
+                    try!(words[2].parse::<f32>().map_err(|_| error.clone())),
+                )
+            },
+            None => return Err(String::from("line 3: expect seed, dt and difficulty")),
+        };
+
+        let mut recording = Recording::new(level,seed,dt,difficulty);
+        for (n,line) in lines {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            let error = format!("line {}: expect count, direction and intensity",n+1);
+            if words.len() != 3 {
+                return Err(error);
+            }
+            recording.runs.push((
+                try!(words[0].parse::<u32>().map_err(|_| error.clone())),
+                try!(words[1].parse::<f32>().map_err(|_| error.clone())),
+                try!(words[2].parse::<f32>().map_err(|_| error.clone())),
+            ));
+        }
+
+        Ok(recording)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Recording,String> {
+        let path = path.as_ref();
+        let mut string = String::new();
+        try!(try!(fs::File::open(path).map_err(|e| format!("{}: {}",path.display(),e)))
+             .read_to_string(&mut string)
+             .map_err(|e| format!("{}: {}",path.display(),e)));
+        Recording::from_str(&*string).map_err(|e| format!("{}: {}",path.display(),e))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = try!(fs::File::create(path));
+        file.write_all(self.to_string().as_bytes())
+    }
+}
+
+/// the forces of a recording fed back update after update
+pub struct Replay {
+    pub dt: f64,
+    pub difficulty: f32,
+    forces: Vec<(f32,f32)>,
+    next: usize,
+}
+
+impl Replay {
+    pub fn new(recording: &Recording) -> Self {
+        Replay {
+            dt: recording.dt,
+            difficulty: recording.difficulty,
+            forces: recording.forces().collect(),
+            next: 0,
+        }
+    }
+
+    pub fn next_force(&mut self) -> Option<(f32,f32)> {
+        let force = self.forces.get(self.next).cloned();
+        self.next += 1;
+        force
+    }
+}
+
+#[test]
+fn recording_test() {
+    let mut recording = Recording::new(Level::Room { castle: 1, dungeon: 2, room: 3 }, 42, 1./120., 0.7);
+    recording.push(0.,0.);
+    recording.push(0.,0.);
+    recording.push(1.5707964,1.);
+    recording.push(-0.3,0.25);
+    recording.push(-0.3,0.25);
+
+    assert_eq!(recording.runs.len(),3);
+    assert_eq!(recording.forces().collect::<Vec<_>>(),vec!((0.,0.),(0.,0.),(1.5707964,1.),(-0.3,0.25),(-0.3,0.25)));
+
+    let parsed = Recording::from_str(&*recording.to_string()).unwrap();
+    assert_eq!(parsed,recording);
+
+    assert!(Recording::from_str("ruga-replay 2\nroom 1\n0 0.1 1\n").is_err());
+    assert!(Recording::from_str(&*recording.to_string().replace(MAGIC,"ruga-replay 1")).is_err());
+}
@@ -0,0 +1,217 @@
+//! bot checking that the character of a room can reach a portal alive,
+//! it drives the force of the character while the systems of the game run
+
+use components::*;
+use config;
+use game::{ Game, Outcome };
+use levels::Level;
+use resource::{ NavigationGrid, PhysicWorld };
+use specs::Join;
+use std::fmt;
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Verdict {
+    /// the bot reached a portal after this number of updates
+    Solved(usize),
+    LoadError(String),
+    NoCharacter,
+    /// walls and columns separate the character from every portal
+    NoPath,
+    /// every path to a portal goes through a laser
+    LaserOnEveryPath,
+    /// the bot died in every attempt
+    Killed,
+    /// the bot didn't reach a portal in config.solver.max_updates
+    Timeout,
+}
+
+impl Verdict {
+    pub fn is_solved(&self) -> bool {
+        if let Verdict::Solved(_) = *self { true } else { false }
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        use self::Verdict::*;
+        match *self {
+            Solved(updates) => write!(fmt,"solved in {} updates",updates),
+            LoadError(ref e) => write!(fmt,"load error: {}",e),
+            NoCharacter => write!(fmt,"no character"),
+            NoPath => write!(fmt,"no path to a portal"),
+            LaserOnEveryPath => write!(fmt,"every path to a portal touches a laser"),
+            Killed => write!(fmt,"the bot is killed on every attempt"),
+            Timeout => write!(fmt,"the bot doesn't reach a portal in time"),
+        }
+    }
+}
+
+enum Attempt {
+    Solved(usize),
+    LoadError(String),
+    Killed,
+    Timeout,
+}
+
+/// the position of the character if alive
+fn character_position(game: &mut Game) -> Option<[f32;2]> {
+    let world = game.world();
+    let characters = world.read::<PlayerControl>();
+    let states = world.read::<PhysicState>();
+    for (_, state) in (&characters, &states).iter() {
+        return Some(state.position);
+    }
+    None
+}
+
+fn portal_positions(game: &mut Game) -> Vec<[f32;2]> {
+    let world = game.world();
+    let portals = world.read::<Portal>();
+    let grid_squares = world.read::<GridSquare>();
+    (&portals, &grid_squares).iter().map(|(_,square)| square.position).collect()
+}
+
+/// grid of the cells blocked for the character, with lasers or not
+fn navigation_grid(game: &mut Game, lasers: bool) -> NavigationGrid {
+    let config = config.load();
+    let mut mask = config.entities.char_mask.val;
+    if lasers {
+        mask |= config.entities.laser_group.val;
+    }
+    let mut grid = NavigationGrid::new();
+    grid.fill(&game.world().read_resource::<PhysicWorld>(), mask);
+    grid
+}
+
+/// the portals reachable in the grid sorted by length of path
+fn reachable_portals(grid: &NavigationGrid, start: [f32;2], portals: &[[f32;2]]) -> Vec<[f32;2]> {
+    let mut reachable = portals.iter()
+        .filter_map(|&portal| grid.find_path(start,portal).map(|path| (path.len(),portal)))
+        .collect::<Vec<_>>();
+    reachable.sort_by_key(|&(length,_)| length);
+    reachable.into_iter().map(|(_,portal)| portal).collect()
+}
+
+/// follow the path to the portal, the path is searched again each update
+/// as the character is pushed around
+fn attempt(game: &mut Game, grid: &NavigationGrid, portal: [f32;2], wait: usize) -> Attempt {
+    let config = config.load();
+    let dt = 1. / config.event_loop.ups as f32;
+
+    for update in 0..config.solver.max_updates {
+        let (direction,intensity) = match character_position(game) {
+            Some(pos) if update >= wait => {
+                let mut path = grid.find_path(pos,portal).unwrap_or(vec!(portal));
+                while path.len() > 1 {
+                    let waypoint = *path.last().unwrap();
+                    if (waypoint[0] - pos[0]).powi(2) + (waypoint[1] - pos[1]).powi(2) < config.solver.waypoint_radius.powi(2) {
+                        path.pop();
+                    } else {
+                        break;
+                    }
+                }
+                let waypoint = *path.last().unwrap();
+                let distance = ((waypoint[0] - pos[0]).powi(2) + (waypoint[1] - pos[1]).powi(2)).sqrt();
+
+                // slow down on the portal so the character doesn't overshoot it
+                let intensity = if path.len() == 1 {
+                    (distance/config.solver.slow_distance).min(1.)
+                } else {
+                    1.
+                };
+                ((waypoint[1] - pos[1]).atan2(waypoint[0] - pos[0]), intensity)
+            },
+            _ => (0.,0.),
+        };
+        game.set_player_force(direction,intensity);
+
+        let outcomes = match game.step(dt,1.) {
+            Ok(outcomes) => outcomes,
+            Err(e) => return Attempt::LoadError(e),
+        };
+        if let Some(e) = outcomes.iter().filter_map(|o| if let Outcome::Failed(ref e) = *o { Some(e.clone()) } else { None }).next() {
+            return Attempt::LoadError(e);
+        }
+        if outcomes.contains(&Outcome::Reset) {
+            return Attempt::Killed;
+        }
+        if outcomes.contains(&Outcome::Loaded) {
+            return Attempt::Solved(update+1);
+        }
+    }
+    Attempt::Timeout
+}
+
+/// check the level loaded by load, it is loaded again before each attempt
+pub fn solve<F: FnMut(&mut Game) -> Result<(),String>>(game: &mut Game, load: &mut F) -> Verdict {
+    let config = config.load();
+    if let Err(e) = load(game) {
+        return Verdict::LoadError(e);
+    }
+
+    let start = match character_position(game) {
+        Some(pos) => pos,
+        None => return Verdict::NoCharacter,
+    };
+    let portals = portal_positions(game);
+
+    if reachable_portals(&navigation_grid(game,false),start,&portals).is_empty() {
+        return Verdict::NoPath;
+    }
+
+    let grid = navigation_grid(game,true);
+    let targets = reachable_portals(&grid,start,&portals);
+    if targets.is_empty() {
+        return Verdict::LaserOnEveryPath;
+    }
+
+    let mut timeout = false;
+    for &wait in &config.solver.waits {
+        for &portal in &targets {
+            // same seed on each attempt so monsters behave the same
+            game.reseed(0);
+            if let Err(e) = load(game) {
+                return Verdict::LoadError(e);
+            }
+            match attempt(game,&grid,portal,wait) {
+                Attempt::Solved(updates) => return Verdict::Solved(updates),
+                Attempt::LoadError(e) => return Verdict::LoadError(e),
+                Attempt::Killed => (),
+                Attempt::Timeout => timeout = true,
+            }
+        }
+    }
+
+    if timeout { Verdict::Timeout } else { Verdict::Killed }
+}
+
+pub fn solve_level(game: &mut Game, level: &Level) -> Verdict {
+    solve(game, &mut |game| game.goto_level(level.clone()))
+}
+
+#[test]
+fn solve_test() {
+    use audio::NullAudio;
+    use levels;
+    use progress::Progress;
+
+    let (castles,_) = levels::load_castles(vec!()).unwrap();
+    let level = Level::Room { castle: 0, dungeon: 0, room: 0 };
+    let mut game = Game::new(castles,Progress::new(),Box::new(NullAudio::new()),0).unwrap();
+
+    let mut room = vec!(([0,0],String::from("character")),([4,0],String::from("portal")));
+    for x in -1..6 {
+        room.push(([x,-1],String::from("wall")));
+        room.push(([x,1],String::from("wall")));
+    }
+    room.push(([-1,0],String::from("wall")));
+    room.push(([5,0],String::from("wall")));
+    assert!(solve(&mut game, &mut |game| game.load_room_tiles(&level,&room).map_err(|e| format!("{}",e))).is_solved());
+
+    room.push(([2,0],String::from("laser")));
+    assert_eq!(solve(&mut game, &mut |game| game.load_room_tiles(&level,&room).map_err(|e| format!("{}",e))),Verdict::LaserOnEveryPath);
+
+    room.pop();
+    room.push(([2,0],String::from("wall")));
+    assert_eq!(solve(&mut game, &mut |game| game.load_room_tiles(&level,&room).map_err(|e| format!("{}",e))),Verdict::NoPath);
+}
@@ -0,0 +1,376 @@
+//! import of maps made with the Tiled editor in json or tmx format,
+//! tmx maps are converted to the json layout before being placed
+//!
+//! * tiles of tile layers are placed if their tile has an `entity` property
+//! * objects of object layers are placed at their center, the entity is
+//!   their type or their `entity` property, a portal can have a `destination`
+//!   property naming a room of the dungeon
+
+use serde_json::{ self, Map, Value };
+use std::collections::HashMap;
+use std::fmt;
+use xml::attribute::OwnedAttribute;
+use xml::reader::{ EventReader, XmlEvent };
+
+/// flags of flipped tiles in global tile ids
+const FLIP_FLAGS: u64 = 0xE0000000;
+
+pub struct TiledEntity {
+    pub name: String,
+    pub position: [f32;2],
+    pub destination: Option<String>,
+    pub object: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct TiledError {
+    pub object: Option<u64>,
+    pub description: String,
+}
+impl TiledError {
+    fn new<S: Into<String>>(description: S) -> Self {
+        TiledError {
+            object: None,
+            description: description.into(),
+        }
+    }
+    fn object<S: Into<String>>(object: u64, description: S) -> Self {
+        TiledError {
+            object: Some(object),
+            description: description.into(),
+        }
+    }
+}
+impl fmt::Display for TiledError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(object) = self.object {
+            write!(fmt,"object {}: {}",object,self.description)
+        } else {
+            write!(fmt,"{}",self.description)
+        }
+    }
+}
+
+/// properties are either an object (Tiled < 1.2)
+/// or an array of {name, type, value}
+fn property<'a>(json: &'a Value, name: &str) -> Option<&'a Value> {
+    match json.get("properties") {
+        Some(&Value::Object(ref properties)) => properties.get(name),
+        Some(&Value::Array(ref properties)) => {
+            properties.iter()
+                .find(|p| p.get("name").and_then(|n| n.as_str()) == Some(name))
+                .and_then(|p| p.get("value"))
+        },
+        _ => None,
+    }
+}
+
+fn get_f64(json: &Value, key: &str) -> Result<f64,TiledError> {
+    json.get(key).and_then(|v| v.as_f64()).ok_or(TiledError::new(format!("expect number {}",key)))
+}
+
+fn get_u64(json: &Value, key: &str) -> Result<u64,TiledError> {
+    json.get(key).and_then(|v| v.as_u64()).ok_or(TiledError::new(format!("expect integer {}",key)))
+}
+
+/// the entity name of each global tile id that has an entity property
+fn tile_entities(map: &Value) -> Result<HashMap<u64,String>,TiledError> {
+    let mut entities = HashMap::new();
+
+    let tilesets = try!(map.get("tilesets").and_then(|t| t.as_array()).ok_or(TiledError::new("expect tilesets array")));
+    for tileset in tilesets {
+        if tileset.get("source").is_some() {
+            return Err(TiledError::new("external tilesets are not supported, embed them in the map"));
+        }
+        let first_gid = try!(get_u64(tileset,"firstgid"));
+
+        // Tiled < 1.2
+        if let Some(tile_properties) = tileset.get("tileproperties").and_then(|t| t.as_object()) {
+            for (id,properties) in tile_properties {
+                let id = try!(id.parse::<u64>().map_err(|_| TiledError::new(format!("invalid tile id {}",id))));
+                if let Some(entity) = properties.get("entity").and_then(|e| e.as_str()) {
+                    entities.insert(first_gid + id, entity.into());
+                }
+            }
+        }
+
+        if let Some(tiles) = tileset.get("tiles").and_then(|t| t.as_array()) {
+            for tile in tiles {
+                let id = try!(get_u64(tile,"id"));
+                if let Some(entity) = property(tile,"entity").and_then(|e| e.as_str()) {
+                    entities.insert(first_gid + id, entity.into());
+                }
+            }
+        }
+    }
+
+    Ok(entities)
+}
+
+/// parse a Tiled json map into the entities to place,
+/// positions are in grid unit, the top left of the map is [0,1]
+pub fn parse(string: &str) -> Result<Vec<TiledEntity>,TiledError> {
+    let map = try!(serde_json::from_str(string).map_err(|e| TiledError::new(format!("json error: {}",e))));
+    parse_map(&map)
+}
+
+/// parse a Tiled tmx map into the entities to place like `parse`
+pub fn parse_tmx(string: &str) -> Result<Vec<TiledEntity>,TiledError> {
+    let map = try!(tmx_to_json(string));
+    parse_map(&map)
+}
+
+/// attributes of tmx elements that are numbers in json maps
+const TMX_NUMBERS: [&'static str; 9] = ["firstgid","id","gid","x","y","width","height","tilewidth","tileheight"];
+
+fn tmx_element(name: &str, attributes: &[OwnedAttribute]) -> Result<Map<String,Value>,TiledError> {
+    let mut element = Map::new();
+    for attribute in attributes {
+        let key = attribute.name.local_name.clone();
+        let value = if TMX_NUMBERS.contains(&&*key) {
+            let number = try!(attribute.value.parse::<f64>().ok().and_then(|n| serde_json::Number::from_f64(n))
+                .ok_or(TiledError::new(format!("expect number {} in {}",key,name))));
+            // ids are integers in json maps
+            match attribute.value.parse::<u64>() {
+                Ok(integer) => Value::from(integer),
+                Err(_) => Value::Number(number),
+            }
+        } else {
+            Value::String(attribute.value.clone())
+        };
+        element.insert(key,value);
+    }
+    match name {
+        "layer" => { element.insert("type".into(),Value::from("tilelayer")); },
+        "objectgroup" => { element.insert("type".into(),Value::from("objectgroup")); },
+        _ => (),
+    }
+    Ok(element)
+}
+
+fn push(parent: &mut Map<String,Value>, key: &str, value: Value) {
+    if let Value::Array(ref mut array) = *parent.entry(String::from(key)).or_insert_with(|| Value::Array(vec!())) {
+        array.push(value);
+    }
+}
+
+/// convert a tmx map to the layout of a json map,
+/// only the elements and attributes used by `parse_map` are kept
+fn tmx_to_json(string: &str) -> Result<Value,TiledError> {
+    // elements being read with their name, the first one is the map
+    let mut stack: Vec<(String,Map<String,Value>)> = vec!();
+    let mut map = None;
+
+    for event in EventReader::from_str(string) {
+        match try!(event.map_err(|e| TiledError::new(format!("xml error: {}",e)))) {
+            XmlEvent::StartElement { name, attributes, .. } => {
+                let name = name.local_name;
+                if name == "data" {
+                    match attributes.iter().find(|a| a.name.local_name == "encoding") {
+                        Some(encoding) if encoding.value == "csv" => (),
+                        _ => return Err(TiledError::new("expect tile layer data in csv format")),
+                    }
+                }
+                let element = try!(tmx_element(&*name,&attributes));
+                stack.push((name,element));
+            },
+            XmlEvent::Characters(text) => {
+                if let Some(&mut (ref name,ref mut element)) = stack.last_mut() {
+                    if name == "data" {
+                        for gid in text.split(',').map(|gid| gid.trim()).filter(|gid| !gid.is_empty()) {
+                            let gid = try!(gid.parse::<u64>().map_err(|_| TiledError::new(format!("invalid tile {}",gid))));
+                            push(element,"data",Value::from(gid));
+                        }
+                    }
+                }
+            },
+            XmlEvent::EndElement { .. } => {
+                let (name,element) = stack.pop().expect("end of a started element");
+                let parent = match stack.last_mut() {
+                    Some(&mut (ref parent_name,ref mut parent)) => (&**parent_name,parent),
+                    None if name == "map" => {
+                        map = Some(element);
+                        continue;
+                    },
+                    None => return Err(TiledError::new(format!("expect map element, found {}",name))),
+                };
+                match (parent.0,&*name) {
+                    ("map","tileset") => push(parent.1,"tilesets",Value::Object(element)),
+                    ("map","layer") | ("map","objectgroup") => push(parent.1,"layers",Value::Object(element)),
+                    ("tileset","tile") => push(parent.1,"tiles",Value::Object(element)),
+                    ("objectgroup","object") => push(parent.1,"objects",Value::Object(element)),
+                    ("properties","property") => push(parent.1,"properties",Value::Object(element)),
+                    (_,"properties") => {
+                        let properties = element.get("properties").cloned().unwrap_or(Value::Array(vec!()));
+                        parent.1.insert("properties".into(),properties);
+                    },
+                    ("layer","data") => {
+                        let data = element.get("data").cloned().unwrap_or(Value::Array(vec!()));
+                        parent.1.insert("data".into(),data);
+                    },
+                    _ => (),
+                }
+            },
+            _ => (),
+        }
+    }
+
+    match map {
+        Some(map) => Ok(Value::Object(map)),
+        None => Err(TiledError::new("expect map element")),
+    }
+}
+
+/// the entities of a map in the json layout
+fn parse_map(map: &Value) -> Result<Vec<TiledEntity>,TiledError> {
+    let tile_width = try!(get_f64(map,"tilewidth"));
+    let tile_height = try!(get_f64(map,"tileheight"));
+    let tile_entities = try!(tile_entities(map));
+
+    let mut entities = vec!();
+
+    let layers = try!(map.get("layers").and_then(|l| l.as_array()).ok_or(TiledError::new("expect layers array")));
+    for layer in layers {
+        match layer.get("type").and_then(|t| t.as_str()) {
+            Some("tilelayer") => {
+                let width = try!(get_u64(layer,"width"));
+                let data = try!(layer.get("data").and_then(|d| d.as_array())
+                                .ok_or(TiledError::new("expect tile layer data array, use csv layer format")));
+
+                for (i,gid) in data.iter().enumerate() {
+                    let gid = try!(gid.as_u64().ok_or(TiledError::new("expect integer tile"))) & !FLIP_FLAGS;
+                    if gid == 0 { continue; }
+
+                    if let Some(name) = tile_entities.get(&gid) {
+                        let x = i as u64 % width;
+                        let y = i as u64 / width;
+                        entities.push(TiledEntity {
+                            name: name.clone(),
+                            position: [x as f32 + 0.5, -(y as f32) + 0.5],
+                            destination: None,
+                            object: None,
+                        });
+                    }
+                }
+            },
+            Some("objectgroup") => {
+                let objects = try!(layer.get("objects").and_then(|o| o.as_array()).ok_or(TiledError::new("expect objects array")));
+                for object in objects {
+                    let id = try!(get_u64(object,"id"));
+
+                    let name = property(object,"entity")
+                        .or_else(|| object.get("type"))
+                        .or_else(|| object.get("class"))
+                        .and_then(|n| n.as_str())
+                        .and_then(|n| if n.is_empty() { None } else { Some(n) });
+                    let name = try!(name.ok_or(TiledError::object(id,"expect type or entity property")));
+
+                    let x = try!(get_f64(object,"x").map_err(|e| TiledError::object(id,e.description)));
+                    let y = try!(get_f64(object,"y").map_err(|e| TiledError::object(id,e.description)));
+                    let width = object.get("width").and_then(|w| w.as_f64()).unwrap_or(0.);
+                    let height = object.get("height").and_then(|h| h.as_f64()).unwrap_or(0.);
+
+                    // tile objects are anchored at their bottom left
+                    let y = if object.get("gid").is_some() { y - height } else { y };
+
+                    let destination = match property(object,"destination") {
+                        Some(destination) => Some(String::from(try!(destination.as_str()
+                            .ok_or(TiledError::object(id,"expect string destination"))))),
+                        None => None,
+                    };
+
+                    entities.push(TiledEntity {
+                        name: name.into(),
+                        position: [
+                            ((x + width/2.)/tile_width) as f32,
+                            (1. - (y + height/2.)/tile_height) as f32,
+                        ],
+                        destination: destination,
+                        object: Some(id),
+                    });
+                }
+            },
+            _ => (),
+        }
+    }
+
+    Ok(entities)
+}
+
+#[test]
+fn parse_test() {
+    let map = r#"{
+        "tilewidth": 16,
+        "tileheight": 16,
+        "tilesets": [{
+            "firstgid": 1,
+            "tiles": [{ "id": 1, "properties": [{ "name": "entity", "type": "string", "value": "wall" }] }]
+        }],
+        "layers": [
+            { "type": "tilelayer", "width": 2, "height": 2, "data": [2, 0, 0, 2147483650] },
+            { "type": "objectgroup", "objects": [
+                { "id": 3, "type": "monster", "x": 16, "y": 0, "width": 16, "height": 16 },
+                { "id": 4, "type": "portal", "x": 8, "y": 24, "width": 0, "height": 0,
+                  "properties": { "destination": "end.txt" } }
+            ]}
+        ]
+    }"#;
+
+    let entities = parse(map).unwrap();
+    assert_eq!(entities.len(),4);
+    assert_eq!(entities[0].name,"wall");
+    assert_eq!(entities[0].position,[0.5,0.5]);
+    assert_eq!(entities[1].name,"wall");
+    assert_eq!(entities[1].position,[1.5,-0.5]);
+    assert_eq!(entities[2].name,"monster");
+    assert_eq!(entities[2].position,[1.5,0.5]);
+    assert_eq!(entities[3].destination,Some("end.txt".into()));
+    assert_eq!(entities[3].position,[0.5,-0.5]);
+
+    let error = parse(r#"{ "tilewidth": 16, "tileheight": 16, "tilesets": [],
+        "layers": [{ "type": "objectgroup", "objects": [{ "id": 7, "x": 0, "y": 0 }] }] }"#).err().unwrap();
+    assert_eq!(error.object,Some(7));
+}
+
+#[test]
+fn parse_tmx_test() {
+    let map = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.2" orientation="orthogonal" width="2" height="2" tilewidth="16" tileheight="16">
+ <tileset firstgid="1" name="entities" tilewidth="16" tileheight="16" tilecount="2">
+  <image source="entities.png" width="32" height="16"/>
+  <tile id="1">
+   <properties>
+    <property name="entity" value="wall"/>
+   </properties>
+  </tile>
+ </tileset>
+ <layer id="1" name="walls" width="2" height="2">
+  <data encoding="csv">
+2,0,
+0,2147483650
+</data>
+ </layer>
+ <objectgroup id="2" name="entities">
+  <object id="3" type="monster" x="16" y="0" width="16" height="16"/>
+  <object id="4" type="portal" x="8" y="24">
+   <properties>
+    <property name="destination" value="end.txt"/>
+   </properties>
+   <point/>
+  </object>
+ </objectgroup>
+</map>"#;
+
+    let entities = parse_tmx(map).unwrap();
+    assert_eq!(entities.len(),4);
+    assert_eq!(entities[0].name,"wall");
+    assert_eq!(entities[0].position,[0.5,0.5]);
+    assert_eq!(entities[1].position,[1.5,-0.5]);
+    assert_eq!(entities[2].name,"monster");
+    assert_eq!(entities[2].position,[1.5,0.5]);
+    assert_eq!(entities[3].destination,Some("end.txt".into()));
+    assert_eq!(entities[3].position,[0.5,-0.5]);
+    assert_eq!(entities[3].object,Some(4));
+
+    assert!(parse_tmx(r#"<map tilewidth="16" tileheight="16"><layer width="1"><data encoding="base64">AQAAAA==</data></layer></map>"#).is_err());
+}
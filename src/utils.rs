@@ -1,4 +1,16 @@
 use gilrs;
+use rand::{ SeedableRng, XorShiftRng };
+
+/// random number generator of the world,
+/// seeded so that sessions can be replayed
+pub struct WorldRng(pub XorShiftRng);
+
+impl WorldRng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift seed must not be only zeros
+        WorldRng(XorShiftRng::from_seed([seed as u32, (seed >> 32) as u32, 0x9E3779B9, 0x243F6A88]))
+    }
+}
 
 #[derive(Debug,Clone,Copy,PartialEq)]
 pub enum Direction {